@@ -0,0 +1,405 @@
+//! A channel for large messages, which only queues the indices of their slots.
+//!
+//! An [`Arena`] holds `N` message slots. The [`Sender`] acquires a free slot, fills it in
+//! place and sends it to the [`Receiver`], but only the index of the slot passes through
+//! the queue. The receiver gets a [`Message`] that refers to the slot, which frees the
+//! slot again once it is dropped.
+//!
+//! ```
+//! use heapless_async_queues::arena::{Arena, Split};
+//!
+//! struct Frame {
+//!     data: [u8; 512],
+//!     len: usize,
+//! }
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let mut arena: Arena<Frame, 4> = Arena::new();
+//! let Split { mut sender, mut receiver } = arena.split();
+//!
+//! let slot = sender.acquire().await.unwrap();
+//! slot.send(Frame { data: [0xAA; 512], len: 3 });
+//!
+//! let frame = receiver.receive().await.unwrap();
+//! assert_eq!(frame.data[..frame.len], [0xAA; 3]);
+//! # });
+//! ```
+
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+};
+
+use crate::spsc::{Consumer, Finished, Producer, Queue, Split as QueueSplit};
+
+/// A channel of `N` message slots, for passing messages from a [`Sender`] to
+/// a [`Receiver`] in place.
+pub struct Arena<T, const N: usize>
+where
+    T: Unpin,
+{
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    /// The indices of the filled slots, in the order they were sent.
+    messages: Queue<usize, N>,
+    /// The indices of the free slots.
+    free: Queue<usize, N>,
+}
+
+// SAFETY: a slot is only accessed by whoever holds its index, which is passed
+// between the sender and the receiver through the queues.
+unsafe impl<T, const N: usize> Sync for Arena<T, N> where T: Send + Unpin {}
+// SAFETY: as above.
+unsafe impl<T, const N: usize> Send for Sender<'_, T, N> where T: Send + Unpin {}
+// SAFETY: as above.
+unsafe impl<T, const N: usize> Send for Receiver<'_, T, N> where T: Send + Unpin {}
+
+/// The two halves of a split [`Arena`].
+pub struct Split<'arena, T, const N: usize>
+where
+    T: Unpin,
+{
+    /// The sending half of the arena.
+    pub sender: Sender<'arena, T, N>,
+    /// The receiving half of the arena.
+    pub receiver: Receiver<'arena, T, N>,
+}
+
+impl<T, const N: usize> Arena<T, N>
+where
+    T: Unpin,
+{
+    /// Create a new [`Arena`].
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            messages: Queue::new(),
+            free: Queue::new(),
+        }
+    }
+
+    /// Split the arena into a sender and receiver, with all slots free.
+    ///
+    /// Messages that were sent by a previous sender, but not received, are dropped.
+    pub fn split(&mut self) -> Split<'_, T, N> {
+        self.drop_messages();
+        while self.free.pop_exclusive().is_some() {}
+
+        let slots = &self.slots;
+        let QueueSplit {
+            producer: mut released,
+            consumer: free,
+        } = self.free.split();
+        for index in 0..N {
+            // The free list can hold every index
+            let _ = released.enqueue_or_defer(index);
+        }
+
+        let QueueSplit { producer, consumer } = self.messages.split();
+        Split {
+            sender: Sender {
+                slots,
+                messages: producer,
+                free,
+                spare: None,
+            },
+            receiver: Receiver {
+                slots,
+                messages: consumer,
+                released,
+            },
+        }
+    }
+
+    fn drop_messages(&mut self) {
+        while let Some(index) = self.messages.pop_exclusive() {
+            // SAFETY: sent slots are initialized, and we have exclusive access to them.
+            unsafe { self.slots[index].get_mut().assume_init_drop() };
+        }
+    }
+}
+
+impl<T, const N: usize> Default for Arena<T, N>
+where
+    T: Unpin,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Arena<T, N>
+where
+    T: Unpin,
+{
+    fn drop(&mut self) {
+        self.drop_messages();
+    }
+}
+
+/// The sending half of an [`Arena`].
+pub struct Sender<'arena, T, const N: usize>
+where
+    T: Unpin,
+{
+    slots: &'arena [UnsafeCell<MaybeUninit<T>>; N],
+    messages: Producer<'arena, usize, N>,
+    free: Consumer<'arena, usize, N>,
+    /// A slot that was acquired, but not sent.
+    spare: Option<usize>,
+}
+
+impl<'arena, T, const N: usize> Sender<'arena, T, N>
+where
+    T: Unpin,
+{
+    /// Acquire a free slot.
+    ///
+    /// The returned future resolves once the [`Receiver`] has freed a slot, if none are
+    /// free, or to [`Finished`] once the receiver is dropped and no slot is free.
+    pub async fn acquire(&mut self) -> Result<Slot<'_, 'arena, T, N>, Finished> {
+        let index = match self.spare.take() {
+            Some(index) => index,
+            // The free list is finished once the receiver is gone
+            None => self.free.dequeue().await?,
+        };
+
+        Ok(Slot {
+            sender: self,
+            index,
+            sent: false,
+        })
+    }
+
+    /// Returns the amount of free slots.
+    pub fn free(&self) -> usize {
+        self.free.len() + self.spare.is_some() as usize
+    }
+
+    /// Stop sending messages.
+    ///
+    /// Once the [`Receiver`] has received all messages that were sent,
+    /// it receives [`Finished`].
+    pub async fn finish(self) {
+        self.messages.finish().await;
+    }
+}
+
+/// A free slot of an [`Arena`], acquired with [`Sender::acquire`].
+///
+/// If it is dropped without being sent, the slot stays free. Anything that was
+/// written into it is not dropped.
+pub struct Slot<'sender, 'arena, T, const N: usize>
+where
+    T: Unpin,
+{
+    sender: &'sender mut Sender<'arena, T, N>,
+    index: usize,
+    sent: bool,
+}
+
+impl<T, const N: usize> Slot<'_, '_, T, N>
+where
+    T: Unpin,
+{
+    /// The uninitialized slot, for filling the message in place.
+    pub fn as_uninit(&mut self) -> &mut MaybeUninit<T> {
+        // SAFETY: we hold the index of the slot, so nothing else accesses it.
+        unsafe { &mut *self.sender.slots[self.index].get() }
+    }
+
+    /// Write `message` into the slot, and send it.
+    ///
+    /// This never waits, as the queue has a place for every slot.
+    pub fn send(mut self, message: T) {
+        self.as_uninit().write(message);
+        // SAFETY: the slot was just initialized.
+        unsafe { self.send_filled() };
+    }
+
+    /// Send the message that was filled in with [`Slot::as_uninit`].
+    ///
+    /// # Safety
+    /// The slot must be initialized.
+    pub unsafe fn send_filled(mut self) {
+        self.sent = true;
+        // The queue has a place for every slot
+        let _ = self.sender.messages.enqueue_or_defer(self.index);
+    }
+}
+
+impl<T, const N: usize> Drop for Slot<'_, '_, T, N>
+where
+    T: Unpin,
+{
+    fn drop(&mut self) {
+        if !self.sent {
+            self.sender.spare = Some(self.index);
+        }
+    }
+}
+
+/// The receiving half of an [`Arena`].
+pub struct Receiver<'arena, T, const N: usize>
+where
+    T: Unpin,
+{
+    slots: &'arena [UnsafeCell<MaybeUninit<T>>; N],
+    messages: Consumer<'arena, usize, N>,
+    released: Producer<'arena, usize, N>,
+}
+
+impl<'arena, T, const N: usize> Receiver<'arena, T, N>
+where
+    T: Unpin,
+{
+    /// Receive the next message.
+    ///
+    /// The returned future resolves once the [`Sender`] has sent a message, or
+    /// to [`Finished`] once the sender has finished.
+    pub async fn receive(&mut self) -> Result<Message<'_, 'arena, T, N>, Finished> {
+        let index = self.messages.dequeue().await?;
+        Ok(Message {
+            receiver: self,
+            index,
+            taken: false,
+        })
+    }
+}
+
+/// A message in a slot of an [`Arena`], received with [`Receiver::receive`].
+///
+/// The message is dropped and its slot freed once this is dropped.
+pub struct Message<'receiver, 'arena, T, const N: usize>
+where
+    T: Unpin,
+{
+    receiver: &'receiver mut Receiver<'arena, T, N>,
+    index: usize,
+    taken: bool,
+}
+
+impl<T, const N: usize> Message<'_, '_, T, N>
+where
+    T: Unpin,
+{
+    /// Move the message out of its slot, and free the slot.
+    pub fn take(mut this: Self) -> T {
+        this.taken = true;
+        // SAFETY: the slot holds a sent message, which is not dropped
+        // again once it was taken.
+        unsafe { (*this.receiver.slots[this.index].get()).assume_init_read() }
+    }
+}
+
+impl<T, const N: usize> Deref for Message<'_, '_, T, N>
+where
+    T: Unpin,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the slot holds a sent message, which only we access.
+        unsafe { (*self.receiver.slots[self.index].get()).assume_init_ref() }
+    }
+}
+
+impl<T, const N: usize> DerefMut for Message<'_, '_, T, N>
+where
+    T: Unpin,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: as above.
+        unsafe { (*self.receiver.slots[self.index].get()).assume_init_mut() }
+    }
+}
+
+impl<T, const N: usize> Drop for Message<'_, '_, T, N>
+where
+    T: Unpin,
+{
+    fn drop(&mut self) {
+        if !self.taken {
+            // SAFETY: the slot holds a sent message, which is not used after this.
+            unsafe { (*self.receiver.slots[self.index].get()).assume_init_drop() };
+        }
+        // The free list can hold every index
+        let _ = self.receiver.released.enqueue_or_defer(self.index);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::{boxed::Box, rc::Rc};
+
+    use super::{Arena, Message, Split};
+
+    #[tokio::test]
+    async fn pass_messages() {
+        let arena: &'static mut Arena<[u32; 64], 2> = Box::leak(Box::default());
+        let Split {
+            mut sender,
+            mut receiver,
+        } = arena.split();
+
+        let consumer = tokio::task::spawn(async move {
+            let mut received = 0;
+            while let Ok(message) = receiver.receive().await {
+                assert_eq!(*message, [received; 64]);
+                received += 1;
+            }
+            received
+        });
+
+        // Only two slots, so they have to be freed to be acquired again
+        for i in 0..8 {
+            sender.acquire().await.unwrap().send([i; 64]);
+        }
+        sender.finish().await;
+        assert_eq!(consumer.await.unwrap(), 8);
+    }
+
+    #[tokio::test]
+    async fn drops_messages() {
+        let counter = Rc::new(());
+        let mut arena: Arena<Rc<()>, 2> = Arena::new();
+        let Split {
+            mut sender,
+            mut receiver,
+        } = arena.split();
+
+        // A slot that is not sent is reused
+        drop(sender.acquire().await.unwrap());
+        assert_eq!(sender.free(), 2);
+
+        sender.acquire().await.unwrap().send(counter.clone());
+        sender.acquire().await.unwrap().send(counter.clone());
+        assert_eq!(Rc::strong_count(&counter), 3);
+
+        let taken = Message::take(receiver.receive().await.unwrap());
+        assert_eq!(Rc::strong_count(&counter), 3);
+        drop(taken);
+
+        // The message that was not received is dropped with the arena
+        drop((sender, receiver));
+        drop(arena);
+        assert_eq!(Rc::strong_count(&counter), 1);
+    }
+    #[tokio::test]
+    async fn receiver_dropped() {
+        let mut arena: Arena<u32, 1> = Arena::new();
+        let Split {
+            mut sender,
+            mut receiver,
+        } = arena.split();
+
+        sender.acquire().await.unwrap().send(0);
+        assert_eq!(*receiver.receive().await.unwrap(), 0);
+
+        // The slot that was freed can still be acquired, but no slot is freed after that
+        drop(receiver);
+        sender.acquire().await.unwrap().send(1);
+        assert!(sender.acquire().await.is_err());
+    }
+}
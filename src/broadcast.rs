@@ -0,0 +1,426 @@
+//! A broadcast of `'static` references, for announcing large immutable values to many
+//! subscribers.
+//!
+//! Only a reference passes through a [`Broadcast`], so a value is never cloned, no matter
+//! how many [`Subscriber`]s there are. The broadcast keeps the `N` most recent references,
+//! and the [`LagPolicy`] of every subscriber decides what happens once it falls further
+//! behind: it skips the older references, is told how many it missed, or makes the
+//! [`Publisher`] wait for it. Every subscriber counts how many references it missed.
+//!
+//! ```
+//! use heapless_async_queues::broadcast::Broadcast;
+//!
+//! struct Config {
+//!     gains: [f32; 64],
+//! }
+//!
+//! static DEFAULT: Config = Config { gains: [1.0; 64] };
+//! static CONFIG: Broadcast<Config, 2> = Broadcast::new();
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let mut publisher = CONFIG.publisher().unwrap();
+//! let mut subscriber = CONFIG.subscribe();
+//!
+//! publisher.publish(&DEFAULT).await;
+//! let config = subscriber.recv().await.unwrap();
+//! assert_eq!(config.gains[0], 1.0);
+//! # });
+//! ```
+
+use core::{
+    future::poll_fn,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use crate::{
+    builder::WakeStrategy, log::*, mutex::Mutex, waker::WakerRegistration, waker_set::WakerSet,
+};
+
+/// What happens once a [`Subscriber`] falls more than `N` references behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Skip the references that were replaced.
+    Skip,
+    /// Skip the references that were replaced, and report how many with
+    /// [`RecvError::Lagged`].
+    Error,
+    /// The [`Publisher`] waits for the subscriber, so it never misses a reference.
+    Block,
+}
+
+/// The error returned by [`Subscriber::recv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// The subscriber fell too far behind, and missed this many references. The next
+    /// receive returns the oldest reference that is still kept.
+    Lagged(usize),
+}
+
+/// The position of a [`Subscriber`] that blocks the [`Publisher`].
+struct Cursor {
+    active: AtomicBool,
+    next: AtomicUsize,
+}
+
+/// A broadcast of references to `T`, keeping the `N` most recent ones, which up to
+/// `W` subscribers can wait for at the same time.
+///
+/// Up to `W` of the subscribers can use [`LagPolicy::Block`].
+pub struct Broadcast<T: 'static, const N: usize, const W: usize = 4> {
+    slots: [AtomicPtr<T>; N],
+    /// The sequence number of the reference in every slot, plus one. Zero while the
+    /// slot is being written.
+    stamps: [AtomicUsize; N],
+    /// The amount of references that were published.
+    published: AtomicUsize,
+    publisher_taken: AtomicBool,
+    publisher_waker: Mutex<WakerRegistration>,
+    cursors: [Cursor; W],
+    wakers: WakerSet<W>,
+}
+
+impl<T, const N: usize, const W: usize> Broadcast<T, N, W>
+where
+    T: Sync + 'static,
+{
+    /// Checked at compile time, when the broadcast is created.
+    const VALID_SIZE: () = assert!(N > 0, "The broadcast must keep at least one reference");
+
+    /// Create a new [`Broadcast`].
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_SIZE;
+
+        Self {
+            slots: [const { AtomicPtr::new(ptr::null_mut()) }; N],
+            stamps: [const { AtomicUsize::new(0) }; N],
+            published: AtomicUsize::new(0),
+            publisher_taken: AtomicBool::new(false),
+            publisher_waker: Mutex::new(WakerRegistration::new()),
+            cursors: [const {
+                Cursor {
+                    active: AtomicBool::new(false),
+                    next: AtomicUsize::new(0),
+                }
+            }; W],
+            wakers: WakerSet::new(),
+        }
+    }
+
+    /// Take the [`Publisher`] of this broadcast.
+    ///
+    /// Returns `None` if it was already taken.
+    pub fn publisher(&self) -> Option<Publisher<'_, T, N, W>> {
+        (!self.publisher_taken.swap(true, Ordering::AcqRel))
+            .then_some(Publisher { broadcast: self })
+    }
+
+    /// Create a [`Subscriber`] with [`LagPolicy::Skip`], which receives the references
+    /// that are published from now on.
+    pub fn subscribe(&self) -> Subscriber<'_, T, N, W> {
+        Subscriber {
+            broadcast: self,
+            next: self.published.load(Ordering::SeqCst),
+            lagged: 0,
+            policy: LagPolicy::Skip,
+            cursor: None,
+        }
+    }
+
+    /// Create a [`Subscriber`] with `policy`, which receives the references that are
+    /// published from now on.
+    ///
+    /// Returns `None` if `policy` is [`LagPolicy::Block`], and `W` subscribers already
+    /// block the publisher.
+    pub fn subscribe_with(&self, policy: LagPolicy) -> Option<Subscriber<'_, T, N, W>> {
+        let mut subscriber = self.subscribe();
+        subscriber.policy = policy;
+        if policy == LagPolicy::Block {
+            let index = self.cursors.iter().position(|cursor| {
+                cursor
+                    .active
+                    .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            })?;
+            // Publishing may have continued while we took the cursor
+            subscriber.next = self.published.load(Ordering::SeqCst);
+            self.cursors[index]
+                .next
+                .store(subscriber.next, Ordering::SeqCst);
+            subscriber.cursor = Some(index);
+        }
+        Some(subscriber)
+    }
+
+    /// Returns true if a blocking subscriber would miss a reference that
+    /// is published now.
+    fn is_blocked(&self) -> bool {
+        let published = self.published.load(Ordering::SeqCst);
+        self.cursors.iter().any(|cursor| {
+            cursor.active.load(Ordering::SeqCst)
+                && published.wrapping_sub(cursor.next.load(Ordering::SeqCst)) >= N
+        })
+    }
+
+    fn wake_publisher(&self) {
+        // If the waker is locked, the publisher is registering, and checks
+        // the cursors afterwards.
+        if let Some(mut wk) = self.publisher_waker.try_lock() {
+            wk.wake();
+        }
+    }
+
+    /// Read the reference with sequence number `seq`, if it is still in its slot.
+    fn read(&self, seq: usize) -> Option<&'static T> {
+        let index = seq % N;
+        let expected = seq.wrapping_add(1);
+
+        let before = self.stamps[index].load(Ordering::SeqCst);
+        let value = self.slots[index].load(Ordering::SeqCst);
+        let after = self.stamps[index].load(Ordering::SeqCst);
+        if before != expected || after != expected {
+            return None;
+        }
+        // SAFETY: the stamps show that the slot held a reference that was published.
+        Some(unsafe { &*value })
+    }
+}
+
+impl<T, const N: usize, const W: usize> Default for Broadcast<T, N, W>
+where
+    T: Sync + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The publishing half of a [`Broadcast`], taken with [`Broadcast::publisher`].
+pub struct Publisher<'broadcast, T: 'static, const N: usize, const W: usize> {
+    broadcast: &'broadcast Broadcast<T, N, W>,
+}
+
+impl<T, const N: usize, const W: usize> Publisher<'_, T, N, W>
+where
+    T: Sync + 'static,
+{
+    /// Publish `value` to all subscribers, replacing the oldest reference if `N`
+    /// references are kept already.
+    ///
+    /// The returned future resolves once every subscriber with [`LagPolicy::Block`]
+    /// has received the reference that is replaced.
+    pub async fn publish(&mut self, value: &'static T) {
+        poll_fn(|cx| {
+            if self.try_publish(value).is_ok() {
+                return Poll::Ready(());
+            }
+            match self.broadcast.publisher_waker.try_lock() {
+                Some(mut wk) => {
+                    wk.register(cx.waker());
+                }
+                None => cx.waker().wake_by_ref(),
+            }
+            // Check again, in case a subscriber caught up before we registered
+            match self.try_publish(value) {
+                Ok(()) => Poll::Ready(()),
+                Err(_) => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Publish `value` to all subscribers, like [`Publisher::publish`].
+    ///
+    /// Returns `value` if a subscriber with [`LagPolicy::Block`] would miss a reference.
+    pub fn try_publish(&mut self, value: &'static T) -> Result<(), &'static T> {
+        let broadcast = self.broadcast;
+        if broadcast.is_blocked() {
+            trace!("Publisher is blocked by a subscriber");
+            return Err(value);
+        }
+        let seq = broadcast.published.load(Ordering::Relaxed);
+        let index = seq % N;
+
+        broadcast.stamps[index].store(0, Ordering::SeqCst);
+        broadcast.slots[index].store(ptr::from_ref(value).cast_mut(), Ordering::SeqCst);
+        broadcast.stamps[index].store(seq.wrapping_add(1), Ordering::SeqCst);
+        broadcast
+            .published
+            .store(seq.wrapping_add(1), Ordering::SeqCst);
+
+        trace!("Published reference {}", seq);
+        broadcast.wakers.wake_or_defer(WakeStrategy::All);
+        Ok(())
+    }
+}
+
+impl<T, const N: usize, const W: usize> Drop for Publisher<'_, T, N, W> {
+    fn drop(&mut self) {
+        self.broadcast
+            .publisher_taken
+            .store(false, Ordering::Release);
+    }
+}
+
+/// A subscriber of a [`Broadcast`], created with [`Broadcast::subscribe`].
+pub struct Subscriber<'broadcast, T: 'static, const N: usize, const W: usize> {
+    broadcast: &'broadcast Broadcast<T, N, W>,
+    /// The sequence number of the next reference to receive.
+    next: usize,
+    lagged: usize,
+    policy: LagPolicy,
+    /// The cursor that blocks the publisher, for [`LagPolicy::Block`].
+    cursor: Option<usize>,
+}
+
+impl<T, const N: usize, const W: usize> Subscriber<'_, T, N, W>
+where
+    T: Sync + 'static,
+{
+    /// Receive the next reference, if one was published.
+    ///
+    /// References that were replaced before they were received are counted in
+    /// [`Subscriber::lagged`], and handled according to the [`LagPolicy`].
+    pub fn try_recv(&mut self) -> Result<Option<&'static T>, RecvError> {
+        let broadcast = self.broadcast;
+        loop {
+            let published = broadcast.published.load(Ordering::SeqCst);
+            let behind = published.wrapping_sub(self.next);
+            if behind == 0 {
+                return Ok(None);
+            }
+            if behind > N {
+                let skipped = behind - N;
+                debug!("Subscriber skipped {} references", skipped);
+                self.lagged += skipped;
+                self.next = published.wrapping_sub(N);
+                if self.policy == LagPolicy::Error {
+                    return Err(RecvError::Lagged(skipped));
+                }
+            }
+
+            if let Some(value) = broadcast.read(self.next) {
+                self.next = self.next.wrapping_add(1);
+                if let Some(index) = self.cursor {
+                    broadcast.cursors[index]
+                        .next
+                        .store(self.next, Ordering::SeqCst);
+                    broadcast.wake_publisher();
+                }
+                return Ok(Some(value));
+            }
+            // The reference was replaced while we read it, so we are behind again
+        }
+    }
+
+    /// Receive the next reference.
+    ///
+    /// The returned future resolves once a reference was published, if none was
+    /// published since the last one was received.
+    pub async fn recv(&mut self) -> Result<&'static T, RecvError> {
+        poll_fn(|cx| {
+            if let Some(value) = self.try_recv().transpose() {
+                return Poll::Ready(value);
+            }
+            if !self.broadcast.wakers.register(cx.waker()) {
+                cx.waker().wake_by_ref();
+            }
+            // Check again, in case a reference was published before we registered
+            match self.try_recv().transpose() {
+                Some(value) => Poll::Ready(value),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Returns the amount of references that this subscriber missed, because
+    /// it fell too far behind.
+    pub fn lagged(&self) -> usize {
+        self.lagged
+    }
+
+    /// Returns the [`LagPolicy`] of this subscriber.
+    pub fn policy(&self) -> LagPolicy {
+        self.policy
+    }
+}
+
+impl<T: 'static, const N: usize, const W: usize> Drop for Subscriber<'_, T, N, W> {
+    fn drop(&mut self) {
+        if let Some(index) = self.cursor {
+            let broadcast = self.broadcast;
+            broadcast.cursors[index]
+                .active
+                .store(false, Ordering::SeqCst);
+            if let Some(mut wk) = broadcast.publisher_waker.try_lock() {
+                wk.wake();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Broadcast, LagPolicy, RecvError};
+
+    static VALUES: [u32; 5] = [0, 1, 2, 3, 4];
+
+    #[tokio::test]
+    async fn lagging_subscriber() {
+        static BROADCAST: Broadcast<u32, 2> = Broadcast::new();
+
+        let mut publisher = BROADCAST.publisher().unwrap();
+        assert!(BROADCAST.publisher().is_none());
+        let mut fast = BROADCAST.subscribe();
+        let mut slow = BROADCAST.subscribe();
+        let mut erroring = BROADCAST.subscribe_with(LagPolicy::Error).unwrap();
+
+        for value in &VALUES {
+            publisher.publish(value).await;
+            assert_eq!(fast.recv().await, Ok(value));
+        }
+        assert_eq!(fast.lagged(), 0);
+
+        // Only the two most recent references are kept
+        assert_eq!(slow.recv().await, Ok(&3));
+        assert_eq!(slow.try_recv(), Ok(Some(&4)));
+        assert_eq!(slow.try_recv(), Ok(None));
+        assert_eq!(slow.lagged(), 3);
+
+        assert_eq!(erroring.recv().await, Err(RecvError::Lagged(3)));
+        assert_eq!(erroring.recv().await, Ok(&3));
+    }
+
+    #[tokio::test]
+    async fn blocking_subscriber() {
+        static BROADCAST: Broadcast<u32, 2, 1> = Broadcast::new();
+
+        let mut publisher = BROADCAST.publisher().unwrap();
+        let mut blocking = BROADCAST.subscribe_with(LagPolicy::Block).unwrap();
+        assert!(BROADCAST.subscribe_with(LagPolicy::Block).is_none());
+
+        publisher.publish(&VALUES[0]).await;
+        publisher.publish(&VALUES[1]).await;
+        assert!(publisher.try_publish(&VALUES[2]).is_err());
+
+        let received = tokio::spawn(async move {
+            let mut received = [0; 5];
+            for value in &mut received {
+                *value = *blocking.recv().await.unwrap();
+            }
+            (received, blocking.lagged())
+        });
+        for value in &VALUES[2..] {
+            publisher.publish(value).await;
+        }
+        assert_eq!(received.await.unwrap(), (VALUES, 0));
+
+        // Once the subscriber is gone, the publisher no longer waits
+        assert!(publisher.try_publish(&VALUES[0]).is_ok());
+        assert!(publisher.try_publish(&VALUES[1]).is_ok());
+        assert!(publisher.try_publish(&VALUES[2]).is_ok());
+    }
+}
@@ -0,0 +1,226 @@
+//! Configuration of queue behavior.
+//!
+//! A [`QueueBuilder`] collects the options of a queue, and then builds an
+//! [`spsc::Queue`](crate::spsc::Queue) or an [`MpMcQueue`]
+//! from them. All of the builder methods are `const`, so configured queues can
+//! still be placed in a `static`:
+//!
+//! ```
+//! use heapless_async_queues::{builder::{OverflowPolicy, QueueBuilder}, mpmc::MpMcQueue};
+//!
+//! static Q: MpMcQueue<u32, 2, 8> = QueueBuilder::new()
+//!     .overflow(OverflowPolicy::DropOldest)
+//!     .metrics(true)
+//!     .build_mpmc();
+//! ```
+
+use crate::{
+    instrument::{Hook, Instrument},
+    mpmc::MpMcQueue,
+    spsc::{Queue, Storage},
+};
+
+/// What happens to a value that is enqueued while the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until space becomes available.
+    Block,
+    /// Drop the value that is being enqueued.
+    DropNewest,
+    /// Drop the oldest value in the queue to make room for the
+    /// value that is being enqueued, i.e. overwrite it.
+    DropOldest,
+    /// Replace the most recently enqueued value with the value that is being enqueued.
+    ///
+    /// The items in the queue are then at most as stale as the capacity of the queue
+    /// allows, while the latest value is never lost. An [`MpMcQueue`] can not replace
+    /// a value that was already enqueued, and drops its oldest value instead.
+    ReplaceNewest,
+    /// Fail to enqueue the value, without waiting.
+    ///
+    /// Fallible enqueues like [`Producer::try_enqueue`](crate::spsc::Producer::try_enqueue)
+    /// hand the value back, and enqueue futures resolve immediately to an error that
+    /// holds it. Enqueues of many items, like
+    /// [`Producer::enqueue_iter`](crate::spsc::Producer::enqueue_iter), drop it.
+    Fail,
+}
+
+/// Which waiting futures are woken when a queue makes progress.
+///
+/// This only makes a difference for queues that can have more than
+/// one waiter on either side, i.e. the [`MpMcQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeStrategy {
+    /// Wake all waiters, in order of registration.
+    All,
+    /// Only wake one registered waiter, the one in the lowest waker slot.
+    ///
+    /// This avoids waking a crowd of futures of which only one can make progress.
+    /// Slots are reused once they are free, so the woken waiter is not necessarily
+    /// the one that has waited the longest.
+    ///
+    /// The [`MpMcQueue`] futures give up their slot when they are dropped, and one
+    /// that is dropped after being woken, but before acting on the wake, passes it on.
+    /// Waiters that register through [`MpMcQueue::poll_enqueue`],
+    /// [`MpMcQueue::poll_dequeue`] or a [`ChannelGroup`](crate::group::ChannelGroup)
+    /// keep their slot until they are woken, even if they stop waiting, so a wake that
+    /// goes to such a waiter is lost.
+    ///
+    /// If the wakers are locked while waking, the wake is deferred, and the holder
+    /// of the lock wakes all waiters instead.
+    One,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Config {
+    pub overflow: OverflowPolicy,
+    pub wake: WakeStrategy,
+    pub low_watermark: usize,
+    pub high_watermark: usize,
+    pub metrics: bool,
+    pub poll_budget: usize,
+    pub instrument: Hook,
+}
+
+impl Config {
+    pub const DEFAULT: Self = Self {
+        overflow: OverflowPolicy::Block,
+        wake: WakeStrategy::All,
+        low_watermark: usize::MAX,
+        high_watermark: 1,
+        metrics: false,
+        poll_budget: usize::MAX,
+        instrument: Hook::NONE,
+    };
+}
+
+/// A builder for configured queues.
+#[derive(Debug, Clone, Copy)]
+pub struct QueueBuilder {
+    config: Config,
+}
+
+impl QueueBuilder {
+    /// Create a new builder.
+    ///
+    /// Without further configuration, it builds queues that behave exactly like
+    /// the ones created with `new()`.
+    pub const fn new() -> Self {
+        Self {
+            config: Config::DEFAULT,
+        }
+    }
+
+    /// Set the [`OverflowPolicy`] of the queue.
+    ///
+    /// Defaults to [`OverflowPolicy::Block`].
+    pub const fn overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.config.overflow = policy;
+        self
+    }
+
+    /// Set the [`WakeStrategy`] of the queue.
+    ///
+    /// Defaults to [`WakeStrategy::All`].
+    pub const fn wake_strategy(mut self, strategy: WakeStrategy) -> Self {
+        self.config.wake = strategy;
+        self
+    }
+
+    /// Set the watermarks of the queue.
+    ///
+    /// A waiting producer is only woken once the queue holds at most `low` items, and
+    /// a waiting consumer is only woken once the queue holds at least `high` items. This
+    /// trades latency for fewer wakeups: items below the high watermark are not delivered
+    /// until more items arrive.
+    ///
+    /// Watermarks only apply to [`spsc::Queue`](crate::spsc::Queue)s, as the [`MpMcQueue`]
+    /// can not keep track of its length.
+    ///
+    /// By default, both sides are woken after every item.
+    pub const fn watermarks(mut self, low: usize, high: usize) -> Self {
+        self.config.low_watermark = low;
+        self.config.high_watermark = high;
+        self
+    }
+
+    /// Enable or disable collection of [`Metrics`](crate::metrics::Metrics).
+    ///
+    /// Disabled by default.
+    pub const fn metrics(mut self, enabled: bool) -> Self {
+        self.config.metrics = enabled;
+        self
+    }
+
+    /// Set the poll budget of the queue.
+    ///
+    /// Operations that move many items in a loop, like
+    /// [`Producer::enqueue_iter`](crate::spsc::Producer::enqueue_iter) or
+    /// [`Consumer::for_each`](crate::spsc::Consumer::for_each), yield to the executor after
+    /// moving `items` items without waiting. This keeps a task that always finds the queue
+    /// ready from starving the other tasks on a single-threaded executor.
+    ///
+    /// Unlimited by default.
+    ///
+    /// # Panics
+    /// If `items` is zero.
+    pub const fn poll_budget(mut self, items: usize) -> Self {
+        assert!(items > 0, "The poll budget must allow at least one item");
+        self.config.poll_budget = items;
+        self
+    }
+
+    /// Call `instrument` on the events of the queue.
+    ///
+    /// Unlike [`QueueBuilder::metrics`], this works for every queue, even the ones
+    /// that are not built with metrics enabled.
+    pub const fn instrument(mut self, instrument: &'static dyn Instrument) -> Self {
+        self.config.instrument = Hook::new(instrument);
+        self
+    }
+
+    /// Build an [`spsc::Queue`](crate::spsc::Queue).
+    ///
+    /// # Panics
+    /// If the high watermark is larger than the capacity of the queue.
+    pub const fn build_spsc<T, const N: usize>(self) -> Queue<T, N>
+    where
+        T: Unpin,
+    {
+        assert!(
+            self.config.high_watermark <= N,
+            "The high watermark must not exceed the capacity of the queue"
+        );
+        Queue::with_config(self.config)
+    }
+
+    /// Build an [`spsc::Queue`](crate::spsc::Queue) that stores its slots in `storage`.
+    ///
+    /// # Panics
+    /// If the high watermark is larger than the capacity of the queue.
+    pub const fn build_spsc_with<T, const N: usize, B>(self, storage: B) -> Queue<T, N, B>
+    where
+        T: Unpin,
+        B: Storage<T, N>,
+    {
+        assert!(
+            self.config.high_watermark <= N,
+            "The high watermark must not exceed the capacity of the queue"
+        );
+        Queue::with_storage_config(storage, self.config)
+    }
+
+    /// Build an [`MpMcQueue`].
+    pub const fn build_mpmc<T, const W: usize, const N: usize>(self) -> MpMcQueue<T, W, N>
+    where
+        T: Unpin,
+    {
+        MpMcQueue::with_config(self.config)
+    }
+}
+
+impl Default for QueueBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
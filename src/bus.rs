@@ -0,0 +1,165 @@
+//! A bus, publishing every item to a changing set of [`spsc`](crate::spsc) queues.
+//!
+//! Subscribers are attached to a [`Bus`] by handing it the [`Producer`] of their queue, and
+//! can be detached again at any time. Every published item is cloned into the queue of
+//! every attached subscriber, which applies its own
+//! [`OverflowPolicy`](crate::builder::OverflowPolicy): a subscriber that may lose items
+//! can use a dropping policy, while one that may not makes the publisher wait for it.
+
+use crate::{
+    log::*,
+    spsc::{Owned, Producer, Storage},
+};
+
+/// A subscriber that is attached to a [`Bus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subscription(usize);
+
+/// A bus with up to `SUBS` subscribers, whose queues hold `N` items.
+pub struct Bus<'queue, T, const N: usize, const SUBS: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    subscribers: [Option<Producer<'queue, T, N, B>>; SUBS],
+}
+
+impl<'queue, T, const N: usize, const SUBS: usize, B> Bus<'queue, T, N, SUBS, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// Create a bus without subscribers.
+    pub fn new() -> Self {
+        Self {
+            subscribers: core::array::from_fn(|_| None),
+        }
+    }
+
+    /// Attach the queue of `producer` as a subscriber.
+    ///
+    /// Returns the producer if all `SUBS` subscriber slots are taken.
+    pub fn attach(
+        &mut self,
+        producer: Producer<'queue, T, N, B>,
+    ) -> Result<Subscription, Producer<'queue, T, N, B>> {
+        match self.subscribers.iter().position(Option::is_none) {
+            Some(index) => {
+                debug!("Attaching bus subscriber {}", index);
+                self.subscribers[index] = Some(producer);
+                Ok(Subscription(index))
+            }
+            None => Err(producer),
+        }
+    }
+
+    /// Detach `subscription`, and hand back the producer of its queue.
+    pub fn detach(&mut self, subscription: Subscription) -> Option<Producer<'queue, T, N, B>> {
+        self.subscribers[subscription.0].take()
+    }
+
+    /// Returns the amount of attached subscribers.
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.iter().flatten().count()
+    }
+
+    /// Publish `value` to every attached subscriber.
+    ///
+    /// The subscribers are enqueued into one after the other, so the returned future
+    /// resolves once every subscriber has taken the value, according to the overflow
+    /// policy of its queue. It resolves to the amount of subscribers whose queue did not
+    /// reject the value with [`OverflowPolicy::Fail`](crate::builder::OverflowPolicy::Fail).
+    /// The value is dropped if no subscriber is attached.
+    pub async fn publish(&mut self, value: T) -> usize
+    where
+        T: Clone,
+    {
+        let mut taken = 0;
+        let mut subscribers = self.subscribers.iter_mut().flatten().peekable();
+        while let Some(producer) = subscribers.next() {
+            if subscribers.peek().is_none() {
+                // The last subscriber can take the value itself
+                taken += producer.enqueue(value).await.is_ok() as usize;
+                break;
+            }
+            taken += producer.enqueue(value.clone()).await.is_ok() as usize;
+        }
+        taken
+    }
+
+    /// Finish the stream of every attached subscriber, and detach them.
+    pub async fn finish(&mut self) {
+        for producer in self.subscribers.iter_mut().filter_map(Option::take) {
+            producer.finish().await;
+        }
+    }
+}
+
+impl<T, const N: usize, const SUBS: usize, B> Default for Bus<'_, T, N, SUBS, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::{boxed::Box, vec::Vec};
+
+    use super::Bus;
+    use crate::{
+        builder::{OverflowPolicy, QueueBuilder},
+        spsc::{Queue, Split},
+    };
+
+    #[tokio::test]
+    async fn publish() {
+        let lossless: &'static mut Queue<u32, 4> = Box::leak(Box::new(Queue::new()));
+        let lossy: &'static mut Queue<u32, 4> = Box::leak(Box::new(
+            QueueBuilder::new()
+                .overflow(OverflowPolicy::DropNewest)
+                .build_spsc(),
+        ));
+        let Split {
+            producer: tx_lossless,
+            consumer: mut rx_lossless,
+        } = lossless.split();
+        let Split {
+            producer: tx_lossy,
+            consumer: mut rx_lossy,
+        } = lossy.split();
+
+        let mut bus: Bus<u32, 4, 2> = Bus::new();
+        // Nobody is subscribed yet
+        bus.publish(0).await;
+
+        let lossy = bus.attach(tx_lossy).ok().unwrap();
+        assert!(bus.attach(tx_lossless).is_ok());
+        assert_eq!(bus.subscribers(), 2);
+
+        let consumer = tokio::spawn(async move {
+            let mut values = Vec::new();
+            while let Ok(value) = rx_lossless.dequeue().await {
+                values.push(value);
+            }
+            values
+        });
+
+        // The lossy subscriber never dequeues, but does not hold up the others
+        for value in 1..10 {
+            bus.publish(value).await;
+        }
+        let mut tx_lossy = bus.detach(lossy).unwrap();
+        bus.finish().await;
+
+        assert_eq!(consumer.await.unwrap(), (1..10).collect::<Vec<_>>());
+        for value in 1..5 {
+            assert_eq!(rx_lossy.try_dequeue().ok(), Some(value));
+        }
+        assert!(tx_lossy.try_enqueue(0).is_ok());
+    }
+}
@@ -0,0 +1,202 @@
+//! The parts that are shared by all queue flavors.
+//!
+//! A [`Core`] holds the configuration, metrics and finished state of a queue, and
+//! applies its [`OverflowPolicy`]. How items are stored, and how a full queue makes
+//! room, is up to the [`Flavor`] of the queue.
+
+use core::{
+    future::{poll_fn, Future},
+    pin::pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
+
+use crate::{
+    builder::{Config, OverflowPolicy},
+    log::*,
+    metrics::{Counters, Metrics},
+};
+
+/// The item that makes room for a new item in a full queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eviction {
+    /// The oldest item.
+    Oldest,
+    /// The newest item. Flavors that can not replace their newest item
+    /// drop the oldest one instead.
+    Newest,
+}
+
+/// The way a flavor of queue stores its items.
+pub trait Flavor<T> {
+    /// Enqueue `value`, if the queue is not full.
+    fn insert(&mut self, value: T) -> Result<(), T>;
+
+    /// Make room for `value` in a full queue by dropping the item chosen by
+    /// `eviction`, and enqueue it.
+    ///
+    /// Returns the amount of dropped items, or `value` if nothing was dropped and
+    /// the enqueuer has to wait.
+    fn evict(&mut self, value: T, eviction: Eviction) -> Result<usize, T>;
+}
+
+/// The state that every queue flavor has.
+pub struct Core {
+    pub config: Config,
+    pub metrics: Counters,
+    /// Set once the producing side has finished the stream.
+    pub finished: AtomicBool,
+}
+
+impl Core {
+    pub const fn new(config: Config) -> Self {
+        Self {
+            metrics: Counters::new(config.metrics, config.instrument),
+            finished: AtomicBool::new(false),
+            config,
+        }
+    }
+
+    /// Create a [`Budget`] for an operation that moves many items.
+    pub fn budget(&self) -> Budget {
+        Budget::new(self.config.poll_budget)
+    }
+
+    /// Returns the [`Metrics`] of the queue, if it was built with metrics enabled.
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.metrics.snapshot()
+    }
+
+    /// Mark the stream as finished.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Release);
+    }
+
+    /// Check if the stream was finished.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// Enqueue `value` into `flavor`, applying the overflow policy if it is full.
+    ///
+    /// Only returns the value if the enqueuer has to wait before it can be enqueued, or if
+    /// the queue rejects it.
+    pub fn push<T, F>(&self, flavor: &mut F, value: T) -> Result<(), T>
+    where
+        F: Flavor<T>,
+    {
+        self.push_counted(flavor, value).map(|_| ())
+    }
+
+    /// Like [`Core::push`], but drops a value that is rejected by
+    /// [`OverflowPolicy::Fail`], for enqueuers that can not hand it back.
+    ///
+    /// Returns true if the value was enqueued, and false if it was dropped.
+    pub fn push_or_drop<T, F>(&self, flavor: &mut F, value: T) -> Result<bool, T>
+    where
+        F: Flavor<T>,
+    {
+        match self.push_counted(flavor, value) {
+            Err(value) if self.config.overflow == OverflowPolicy::Fail => {
+                trace!("Queue full, failing enqueue");
+                drop(value);
+                self.metrics.dropped();
+                Ok(false)
+            }
+            res => res,
+        }
+    }
+
+    /// Like [`Core::push`], but returns true if the value was enqueued, and false if the
+    /// overflow policy dropped it.
+    fn push_counted<T, F>(&self, flavor: &mut F, value: T) -> Result<bool, T>
+    where
+        F: Flavor<T>,
+    {
+        let value = match flavor.insert(value) {
+            Ok(()) => {
+                self.metrics.enqueued();
+                return Ok(true);
+            }
+            Err(value) => value,
+        };
+
+        let eviction = match self.config.overflow {
+            OverflowPolicy::Block | OverflowPolicy::Fail => return Err(value),
+            OverflowPolicy::DropNewest => {
+                trace!("Queue full, dropping newest value");
+                self.metrics.dropped();
+                return Ok(false);
+            }
+            OverflowPolicy::DropOldest => Eviction::Oldest,
+            OverflowPolicy::ReplaceNewest => Eviction::Newest,
+        };
+
+        let dropped = flavor.evict(value, eviction)?;
+        self.metrics.dropped_many(dropped);
+        self.metrics.enqueued();
+        Ok(true)
+    }
+}
+
+/// The amount of items that an operation may move before it yields to the executor.
+pub struct Budget {
+    budget: usize,
+    left: usize,
+}
+
+impl Budget {
+    const fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            left: budget,
+        }
+    }
+
+    /// Returns true if the next item uses up the budget, so that spending
+    /// it yields to the executor.
+    pub fn runs_out(&self) -> bool {
+        self.left == 1
+    }
+
+    /// Move one item with `op`.
+    ///
+    /// The budget is refilled if `op` has to wait, and once it is used up, this
+    /// yields to the executor before returning.
+    pub async fn spend<F>(&mut self, op: F) -> F::Output
+    where
+        F: Future,
+    {
+        let mut op = pin!(op);
+        let output = poll_fn(|cx| {
+            let poll = op.as_mut().poll(cx);
+            if poll.is_pending() {
+                self.left = self.budget;
+            }
+            poll
+        })
+        .await;
+
+        self.left -= 1;
+        if self.left == 0 {
+            trace!("Poll budget used up, yielding");
+            self.left = self.budget;
+            yield_now().await;
+        }
+        output
+    }
+}
+
+/// Yield to the executor once, letting other tasks run.
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
@@ -0,0 +1,162 @@
+//! A debouncer, collapsing bursts of signals into a single delivery.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{log::*, mutex::Mutex, waker::WakerRegistration};
+
+/// A debouncer.
+///
+/// Every call to [`Debouncer::signal`] replaces the pending value and restarts the quiet
+/// period. [`Debouncer::wait`] only resolves once no new value was signalled for the
+/// duration of the quiet period.
+///
+/// The debouncer does not keep track of time by itself: the application advances it
+/// by calling [`Debouncer::tick`], for example from a timer interrupt.
+///
+/// The debouncer can only be shared between contexts if its values can be sent
+/// between them:
+///
+/// ```compile_fail
+/// use heapless_async_queues::debounce::Debouncer;
+/// use std::rc::Rc;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<Debouncer<Rc<u8>>>();
+/// ```
+pub struct Debouncer<T> {
+    value: Mutex<Option<T>>,
+    /// The amount of ticks that remain in the current quiet period.
+    remaining: AtomicU32,
+    quiet_period: u32,
+    waker: Mutex<WakerRegistration>,
+}
+
+impl<T> Debouncer<T> {
+    /// Create a new [`Debouncer`], which delivers a value once no new
+    /// value was signalled for `quiet_period` ticks.
+    pub const fn new(quiet_period: u32) -> Self {
+        Self {
+            value: Mutex::new(None),
+            remaining: AtomicU32::new(0),
+            quiet_period,
+            waker: Mutex::new(WakerRegistration::new()),
+        }
+    }
+
+    /// Signal `value`, replacing the pending value (if any) and restarting
+    /// the quiet period.
+    ///
+    /// If the waiter is currently checking for a value, `value` is returned. Retrying
+    /// is possible, but only works if the waiter can run in between the retries.
+    pub fn signal(&self, value: T) -> Result<(), T> {
+        let Some(mut pending) = self.value.try_lock() else {
+            return Err(value);
+        };
+
+        self.remaining.store(self.quiet_period, Ordering::SeqCst);
+        *pending = Some(value);
+        Ok(())
+    }
+
+    /// Advance the debouncer by one tick.
+    ///
+    /// Wakes the waiter if this tick ends the quiet period.
+    pub fn tick(&self) {
+        let ended = self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| r.checked_sub(1))
+            == Ok(1);
+
+        if ended {
+            if let Some(mut wk) = self.waker.try_lock() {
+                trace!("Quiet period ended, waking waiter");
+                wk.wake();
+            }
+            // If the waker is locked, the waiter is registering, and checks for a
+            // value afterwards.
+        }
+    }
+
+    /// Wait for a debounced value.
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub fn wait(&self) -> WaitFuture<'_, T> {
+        WaitFuture { debouncer: self }
+    }
+
+    /// Take the pending value, if the quiet period has ended.
+    ///
+    /// Returns `Err(())` if the value is being signalled concurrently.
+    fn take(&self) -> Result<Option<T>, ()> {
+        let mut value = self.value.try_lock().ok_or(())?;
+
+        if self.remaining.load(Ordering::SeqCst) == 0 {
+            Ok(value.take())
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// The future returned by [`Debouncer::wait`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct WaitFuture<'a, T> {
+    debouncer: &'a Debouncer<T>,
+}
+
+impl<T> Future for WaitFuture<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let debouncer = self.debouncer;
+
+        // Register before checking, so that a tick ending the quiet period
+        // in between can not be missed.
+        if let Some(mut wk) = debouncer.waker.try_lock() {
+            wk.register(cx.waker());
+        } else {
+            cx.waker().wake_by_ref();
+        }
+
+        match debouncer.take() {
+            Ok(Some(value)) => Poll::Ready(value),
+            Ok(None) => Poll::Pending,
+            Err(()) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::time::Duration;
+
+    use super::Debouncer;
+
+    #[tokio::test]
+    async fn collapses_signals() {
+        static D: Debouncer<u32> = Debouncer::new(3);
+
+        let waiter = tokio::task::spawn(D.wait());
+
+        for i in 0..5 {
+            assert!(D.signal(i).is_ok());
+            D.tick();
+        }
+
+        // One tick of the quiet period is left
+        D.tick();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!waiter.is_finished());
+
+        D.tick();
+        assert_eq!(waiter.await.unwrap(), 4);
+    }
+}
@@ -0,0 +1,234 @@
+//! A channel for loaning `'static` buffers, e.g. between a protocol task and a DMA driver.
+//!
+//! The [`Sender`] acquires a free buffer, fills it, and sends it to the [`Receiver`] as a
+//! [`Descriptor`], together with the length of its contents and some metadata. The receiver
+//! processes the buffer, e.g. by handing it to a DMA transfer, and releases it back into the
+//! free list once it is done, so that the sender can acquire it again.
+//!
+//! The buffers themselves never move, only the descriptors referring to them are queued.
+
+use crate::spsc::{
+    Consumer, EnqueueError, Finished, Producer, ProducerError, Queue, Split as QueueSplit,
+};
+
+/// A buffer that is sent through a [`DescriptorChannel`].
+pub struct Descriptor<M> {
+    /// The buffer.
+    pub buf: &'static mut [u8],
+    /// The length of the contents of the buffer.
+    pub len: usize,
+    /// Metadata describing the contents, e.g. a destination address.
+    pub meta: M,
+}
+
+impl<M> Descriptor<M> {
+    /// Create a new descriptor for the first `len` bytes of `buf`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is larger than the length of `buf`.
+    pub fn new(buf: &'static mut [u8], len: usize, meta: M) -> Self {
+        assert!(len <= buf.len(), "descriptor is longer than its buffer");
+        Self { buf, len, meta }
+    }
+
+    /// The contents of the buffer.
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// A channel for loaning buffers from a [`Sender`] to a [`Receiver`].
+///
+/// The free list holds at most `N` buffers.
+pub struct DescriptorChannel<M, const N: usize>
+where
+    M: Unpin,
+{
+    descriptors: Queue<Descriptor<M>, N>,
+    free: Queue<&'static mut [u8], N>,
+}
+
+/// The two halves of a split [`DescriptorChannel`].
+pub struct Split<'channel, M, const N: usize>
+where
+    M: Unpin,
+{
+    /// The sending half of the channel.
+    pub sender: Sender<'channel, M, N>,
+    /// The receiving half of the channel.
+    pub receiver: Receiver<'channel, M, N>,
+}
+
+impl<M, const N: usize> DescriptorChannel<M, N>
+where
+    M: Unpin,
+{
+    /// Create a new [`DescriptorChannel`]
+    pub const fn new() -> Self {
+        Self {
+            descriptors: Queue::new(),
+            free: Queue::new(),
+        }
+    }
+
+    /// Split the channel into a sender and receiver, with `buffers` in the free list.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more buffers than the free list can hold.
+    pub fn split<I>(&mut self, buffers: I) -> Split<'_, M, N>
+    where
+        I: IntoIterator<Item = &'static mut [u8]>,
+    {
+        let QueueSplit {
+            producer: mut released,
+            consumer: free,
+        } = self.free.split();
+
+        for buf in buffers {
+            if let Err(ProducerError::Full(_)) = released.try_enqueue(buf) {
+                panic!("more buffers than the free list can hold");
+            }
+        }
+
+        let QueueSplit { producer, consumer } = self.descriptors.split();
+        Split {
+            sender: Sender {
+                descriptors: producer,
+                free,
+            },
+            receiver: Receiver {
+                descriptors: consumer,
+                released,
+            },
+        }
+    }
+}
+
+impl<M, const N: usize> Default for DescriptorChannel<M, N>
+where
+    M: Unpin,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The sending half of a [`DescriptorChannel`].
+pub struct Sender<'channel, M, const N: usize>
+where
+    M: Unpin,
+{
+    descriptors: Producer<'channel, Descriptor<M>, N>,
+    free: Consumer<'channel, &'static mut [u8], N>,
+}
+
+impl<M, const N: usize> Sender<'_, M, N>
+where
+    M: Unpin,
+{
+    /// Acquire a buffer from the free list.
+    ///
+    /// The returned future resolves once the [`Receiver`] has released a buffer, if none
+    /// are free, or to [`Finished`] once the receiver is dropped and no buffer is free.
+    pub async fn acquire(&mut self) -> Result<&'static mut [u8], Finished> {
+        // The free list is finished once the receiver is gone
+        self.free.dequeue().await
+    }
+
+    /// Returns the amount of buffers in the free list.
+    pub fn free(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Send `descriptor` to the [`Receiver`].
+    ///
+    /// The returned future resolves once there is space in the channel.
+    pub async fn send(&mut self, descriptor: Descriptor<M>) {
+        // The descriptor is only rejected once the receiver is gone, which would never
+        // process it
+        let _ = self.descriptors.enqueue(descriptor).await;
+    }
+
+    /// Stop sending descriptors.
+    ///
+    /// Once the [`Receiver`] has received all descriptors that were sent,
+    /// it receives [`Finished`].
+    pub async fn finish(self) {
+        self.descriptors.finish().await;
+    }
+}
+
+/// The receiving half of a [`DescriptorChannel`].
+pub struct Receiver<'channel, M, const N: usize>
+where
+    M: Unpin,
+{
+    descriptors: Consumer<'channel, Descriptor<M>, N>,
+    released: Producer<'channel, &'static mut [u8], N>,
+}
+
+impl<M, const N: usize> Receiver<'_, M, N>
+where
+    M: Unpin,
+{
+    /// Receive the next descriptor.
+    ///
+    /// The returned future resolves once the [`Sender`] has sent a descriptor, or
+    /// to [`Finished`] once the sender has finished.
+    pub async fn receive(&mut self) -> Result<Descriptor<M>, Finished> {
+        self.descriptors.dequeue().await
+    }
+
+    /// Release `buf` back into the free list.
+    ///
+    /// The returned future only waits if more buffers are released than
+    /// the free list can hold.
+    pub async fn release(&mut self, buf: &'static mut [u8]) {
+        if let Err(EnqueueError::Disconnected(buf)) = self.released.enqueue(buf).await {
+            // Keep the buffer in the free list for the next split, if it fits
+            let _ = self.released.enqueue_or_defer(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::boxed::Box;
+
+    use super::{Descriptor, DescriptorChannel, Split};
+
+    #[tokio::test]
+    async fn loan_buffers() {
+        let channel: &'static mut DescriptorChannel<u8, 4> = Box::leak(Box::default());
+        let buffers = [0, 1].map(|_| &mut Box::leak(Box::new([0u8; 8]))[..]);
+        let Split {
+            mut sender,
+            mut receiver,
+        } = channel.split(buffers);
+
+        let driver = tokio::task::spawn(async move {
+            let mut address = 0;
+            while let Ok(descriptor) = receiver.receive().await {
+                assert_eq!(descriptor.meta, address);
+                assert_eq!(descriptor.data(), [address; 3]);
+                receiver.release(descriptor.buf).await;
+                address += 1;
+            }
+            assert_eq!(address, 8);
+            assert_eq!(receiver.released.len(), 2);
+        });
+
+        // Only two buffers, so they have to be released to be acquired again
+        for address in 0..8 {
+            let buf = sender.acquire().await.unwrap();
+            buf[..3].fill(address);
+            sender.send(Descriptor::new(buf, 3, address)).await;
+        }
+        sender.finish().await;
+
+        driver.await.unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+//! Finding out who is waiting on a queue.
+//!
+//! With the `diagnostics` feature, [`spsc`](crate::spsc) handles and [`mpmc`](crate::mpmc)
+//! futures and handles can be given a [`WaiterName`]. The name is stored with the waker
+//! that they register while waiting, and the queues report their current [`Waiter`]s:
+//!
+//! ```
+//! use heapless_async_queues::{diagnostics::Operation, mpmc::MpMcQueue};
+//!
+//! static QUEUE: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let uart = tokio::spawn(QUEUE.dequeue().named("uart"));
+//! # tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+//!
+//! // Later, e.g. when the system seems to hang
+//! QUEUE.waiters(|waiter| {
+//!     assert_eq!(waiter.operation, Operation::Dequeue);
+//!     assert_eq!(waiter.name, Some("uart".into()));
+//! });
+//! # QUEUE.enqueue(1).await.unwrap();
+//! # uart.await.unwrap();
+//! # });
+//! ```
+
+/// Identifies a waiting task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaiterName {
+    /// A descriptive name.
+    Name(&'static str),
+    /// A numeric ID, e.g. the index of the task.
+    Id(u32),
+}
+
+impl From<&'static str> for WaiterName {
+    fn from(name: &'static str) -> Self {
+        Self::Name(name)
+    }
+}
+
+impl From<u32> for WaiterName {
+    fn from(id: u32) -> Self {
+        Self::Id(id)
+    }
+}
+
+/// The operation that a [`Waiter`] is waiting to complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Waiting for space to enqueue an item.
+    Enqueue,
+    /// Waiting for an item to dequeue.
+    Dequeue,
+    /// Waiting for the queue to become empty or full.
+    Occupancy,
+}
+
+/// A task that is currently waiting on a queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Waiter {
+    /// The name of the waiter, if it was given one.
+    pub name: Option<WaiterName>,
+    /// What the waiter is waiting for.
+    pub operation: Operation,
+}
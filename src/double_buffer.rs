@@ -0,0 +1,282 @@
+//! A ping-pong double buffer, for streaming pipelines like ADC or DAC DMA transfers.
+//!
+//! The [`Producer`] always owns one of the two halves, and fills it. Once a half is
+//! complete, the producer submits it to the [`Consumer`] and continues with the other
+//! half, while the consumer processes the completed one.
+
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    ops::Deref,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{log::*, mutex::Mutex, waker::WakerRegistration};
+
+/// The half is owned by the producer.
+const FREE: u8 = 0;
+/// The half was submitted, but not yet taken by the consumer.
+const READY: u8 = 1;
+/// The half is owned by the consumer.
+const READING: u8 = 2;
+
+/// A double buffer.
+pub struct DoubleBuffer<T> {
+    halves: [UnsafeCell<T>; 2],
+    states: [AtomicU8; 2],
+    producer_waker: Mutex<WakerRegistration>,
+    consumer_waker: Mutex<WakerRegistration>,
+}
+
+unsafe impl<T> Sync for DoubleBuffer<T> where T: Send {}
+
+/// The two halves of a split [`DoubleBuffer`].
+pub struct Split<'buffer, T> {
+    /// The producing half of the double buffer.
+    pub producer: Producer<'buffer, T>,
+    /// The consuming half of the double buffer.
+    pub consumer: Consumer<'buffer, T>,
+}
+
+impl<T> DoubleBuffer<T> {
+    /// Create a new [`DoubleBuffer`] from its two halves.
+    pub const fn new(first: T, second: T) -> Self {
+        Self {
+            halves: [UnsafeCell::new(first), UnsafeCell::new(second)],
+            states: [AtomicU8::new(FREE), AtomicU8::new(FREE)],
+            producer_waker: Mutex::new(WakerRegistration::new()),
+            consumer_waker: Mutex::new(WakerRegistration::new()),
+        }
+    }
+
+    /// Split the double buffer into a producer and consumer.
+    ///
+    /// A half that was submitted by a previous producer, but not taken, is kept
+    /// for the consumer and the producer starts with the other half.
+    pub fn split(&mut self) -> Split<'_, T> {
+        for state in &mut self.states {
+            // No guard is alive anymore, so a half that was being read is free again.
+            if *state.get_mut() == READING {
+                *state.get_mut() = FREE;
+            }
+        }
+        let current = (*self.states[0].get_mut() == READY) as usize;
+
+        let buffer = &*self;
+        Split {
+            producer: Producer { buffer, current },
+            consumer: Consumer { buffer },
+        }
+    }
+
+    fn register(waker: &Mutex<WakerRegistration>, cx: &Context<'_>) {
+        if let Some(mut wk) = waker.try_lock() {
+            wk.register(cx.waker());
+        } else {
+            cx.waker().wake_by_ref();
+        }
+    }
+
+    fn wake(waker: &Mutex<WakerRegistration>) {
+        // If the waker is locked, the other side is registering, and checks
+        // the state afterwards.
+        if let Some(mut wk) = waker.try_lock() {
+            wk.wake();
+        }
+    }
+}
+
+impl<T> Default for DoubleBuffer<T>
+where
+    T: Default,
+{
+    fn default() -> Self {
+        Self::new(T::default(), T::default())
+    }
+}
+
+/// The producing half of a [`DoubleBuffer`].
+pub struct Producer<'buffer, T> {
+    buffer: &'buffer DoubleBuffer<T>,
+    current: usize,
+}
+
+impl<'buffer, T> Producer<'buffer, T> {
+    /// The half that is currently being filled.
+    pub fn buffer(&mut self) -> &mut T {
+        // SAFETY: the consumer never accesses the half owned by the producer.
+        unsafe { &mut *self.buffer.halves[self.current].get() }
+    }
+
+    /// Submit the half that is currently being filled to the [`Consumer`].
+    ///
+    /// The returned future resolves once the consumer has released the other half,
+    /// which then becomes the half that is being filled.
+    #[must_use = "the half is not submitted unless the returned future is awaited"]
+    pub fn submit<'me>(&'me mut self) -> SubmitFuture<'me, 'buffer, T> {
+        SubmitFuture { producer: self }
+    }
+
+    /// Returns true if the consumer has released the other half, so that
+    /// [`Producer::submit`] would resolve immediately.
+    pub fn ready(&self) -> bool {
+        self.buffer.states[self.current ^ 1].load(Ordering::Acquire) == FREE
+    }
+}
+
+/// The future returned by [`Producer::submit`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SubmitFuture<'producer, 'buffer, T> {
+    producer: &'producer mut Producer<'buffer, T>,
+}
+
+impl<T> Future for SubmitFuture<'_, '_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let producer = &mut *self.get_mut().producer;
+        let buffer = producer.buffer;
+
+        DoubleBuffer::<T>::register(&buffer.producer_waker, cx);
+
+        if !producer.ready() {
+            return Poll::Pending;
+        }
+
+        let half = producer.current;
+        trace!("Submitting half {}", half);
+        buffer.states[half].store(READY, Ordering::Release);
+        producer.current ^= 1;
+        DoubleBuffer::<T>::wake(&buffer.consumer_waker);
+
+        Poll::Ready(())
+    }
+}
+
+/// The consuming half of a [`DoubleBuffer`].
+pub struct Consumer<'buffer, T> {
+    buffer: &'buffer DoubleBuffer<T>,
+}
+
+impl<'buffer, T> Consumer<'buffer, T> {
+    /// Wait for the [`Producer`] to submit a completed half.
+    ///
+    /// The half is handed back to the producer once the returned [`ReadGuard`]
+    /// is dropped.
+    #[must_use = "no half is taken unless the returned future is awaited"]
+    pub fn swap<'me>(&'me mut self) -> SwapFuture<'me, 'buffer, T> {
+        SwapFuture { consumer: self }
+    }
+
+    /// Returns true if a completed half is available, so that
+    /// [`Consumer::swap`] would resolve immediately.
+    pub fn ready(&self) -> bool {
+        self.ready_half().is_some()
+    }
+
+    fn ready_half(&self) -> Option<usize> {
+        (0..2).find(|&idx| self.buffer.states[idx].load(Ordering::Acquire) == READY)
+    }
+}
+
+/// The future returned by [`Consumer::swap`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SwapFuture<'consumer, 'buffer, T> {
+    consumer: &'consumer mut Consumer<'buffer, T>,
+}
+
+impl<'consumer, T> Future for SwapFuture<'consumer, '_, T> {
+    type Output = ReadGuard<'consumer, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let buffer = self.consumer.buffer;
+
+        DoubleBuffer::<T>::register(&buffer.consumer_waker, cx);
+
+        if let Some(half) = self.consumer.ready_half() {
+            trace!("Taking half {}", half);
+            buffer.states[half].store(READING, Ordering::Release);
+            Poll::Ready(ReadGuard { buffer, half })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A completed half of a [`DoubleBuffer`], returned by [`Consumer::swap`].
+///
+/// The half is handed back to the [`Producer`] when the guard is dropped.
+#[must_use = "if unused the half is immediately handed back to the producer"]
+pub struct ReadGuard<'consumer, T> {
+    buffer: &'consumer DoubleBuffer<T>,
+    half: usize,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: the producer never accesses a half that is being read.
+        unsafe { &*self.buffer.halves[self.half].get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.buffer.states[self.half].store(FREE, Ordering::Release);
+        DoubleBuffer::<T>::wake(&self.buffer.producer_waker);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::boxed::Box;
+
+    use super::{DoubleBuffer, Split};
+
+    #[tokio::test]
+    async fn ping_pong() {
+        let buffer: &'static mut DoubleBuffer<[u32; 4]> = Box::leak(Box::default());
+        let Split {
+            mut producer,
+            mut consumer,
+        } = buffer.split();
+
+        let consumer = tokio::task::spawn(async move {
+            for block in 0..8 {
+                let half = consumer.swap().await;
+                assert_eq!(*half, [block; 4]);
+            }
+        });
+
+        for block in 0..8 {
+            producer.buffer().fill(block);
+            producer.submit().await;
+        }
+
+        consumer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn split_again() {
+        let mut buffer: DoubleBuffer<u32> = DoubleBuffer::default();
+        {
+            let Split { mut producer, .. } = buffer.split();
+            *producer.buffer() = 1;
+            producer.submit().await;
+        }
+
+        // The submitted half is kept for the consumer, and not handed to the producer
+        let Split {
+            mut producer,
+            mut consumer,
+        } = buffer.split();
+        let filling: *const u32 = producer.buffer();
+        let half = consumer.swap().await;
+        assert_eq!(*half, 1);
+        assert!(!core::ptr::eq(filling, &*half));
+    }
+}
@@ -0,0 +1,248 @@
+//! An earliest-deadline-first queue, built on [`heapless::BinaryHeap`].
+
+use core::{
+    cmp::Ordering,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use heapless::binary_heap::{BinaryHeap, Min};
+
+use crate::{builder::WakeStrategy, log::*, mutex::Mutex, waker_set::WakerSet};
+
+/// An item in the heap, ordered by its deadline only.
+struct Entry<D, T> {
+    deadline: D,
+    value: T,
+}
+
+impl<D: Ord, T> PartialEq for Entry<D, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl<D: Ord, T> Eq for Entry<D, T> {}
+
+impl<D: Ord, T> PartialOrd for Entry<D, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<D: Ord, T> Ord for Entry<D, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.deadline.cmp(&other.deadline)
+    }
+}
+
+/// An earliest-deadline-first queue.
+///
+/// Every item is enqueued with a deadline `D`, and [`EdfQueue::dequeue`] always yields the
+/// item with the earliest deadline. The order of items with equal deadlines is unspecified.
+///
+/// Like the [`MpMcQueue`](crate::mpmc::MpMcQueue), the queue can be shared between several
+/// enqueuers and dequeuers, and `W` is the amount of futures that can wait on either side.
+/// It can only be shared between contexts if its deadlines and items can be sent between them:
+///
+/// ```compile_fail
+/// use heapless_async_queues::edf::EdfQueue;
+/// use std::rc::Rc;
+///
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<EdfQueue<u32, Rc<u8>, 2, 4>>();
+/// ```
+pub struct EdfQueue<D, T, const W: usize, const N: usize>
+where
+    D: Ord,
+{
+    heap: Mutex<BinaryHeap<Entry<D, T>, Min, N>>,
+    dequeue_wakers: WakerSet<W>,
+    enqueue_wakers: WakerSet<W>,
+}
+
+impl<D, T, const W: usize, const N: usize> EdfQueue<D, T, W, N>
+where
+    D: Ord,
+{
+    /// Create a new [`EdfQueue`]
+    pub const fn new() -> Self {
+        Self {
+            heap: Mutex::new(BinaryHeap::new()),
+            dequeue_wakers: WakerSet::new(),
+            enqueue_wakers: WakerSet::new(),
+        }
+    }
+
+    /// Enqueue `value` with `deadline`.
+    ///
+    /// The returned Future will resolve once the value is succesfully enqueued.
+    #[must_use = "the value is not enqueued unless the returned future is awaited"]
+    pub fn enqueue(&self, deadline: D, value: T) -> EnqueueFuture<'_, D, T, W, N> {
+        EnqueueFuture {
+            queue: self,
+            entry: Some(Entry { deadline, value }),
+        }
+    }
+
+    /// Dequeue the item with the earliest deadline, together with its deadline.
+    ///
+    /// The returned Future will resolve once an item is succesfully dequeued.
+    #[must_use = "no item is dequeued unless the returned future is awaited"]
+    pub fn dequeue(&self) -> DequeueFuture<'_, D, T, W, N> {
+        DequeueFuture { queue: self }
+    }
+
+    /// Try to push `entry` into the heap.
+    ///
+    /// Returns the entry if the heap is full, or if it is locked by someone else.
+    fn push(&self, entry: Entry<D, T>) -> Result<(), Entry<D, T>> {
+        let Some(mut heap) = self.heap.try_lock() else {
+            return Err(entry);
+        };
+        heap.push(entry)?;
+        drop(heap);
+
+        trace!("Enqueued EDF item, waking dequeuers");
+        self.dequeue_wakers.wake(WakeStrategy::All);
+        Ok(())
+    }
+
+    /// Try to pop the entry with the earliest deadline from the heap.
+    ///
+    /// Returns `Err(true)` if the heap is empty, and `Err(false)` if it
+    /// is locked by someone else.
+    fn pop(&self) -> Result<Entry<D, T>, bool> {
+        let mut heap = self.heap.try_lock().ok_or(false)?;
+        let entry = heap.pop().ok_or(true)?;
+        drop(heap);
+
+        trace!("Dequeued EDF item, waking enqueuers");
+        self.enqueue_wakers.wake(WakeStrategy::All);
+        Ok(entry)
+    }
+}
+
+impl<D, T, const W: usize, const N: usize> Default for EdfQueue<D, T, W, N>
+where
+    D: Ord,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The future returned by [`EdfQueue::enqueue`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct EnqueueFuture<'queue, D, T, const W: usize, const N: usize>
+where
+    D: Ord,
+{
+    queue: &'queue EdfQueue<D, T, W, N>,
+    entry: Option<Entry<D, T>>,
+}
+
+// The entry is never pinned.
+impl<D, T, const W: usize, const N: usize> Unpin for EnqueueFuture<'_, D, T, W, N> where D: Ord {}
+
+impl<D, T, const W: usize, const N: usize> Future for EnqueueFuture<'_, D, T, W, N>
+where
+    D: Ord,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.get_mut();
+        let Some(entry) = me.entry.take() else {
+            return Poll::Ready(());
+        };
+
+        let entry = match me.queue.push(entry) {
+            Ok(()) => return Poll::Ready(()),
+            Err(entry) => entry,
+        };
+
+        if !me.queue.enqueue_wakers.register(cx.waker()) {
+            cx.waker().wake_by_ref();
+        }
+
+        // Try again, in case an item was dequeued before we registered
+        match me.queue.push(entry) {
+            Ok(()) => Poll::Ready(()),
+            Err(entry) => {
+                me.entry = Some(entry);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The future returned by [`EdfQueue::dequeue`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct DequeueFuture<'queue, D, T, const W: usize, const N: usize>
+where
+    D: Ord,
+{
+    queue: &'queue EdfQueue<D, T, W, N>,
+}
+
+impl<D, T, const W: usize, const N: usize> Future for DequeueFuture<'_, D, T, W, N>
+where
+    D: Ord,
+{
+    type Output = (D, T);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let queue = self.queue;
+
+        let empty = match queue.pop() {
+            Ok(entry) => return Poll::Ready((entry.deadline, entry.value)),
+            Err(empty) => empty,
+        };
+
+        if !empty || !queue.dequeue_wakers.register(cx.waker()) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        // Try again, in case an item was enqueued before we registered
+        match queue.pop() {
+            Ok(entry) => Poll::Ready((entry.deadline, entry.value)),
+            Err(false) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(true) => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::time::Duration;
+
+    use super::EdfQueue;
+
+    #[tokio::test]
+    async fn earliest_deadline_first() {
+        static Q: EdfQueue<u32, &str, 2, 4> = EdfQueue::new();
+
+        Q.enqueue(30, "c").await;
+        Q.enqueue(10, "a").await;
+        Q.enqueue(20, "b").await;
+
+        assert_eq!(Q.dequeue().await, (10, "a"));
+
+        Q.enqueue(5, "urgent").await;
+        assert_eq!(Q.dequeue().await, (5, "urgent"));
+        assert_eq!(Q.dequeue().await, (20, "b"));
+        assert_eq!(Q.dequeue().await, (30, "c"));
+
+        let dequeuer = tokio::task::spawn(Q.dequeue());
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        Q.enqueue(1, "late").await;
+        assert_eq!(dequeuer.await.unwrap(), (1, "late"));
+    }
+}
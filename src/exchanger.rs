@@ -0,0 +1,344 @@
+//! A rendezvous point, where two tasks swap values.
+//!
+//! Both tasks call [`Exchanger::exchange`] with a value of their own, and receive the value
+//! of the other task once both have arrived. This is useful for swapping the ownership of
+//! two buffers between a producer and a consumer task:
+//!
+//! ```
+//! use heapless_async_queues::exchanger::Exchanger;
+//!
+//! static BUFFERS: Exchanger<[u8; 4]> = Exchanger::new();
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let consumer = tokio::spawn(async {
+//!     let filled = BUFFERS.exchange([0; 4]).await;
+//!     assert_eq!(filled, [1, 2, 3, 4]);
+//! });
+//!
+//! let empty = BUFFERS.exchange([1, 2, 3, 4]).await;
+//! assert_eq!(empty, [0; 4]);
+//! # consumer.await.unwrap();
+//! # });
+//! ```
+
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    mem::MaybeUninit,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{log::*, mutex::Mutex, waker::WakerRegistration};
+
+/// Nobody is waiting at the exchanger.
+const EMPTY: u8 = 0;
+/// The first task is storing its value.
+const OFFERING: u8 = 1;
+/// The first task is waiting for the second one.
+const OFFERED: u8 = 2;
+/// The second task took the value of the first one, and is storing its own.
+const REPLYING: u8 = 3;
+/// The value of the second task is waiting for the first one.
+const REPLIED: u8 = 4;
+/// The first task was dropped while the second one was storing its value.
+const ABANDONED: u8 = 5;
+
+/// A rendezvous point, where two tasks swap values of type `T`.
+///
+/// Only two tasks should use an exchanger at the same time: while an exchange
+/// is completing, a task that arrives waits for it to finish, but only one such
+/// task is woken.
+pub struct Exchanger<T> {
+    /// The value of the task that arrived first.
+    offered: UnsafeCell<MaybeUninit<T>>,
+    /// The value of the task that arrived second.
+    reply: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicU8,
+    /// The waker of the task that arrived first.
+    offered_waker: Mutex<WakerRegistration>,
+    /// The waker of a task that waits for the exchanger to become empty.
+    empty_waker: Mutex<WakerRegistration>,
+}
+
+unsafe impl<T> Sync for Exchanger<T> where T: Send {}
+
+impl<T> Exchanger<T> {
+    /// Create a new [`Exchanger`].
+    pub const fn new() -> Self {
+        Self {
+            offered: UnsafeCell::new(MaybeUninit::uninit()),
+            reply: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicU8::new(EMPTY),
+            offered_waker: Mutex::new(WakerRegistration::new()),
+            empty_waker: Mutex::new(WakerRegistration::new()),
+        }
+    }
+
+    /// Swap `value` with the value of the other task.
+    ///
+    /// The returned future resolves once the other task has arrived as well. If it is
+    /// dropped before that, `value` is dropped, unless the other task has already
+    /// taken it, in which case the value of the other task is dropped instead.
+    #[must_use = "no value is exchanged unless the returned future is awaited"]
+    pub fn exchange(&self, value: T) -> Exchange<'_, T> {
+        Exchange {
+            exchanger: self,
+            value: Some(value),
+            offered: false,
+            registration: None,
+        }
+    }
+
+    /// Returns true if a task is waiting for another one to exchange with.
+    pub fn is_waiting(&self) -> bool {
+        self.state.load(Ordering::Acquire) == OFFERED
+    }
+
+    /// Try to exchange `value` immediately. Returns the value of the other task, or hands
+    /// back `value` without storing it if the exchanger is busy.
+    fn try_reply(&self, value: T) -> Result<T, T> {
+        if self
+            .state
+            .compare_exchange(OFFERED, REPLYING, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(value);
+        }
+
+        // SAFETY: `REPLYING` gives us exclusive access to both values.
+        let offered = unsafe { (*self.offered.get()).assume_init_read() };
+        unsafe { (*self.reply.get()).write(value) };
+
+        if self
+            .state
+            .compare_exchange(REPLYING, REPLIED, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            Self::wake(&self.offered_waker);
+        } else {
+            // The first task is gone, so nobody will take the reply
+            // SAFETY: the reply was just stored, and the first task no longer accesses it.
+            unsafe { (*self.reply.get()).assume_init_drop() };
+            self.empty();
+        }
+        Ok(offered)
+    }
+
+    /// Try to store `value` for the next task that arrives. Hands back `value` if the
+    /// exchanger is not empty.
+    fn try_offer(&self, value: T, cx: &Context<'_>) -> Result<(), T> {
+        if self
+            .state
+            .compare_exchange(EMPTY, OFFERING, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return Err(value);
+        }
+
+        // SAFETY: `OFFERING` gives us exclusive access to the offered value.
+        unsafe { (*self.offered.get()).write(value) };
+        // Register before publishing the value, so that the reply can not be missed.
+        Self::register(&self.offered_waker, cx);
+        self.state.store(OFFERED, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the reply, if it was stored.
+    fn take_reply(&self) -> Option<T> {
+        if self.state.load(Ordering::Acquire) != REPLIED {
+            return None;
+        }
+        // SAFETY: the second task never touches the reply once it is stored.
+        let reply = unsafe { (*self.reply.get()).assume_init_read() };
+        self.empty();
+        Some(reply)
+    }
+
+    /// Withdraw the offered value, after the first task was dropped.
+    fn withdraw(&self) {
+        match self
+            .state
+            .compare_exchange(OFFERED, EMPTY, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => {
+                // SAFETY: the offered value was not taken, and nobody else can take it now.
+                unsafe { (*self.offered.get()).assume_init_drop() };
+                Self::wake(&self.empty_waker);
+            }
+            Err(REPLYING)
+                if self
+                    .state
+                    .compare_exchange(REPLYING, ABANDONED, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok() =>
+            {
+                // The second task drops its reply itself
+            }
+            Err(_) => drop(self.take_reply()),
+        }
+    }
+
+    fn empty(&self) {
+        self.state.store(EMPTY, Ordering::Release);
+        Self::wake(&self.empty_waker);
+    }
+
+    fn register(waker: &Mutex<WakerRegistration>, cx: &Context<'_>) -> Option<u32> {
+        match waker.try_lock() {
+            Some(mut wk) => Some(wk.register(cx.waker())),
+            None => {
+                cx.waker().wake_by_ref();
+                None
+            }
+        }
+    }
+
+    fn wake(waker: &Mutex<WakerRegistration>) {
+        // If the waker is locked, the other task is registering, and checks
+        // the state afterwards.
+        if let Some(mut wk) = waker.try_lock() {
+            wk.wake();
+        }
+    }
+}
+
+impl<T> Default for Exchanger<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The future returned by [`Exchanger::exchange`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Exchange<'exchanger, T> {
+    exchanger: &'exchanger Exchanger<T>,
+    /// Our value, until it was stored or taken.
+    value: Option<T>,
+    /// Set once we stored our value, and wait for the other task.
+    offered: bool,
+    /// The generation of the registration with `empty_waker`.
+    registration: Option<u32>,
+}
+
+// Our value is never pinned.
+impl<T> Unpin for Exchange<'_, T> {}
+
+impl<T> Exchange<'_, T> {
+    /// Try to exchange our value with an offered one, or offer it ourselves.
+    fn try_arrive(&mut self, cx: &Context<'_>) -> Option<T> {
+        let exchanger = self.exchanger;
+        let value = self.value.take()?;
+
+        let value = match exchanger.try_reply(value) {
+            Ok(offered) => return Some(offered),
+            Err(value) => value,
+        };
+
+        match exchanger.try_offer(value, cx) {
+            Ok(()) => {
+                trace!("Offered a value for exchange");
+                self.offered = true;
+            }
+            Err(value) => self.value = Some(value),
+        }
+        None
+    }
+}
+
+impl<T> Future for Exchange<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.get_mut();
+        let exchanger = me.exchanger;
+
+        if me.offered {
+            if let Some(reply) = exchanger.take_reply() {
+                me.offered = false;
+                return Poll::Ready(reply);
+            }
+            Exchanger::<T>::register(&exchanger.offered_waker, cx);
+            // Check again, in case the reply was stored before we registered
+            return match exchanger.take_reply() {
+                Some(reply) => {
+                    me.offered = false;
+                    Poll::Ready(reply)
+                }
+                None => Poll::Pending,
+            };
+        }
+
+        if let Some(offered) = me.try_arrive(cx) {
+            return Poll::Ready(offered);
+        }
+        if me.offered {
+            return Poll::Pending;
+        }
+
+        // An exchange is completing, so wait for it to finish
+        me.registration = Exchanger::<T>::register(&exchanger.empty_waker, cx);
+        // Check again, in case it finished before we registered
+        match me.try_arrive(cx) {
+            Some(offered) => Poll::Ready(offered),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Exchange<'_, T> {
+    fn drop(&mut self) {
+        if self.offered {
+            self.exchanger.withdraw();
+        }
+        if let Some(generation) = self.registration {
+            if let Some(mut wk) = self.exchanger.empty_waker.try_lock() {
+                wk.unregister(generation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::{pin::pin, rc::Rc};
+
+    use super::Exchanger;
+
+    #[tokio::test]
+    async fn exchange() {
+        static EXCHANGER: Exchanger<u32> = Exchanger::new();
+
+        let other = tokio::spawn(async {
+            let mut values = [0; 3];
+            for (value, round) in values.iter_mut().zip(10..) {
+                *value = EXCHANGER.exchange(round).await;
+            }
+            values
+        });
+
+        let mut values = [0; 3];
+        for (value, round) in values.iter_mut().zip(0..) {
+            *value = EXCHANGER.exchange(round).await;
+        }
+        assert_eq!(values, [10, 11, 12]);
+        assert_eq!(other.await.unwrap(), [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn withdraw() {
+        let value = Rc::new(());
+        let exchanger = Exchanger::new();
+
+        {
+            let mut exchange = pin!(exchanger.exchange(value.clone()));
+            assert!(embassy_futures::poll_once(&mut exchange).is_pending());
+            assert!(exchanger.is_waiting());
+        }
+
+        // The offered value is dropped with the exchange
+        assert!(!exchanger.is_waiting());
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,664 @@
+//! COBS framing over [`spsc`](crate::spsc) queues of bytes.
+//!
+//! A [`FrameSender`] encodes every frame with [Consistent Overhead Byte Stuffing][cobs],
+//! which removes all zero bytes from it, and terminates it with a zero byte. A
+//! [`FrameReceiver`] decodes and validates the frames as the bytes arrive, and yields
+//! whole frames. This makes a byte queue directly usable as a transport, e.g. between
+//! a protocol task and a UART, where the receiver can resynchronize at the next zero byte
+//! after bytes were lost.
+//!
+//! Where bytes can also be corrupted, frames can be protected with a checksum, using
+//! [`FrameSender::with_crc`] and [`FrameReceiver::with_crc`]. Corrupted frames are then
+//! discarded by the receiver, and counted in [`FrameReceiver::errors`].
+//!
+//! This module is only available with the `framing` feature.
+//!
+//! [cobs]: https://en.wikipedia.org/wiki/Consistent_Overhead_Byte_Stuffing
+
+use heapless::Vec;
+
+use crate::{
+    log::*,
+    spsc::{Consumer, Finished, Producer},
+};
+
+/// The byte that terminates every encoded frame.
+const DELIMITER: u8 = 0;
+/// The longest run of non-zero bytes that a single COBS code can describe.
+const MAX_RUN: usize = 254;
+
+/// A checksum algorithm, protecting every frame against corruption.
+///
+/// The checksum is appended to every frame in little-endian byte order.
+pub trait Crc {
+    /// The width of the checksum in bytes, at most 4.
+    const WIDTH: usize;
+
+    /// Compute the checksum of the concatenation of `parts`.
+    fn checksum(&self, parts: &[&[u8]]) -> u32;
+}
+
+/// No checksum at all, for transports that can not corrupt frames.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoCrc;
+
+impl Crc for NoCrc {
+    const WIDTH: usize = 0;
+
+    fn checksum(&self, _: &[&[u8]]) -> u32 {
+        0
+    }
+}
+
+/// The CRC-16/CCITT-FALSE checksum, with polynomial `0x1021` and initial value `0xFFFF`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crc16Ccitt;
+
+impl Crc for Crc16Ccitt {
+    const WIDTH: usize = 2;
+
+    fn checksum(&self, parts: &[&[u8]]) -> u32 {
+        let mut crc: u16 = 0xFFFF;
+        for &byte in parts.iter().flat_map(|part| part.iter()) {
+            crc ^= (byte as u16) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x1021
+                } else {
+                    crc << 1
+                };
+            }
+        }
+        crc as u32
+    }
+}
+
+/// The error returned by [`FrameReceiver::receive`] and [`FrameReceiver::receive_view`].
+///
+/// In every case, the rest of the frame, up to and including the delimiter, was discarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The stream was finished. An incomplete frame before the end
+    /// of the stream is discarded.
+    Finished,
+    /// The decoded frame does not fit into the buffer.
+    TooLong,
+    /// The frame is not validly encoded, e.g. because bytes were lost.
+    Invalid,
+    /// The checksum of the frame does not match its contents.
+    Corrupted,
+}
+
+/// The amount of frames that a [`FrameReceiver`] discarded, by reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameErrors {
+    /// The amount of frames that did not fit into the buffer.
+    pub too_long: usize,
+    /// The amount of frames that were not validly encoded.
+    pub invalid: usize,
+    /// The amount of frames with a checksum that did not match.
+    pub corrupted: usize,
+}
+
+/// The encoding half of a framed byte queue.
+pub struct FrameSender<'queue, const N: usize, C = NoCrc>
+where
+    C: Crc,
+{
+    producer: Producer<'queue, u8, N>,
+    crc: C,
+}
+
+impl<'queue, const N: usize> FrameSender<'queue, N> {
+    /// Create a new [`FrameSender`], enqueueing encoded frames into `producer`.
+    pub fn new(producer: Producer<'queue, u8, N>) -> Self {
+        Self::with_crc(producer, NoCrc)
+    }
+}
+
+impl<'queue, const N: usize, C> FrameSender<'queue, N, C>
+where
+    C: Crc,
+{
+    /// Create a new [`FrameSender`], enqueueing encoded frames into `producer`,
+    /// and protecting every frame with `crc`.
+    pub fn with_crc(producer: Producer<'queue, u8, N>, crc: C) -> Self {
+        Self { producer, crc }
+    }
+
+    /// Encode `frame` together with its checksum, and enqueue it.
+    ///
+    /// The returned future resolves once the whole encoded frame was enqueued.
+    pub async fn send(&mut self, frame: &[u8]) {
+        let checksum = self.crc.checksum(&[frame]).to_le_bytes();
+        self.encode(&[frame, &checksum[..C::WIDTH]]).await;
+    }
+
+    /// Encode the concatenation of `parts` as a single frame, and enqueue it.
+    async fn encode(&mut self, parts: &[&[u8]]) {
+        let mut run: Vec<u8, MAX_RUN> = Vec::new();
+        // A run of the maximum length is not followed by an encoded zero.
+        let mut after_max_run = false;
+
+        for &byte in parts.iter().flat_map(|part| part.iter()) {
+            if byte == DELIMITER {
+                self.enqueue_run(&mut run).await;
+                after_max_run = false;
+            } else {
+                // The run is flushed as soon as it is full, so there is always space.
+                let _ = run.push(byte);
+                if run.is_full() {
+                    self.enqueue_run(&mut run).await;
+                    after_max_run = true;
+                }
+            }
+        }
+
+        if !run.is_empty() || !after_max_run {
+            self.enqueue_run(&mut run).await;
+        }
+        self.enqueue_byte(DELIMITER).await;
+    }
+
+    /// Enqueue the code of `run` and its bytes, and clear it.
+    async fn enqueue_run(&mut self, run: &mut Vec<u8, MAX_RUN>) {
+        self.enqueue_byte(run.len() as u8 + 1).await;
+        for &byte in run.iter() {
+            self.enqueue_byte(byte).await;
+        }
+        run.clear();
+    }
+
+    /// Enqueue one encoded byte.
+    async fn enqueue_byte(&mut self, byte: u8) {
+        // A byte that the queue rejects corrupts the frame, which the receiver
+        // detects like a byte that a dropping overflow policy dropped.
+        let _ = self.producer.enqueue(byte).await;
+    }
+
+    /// Finish the stream, after the frames that were sent.
+    pub async fn finish(self) {
+        self.producer.finish().await;
+    }
+
+    /// Returns the producer that encoded frames are enqueued into.
+    pub fn into_inner(self) -> Producer<'queue, u8, N> {
+        self.producer
+    }
+}
+
+/// The decoding half of a framed byte queue.
+pub struct FrameReceiver<'queue, const N: usize, C = NoCrc>
+where
+    C: Crc,
+{
+    consumer: Consumer<'queue, u8, N>,
+    crc: C,
+    errors: FrameErrors,
+}
+
+impl<'queue, const N: usize> FrameReceiver<'queue, N> {
+    /// Create a new [`FrameReceiver`], decoding frames dequeued from `consumer`.
+    pub fn new(consumer: Consumer<'queue, u8, N>) -> Self {
+        Self::with_crc(consumer, NoCrc)
+    }
+}
+
+impl<'queue, const N: usize, C> FrameReceiver<'queue, N, C>
+where
+    C: Crc,
+{
+    /// Create a new [`FrameReceiver`], decoding frames dequeued from `consumer`,
+    /// and validating them with `crc`.
+    pub fn with_crc(consumer: Consumer<'queue, u8, N>, crc: C) -> Self {
+        Self {
+            consumer,
+            crc,
+            errors: FrameErrors::default(),
+        }
+    }
+
+    /// Returns the amount of frames that were discarded so far.
+    pub fn errors(&self) -> FrameErrors {
+        self.errors
+    }
+
+    /// Dequeue and validate the next frame into `buf`.
+    ///
+    /// The frame, without its checksum, is appended to `buf`. Returns the length of the
+    /// frame, or a [`FrameError`] if the frame was discarded. Delimiters without a frame
+    /// in between them are skipped.
+    pub async fn receive<const M: usize>(
+        &mut self,
+        buf: &mut Vec<u8, M>,
+    ) -> Result<usize, FrameError> {
+        let start = buf.len();
+        let res = match self.decode(buf).await {
+            Ok(_) => validate(&self.crc, (&buf[start..], &[])).ok_or(FrameError::Corrupted),
+            Err(error) => Err(error),
+        };
+
+        match res {
+            Ok(len) => {
+                buf.truncate(start + len);
+                Ok(len)
+            }
+            Err(error) => {
+                self.discarded(error);
+                buf.truncate(start);
+                Err(error)
+            }
+        }
+    }
+
+    /// Decode and validate the next frame in place, in the queue.
+    ///
+    /// Unlike [`FrameReceiver::receive`], the frame is not copied out of the queue: the
+    /// returned [`FrameView`] borrows it, and releases it from the queue once it is
+    /// dropped. This requires the whole encoded frame to fit into the queue at once, so
+    /// frames that are longer than `N` bytes are discarded as [`FrameError::TooLong`].
+    pub async fn receive_view(&mut self) -> Result<FrameView<'_, 'queue, N, C>, FrameError> {
+        loop {
+            let (first, second) = self
+                .consumer
+                .read_regions(|first, second, stuck| {
+                    stuck || first.contains(&DELIMITER) || second.contains(&DELIMITER)
+                })
+                .await;
+            let available = first.len() + second.len();
+            let end = first
+                .iter()
+                .chain(second.iter())
+                .position(|&byte| byte == DELIMITER);
+
+            let Some(end) = end else {
+                // No delimiter can arrive before the bytes are released
+                let finished = self.consumer.is_finished();
+                self.consumer.release_regions(available);
+                if finished {
+                    return Err(FrameError::Finished);
+                }
+                // Discard the rest of the frame
+                loop {
+                    match self.consumer.dequeue().await {
+                        Ok(DELIMITER) => break,
+                        Ok(_) => {}
+                        Err(Finished) => return Err(FrameError::Finished),
+                    }
+                }
+                self.discarded(FrameError::TooLong);
+                return Err(FrameError::TooLong);
+            };
+
+            if end == 0 {
+                // Nothing but a delimiter, keep waiting for a frame.
+                self.consumer.release_regions(1);
+                continue;
+            }
+
+            let res = decode_in_place(first, second, end).and_then(|len| {
+                let (first, second) = self.consumer.regions();
+                validate(&self.crc, split(first, second, len)).ok_or(FrameError::Corrupted)
+            });
+            match res {
+                Ok(len) => {
+                    return Ok(FrameView {
+                        receiver: self,
+                        len,
+                        encoded: end + 1,
+                    })
+                }
+                Err(error) => {
+                    self.consumer.release_regions(end + 1);
+                    self.discarded(error);
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Dequeue and validate frames into `buf` until a frame is valid.
+    ///
+    /// Like [`FrameReceiver::receive`], but frames that are discarded are only
+    /// counted in [`FrameReceiver::errors`].
+    pub async fn receive_valid<const M: usize>(
+        &mut self,
+        buf: &mut Vec<u8, M>,
+    ) -> Result<usize, Finished> {
+        loop {
+            match self.receive(buf).await {
+                Ok(len) => return Ok(len),
+                Err(FrameError::Finished) => return Err(Finished),
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Count a frame that was discarded because of `error`.
+    fn discarded(&mut self, error: FrameError) {
+        let counter = match error {
+            FrameError::Finished => return,
+            FrameError::TooLong => &mut self.errors.too_long,
+            FrameError::Invalid => &mut self.errors.invalid,
+            FrameError::Corrupted => &mut self.errors.corrupted,
+        };
+        *counter += 1;
+        debug!("Discarding frame that could not be decoded or validated");
+    }
+
+    /// Dequeue and decode the next frame into `buf`.
+    async fn decode<const M: usize>(&mut self, buf: &mut Vec<u8, M>) -> Result<usize, FrameError> {
+        let start = buf.len();
+        let mut error = None;
+        // The code of the current run, and the amount of bytes left in it.
+        let mut code = 0u8;
+        let mut remaining = 0u8;
+
+        loop {
+            let Ok(byte) = self.consumer.dequeue().await else {
+                buf.truncate(start);
+                return Err(FrameError::Finished);
+            };
+
+            if byte == DELIMITER {
+                if code == 0 && error.is_none() {
+                    // Nothing but a delimiter, keep waiting for a frame.
+                    continue;
+                }
+                if remaining != 0 {
+                    error = Some(FrameError::Invalid);
+                }
+                break;
+            }
+
+            if error.is_some() {
+                continue;
+            }
+
+            let decoded = if remaining == 0 {
+                // The previous run, if any, was followed by an encoded zero.
+                let zero = code != 0 && code != MAX_RUN as u8 + 1;
+                code = byte;
+                remaining = byte - 1;
+                zero.then_some(0)
+            } else {
+                remaining -= 1;
+                Some(byte)
+            };
+
+            if let Some(decoded) = decoded {
+                if buf.push(decoded).is_err() {
+                    error = Some(FrameError::TooLong);
+                }
+            }
+        }
+
+        match error {
+            Some(error) => Err(error),
+            None => Ok(buf.len() - start),
+        }
+    }
+
+    /// Returns the consumer that encoded frames are dequeued from.
+    pub fn into_inner(self) -> Consumer<'queue, u8, N> {
+        self.consumer
+    }
+}
+
+/// A frame that was decoded in place, in the queue of a [`FrameReceiver`].
+///
+/// Returned by [`FrameReceiver::receive_view`]. The encoded frame is released from the
+/// queue once this is dropped.
+pub struct FrameView<'receiver, 'queue, const N: usize, C = NoCrc>
+where
+    C: Crc,
+{
+    receiver: &'receiver mut FrameReceiver<'queue, N, C>,
+    /// The length of the frame, without its checksum.
+    len: usize,
+    /// The length of the encoded frame, including its delimiter.
+    encoded: usize,
+}
+
+impl<const N: usize, C> FrameView<'_, '_, N, C>
+where
+    C: Crc,
+{
+    /// Returns the frame, without its checksum.
+    ///
+    /// The frame may wrap around the end of the buffer of the queue, in which case
+    /// the second slice holds the rest of it.
+    pub fn as_slices(&mut self) -> (&[u8], &[u8]) {
+        let (first, second) = self.receiver.consumer.regions();
+        split(first, second, self.len)
+    }
+
+    /// Returns the length of the frame.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the frame is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<const N: usize, C> Drop for FrameView<'_, '_, N, C>
+where
+    C: Crc,
+{
+    fn drop(&mut self) {
+        self.receiver.consumer.release_regions(self.encoded);
+    }
+}
+
+/// Check the checksum at the end of `frame`, which is split into two parts.
+///
+/// Returns the length of the frame without its checksum if it matches.
+fn validate<C: Crc>(crc: &C, frame: (&[u8], &[u8])) -> Option<usize> {
+    let len = (frame.0.len() + frame.1.len()).checked_sub(C::WIDTH)?;
+    let (first, second) = split(frame.0, frame.1, len);
+    let expected = crc.checksum(&[first, second]).to_le_bytes();
+
+    let checksum = frame.0[first.len()..]
+        .iter()
+        .chain(frame.1[second.len()..].iter());
+    checksum.eq(expected[..C::WIDTH].iter()).then_some(len)
+}
+
+/// Returns the first `len` bytes of `first` followed by `second`.
+fn split<'a>(first: &'a [u8], second: &'a [u8], len: usize) -> (&'a [u8], &'a [u8]) {
+    let head = len.min(first.len());
+    (&first[..head], &second[..len - head])
+}
+
+/// Decode the first `end` bytes of `first` followed by `second` in place, which
+/// hold an encoded frame without its delimiter.
+///
+/// Returns the length of the decoded frame, which starts at the beginning of `first`.
+fn decode_in_place(first: &mut [u8], second: &mut [u8], end: usize) -> Result<usize, FrameError> {
+    let wrap = first.len();
+    let byte = |first: &mut [u8], second: &mut [u8], index: usize, set: Option<u8>| {
+        let byte = match index.checked_sub(wrap) {
+            None => &mut first[index],
+            Some(index) => &mut second[index],
+        };
+        if let Some(set) = set {
+            *byte = set;
+        }
+        *byte
+    };
+
+    // Decoded bytes are never ahead of the encoded ones, so nothing
+    // is overwritten before it was read.
+    let mut read = 0;
+    let mut written = 0;
+    while read < end {
+        let code = byte(first, second, read, None) as usize;
+        read += 1;
+        if read + code - 1 > end {
+            return Err(FrameError::Invalid);
+        }
+        for _ in 1..code {
+            let decoded = byte(first, second, read, None);
+            byte(first, second, written, Some(decoded));
+            read += 1;
+            written += 1;
+        }
+        // A run of the maximum length is not followed by an encoded zero.
+        if read < end && code != MAX_RUN + 1 {
+            byte(first, second, written, Some(0));
+            written += 1;
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::{boxed::Box, vec::Vec as StdVec};
+
+    use heapless::Vec;
+
+    use super::{Crc, Crc16Ccitt, FrameError, FrameErrors, FrameReceiver, FrameSender, FrameView};
+    use crate::spsc::{Queue, Split};
+
+    #[tokio::test]
+    async fn cobs_frames() {
+        let queue: &'static mut Queue<u8, 16> = Box::leak(Box::default());
+        let Split {
+            producer,
+            mut consumer,
+        } = queue.split();
+        let mut sender = FrameSender::new(producer);
+
+        let long: &'static [u8; 300] =
+            Box::leak(Box::new(core::array::from_fn(|i| (i % 255) as u8 + 1)));
+        let frames: [&[u8]; 5] = [&[0x11, 0x00, 0x22], &[0x00], long, &long[..254], &[]];
+
+        let sent = tokio::task::spawn(async move {
+            for frame in frames {
+                sender.send(frame).await;
+            }
+            let mut producer = sender.into_inner();
+            // A frame that lost its last bytes, followed by a valid one
+            for byte in [0x05, 0x01, 0x02, 0x00, 0x00, 0x02, 0x33, 0x00] {
+                producer.enqueue(byte).await.unwrap();
+            }
+        });
+
+        // Verify the encoding of the first frame
+        for expected in [0x02, 0x11, 0x02, 0x22, 0x00] {
+            assert_eq!(consumer.dequeue().await, Ok(expected));
+        }
+
+        let mut receiver = FrameReceiver::new(consumer);
+        let mut buf: Vec<u8, 300> = Vec::new();
+        for frame in &frames[1..] {
+            buf.clear();
+            assert_eq!(receiver.receive(&mut buf).await, Ok(frame.len()));
+            assert_eq!(&buf, frame);
+        }
+
+        buf.clear();
+        assert_eq!(receiver.receive(&mut buf).await, Err(FrameError::Invalid));
+        assert!(buf.is_empty());
+        assert_eq!(receiver.receive(&mut buf).await, Ok(1));
+        assert_eq!(buf, [0x33]);
+
+        sent.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn crc_frames() {
+        assert_eq!(Crc16Ccitt.checksum(&[b"1234", b"56789"]), 0x29B1);
+
+        let queue: &'static mut Queue<u8, 16> = Box::leak(Box::default());
+        let Split { producer, consumer } = queue.split();
+
+        let sent = tokio::task::spawn(async move {
+            let mut sender = FrameSender::with_crc(producer, Crc16Ccitt);
+            sender.send(b"first").await;
+
+            // A frame with a checksum that does not match
+            let mut raw = FrameSender::new(sender.into_inner());
+            raw.send(b"lost\xAA\xBB").await;
+
+            let mut sender = FrameSender::with_crc(raw.into_inner(), Crc16Ccitt);
+            sender.send(b"second").await;
+            sender.send(b"third").await;
+            sender.finish().await;
+        });
+
+        let mut receiver = FrameReceiver::with_crc(consumer, Crc16Ccitt);
+        let mut buf: Vec<u8, 16> = Vec::new();
+        assert_eq!(receiver.receive(&mut buf).await, Ok(5));
+        assert_eq!(buf, b"first");
+
+        buf.clear();
+        assert_eq!(receiver.receive(&mut buf).await, Err(FrameError::Corrupted));
+        assert_eq!(receiver.receive(&mut buf).await, Ok(6));
+        assert_eq!(buf, b"second");
+
+        buf.clear();
+        assert_eq!(receiver.receive_valid(&mut buf).await, Ok(5));
+        assert_eq!(buf, b"third");
+        assert_eq!(receiver.receive(&mut buf).await, Err(FrameError::Finished));
+
+        let errors = FrameErrors {
+            corrupted: 1,
+            ..Default::default()
+        };
+        assert_eq!(receiver.errors(), errors);
+        sent.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn frame_views() {
+        let queue: &'static mut Queue<u8, 16> = Box::leak(Box::default());
+        let Split { producer, consumer } = queue.split();
+
+        let sent = tokio::task::spawn(async move {
+            let mut sender = FrameSender::with_crc(producer, Crc16Ccitt);
+            sender.send(b"first").await;
+            // Wraps around the end of the buffer of the queue
+            sender.send(b"\x00second!").await;
+            // Does not fit into the queue
+            sender.send(&[0xAA; 20]).await;
+            sender.send(b"third").await;
+            sender.finish().await;
+        });
+
+        fn contents(mut view: FrameView<16, Crc16Ccitt>) -> StdVec<u8> {
+            let (first, second) = view.as_slices();
+            [first, second].concat()
+        }
+
+        let mut receiver = FrameReceiver::with_crc(consumer, Crc16Ccitt);
+        let view = receiver.receive_view().await.unwrap();
+        assert_eq!(contents(view), b"first");
+        let view = receiver.receive_view().await.unwrap();
+        assert_eq!(view.len(), 8);
+        assert_eq!(contents(view), b"\x00second!");
+
+        assert!(matches!(
+            receiver.receive_view().await,
+            Err(FrameError::TooLong)
+        ));
+        let view = receiver.receive_view().await.unwrap();
+        assert_eq!(contents(view), b"third");
+        assert!(matches!(
+            receiver.receive_view().await,
+            Err(FrameError::Finished)
+        ));
+
+        let errors = FrameErrors {
+            too_long: 1,
+            ..Default::default()
+        };
+        assert_eq!(receiver.errors(), errors);
+        sent.await.unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+//! Waiting for any of several queues at once.
+//!
+//! Selecting over many queues registers a waker with every one of them, on every poll. A
+//! [`ChannelGroup`] instead gives each of its members a waker of its own, which only has
+//! to be registered again once that member was woken. The task itself registers its waker
+//! with the group once, and the group keeps track of which members became ready:
+//!
+//! ```
+//! use heapless_async_queues::{group::ChannelGroup, mpmc::MpMcQueue};
+//!
+//! static COMMANDS: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+//! static EVENTS: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+//! static GROUP: ChannelGroup<2> = ChannelGroup::new();
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! # EVENTS.enqueue(1).await.unwrap();
+//! match GROUP.ready([&mut &COMMANDS, &mut &EVENTS]).await {
+//!     0 => { /* handle a command */ }
+//!     _ => assert_eq!(EVENTS.dequeue().await, 1),
+//! }
+//! # });
+//! ```
+
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+use crate::{log::*, wake_lock::WakeLock, waker::WakerRegistration};
+
+/// A queue that can be a member of a [`ChannelGroup`].
+pub trait GroupMember {
+    /// Resolve if an item can be dequeued, or if the stream is finished. Otherwise,
+    /// register the waker of `cx` to be woken once that is the case.
+    ///
+    /// With several consumers, another one may dequeue the item before this one does.
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()>;
+}
+
+/// A group of up to `K` queues, which a task can wait on with a single waker.
+///
+/// `K` can be at most 32. Only one task should wait on a group, and it should always
+/// pass the same members in the same order.
+#[repr(C)]
+pub struct ChannelGroup<const K: usize> {
+    /// The waker of member `i` points to `indices[i]`, which holds `i`. This must
+    /// be the first field, so the group can be found from that pointer.
+    indices: [u8; K],
+    waker: WakeLock<WakerRegistration>,
+    /// The members that were woken since they were last polled.
+    ready: AtomicU32,
+    /// The members that have registered the waker of the group.
+    armed: AtomicU32,
+    /// The member that is polled first, to take turns between ready members.
+    next: AtomicUsize,
+}
+
+impl<const K: usize> ChannelGroup<K> {
+    /// Checked at compile time, when the group is created.
+    const VALID_SIZE: () = assert!(K > 0 && K <= 32, "A group holds 1 to 32 members");
+
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        Self::clone_member,
+        Self::wake_member,
+        Self::wake_member,
+        Self::drop_member,
+    );
+
+    /// Create a new group.
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_SIZE;
+
+        let mut indices = [0; K];
+        let mut i = 0;
+        while i < K {
+            indices[i] = i as u8;
+            i += 1;
+        }
+
+        Self {
+            indices,
+            waker: WakeLock::new(WakerRegistration::new()),
+            ready: AtomicU32::new(0),
+            armed: AtomicU32::new(0),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wait until any of `members` is ready, and resolve to its index.
+    ///
+    /// Members that have not been woken since they last registered their
+    /// waker are not polled again.
+    pub async fn ready(&'static self, mut members: [&mut dyn GroupMember; K]) -> usize {
+        poll_fn(|cx| {
+            match self.waker.try_lock() {
+                Some(mut wk) => {
+                    wk.register(cx.waker());
+                }
+                None => cx.waker().wake_by_ref(),
+            }
+
+            let start = self.next.load(Ordering::Relaxed);
+            for offset in 0..K {
+                let index = (start + offset) % K;
+                let bit = 1 << index;
+
+                let woken = self.ready.fetch_and(!bit, Ordering::AcqRel) & bit != 0;
+                if !woken && self.armed.load(Ordering::Relaxed) & bit != 0 {
+                    continue;
+                }
+
+                let waker = self.member_waker(index);
+                if members[index]
+                    .poll_ready(&mut Context::from_waker(&waker))
+                    .is_ready()
+                {
+                    trace!("Group member {} is ready", index);
+                    self.armed.fetch_and(!bit, Ordering::Relaxed);
+                    self.next.store((index + 1) % K, Ordering::Relaxed);
+                    return Poll::Ready(index);
+                }
+                self.armed.fetch_or(bit, Ordering::Relaxed);
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    fn member_waker(&'static self, index: usize) -> Waker {
+        let data = (&self.indices[index] as *const u8).cast();
+        // SAFETY: the vtable upholds the `RawWaker` contract for pointers into a
+        // `'static` group.
+        unsafe { Waker::from_raw(RawWaker::new(data, &Self::VTABLE)) }
+    }
+
+    unsafe fn clone_member(data: *const ()) -> RawWaker {
+        RawWaker::new(data, &Self::VTABLE)
+    }
+
+    unsafe fn wake_member(data: *const ()) {
+        let index = *data.cast::<u8>();
+        // SAFETY: `data` points to `indices[index]` of a `'static` group, whose
+        // `indices` are its first field.
+        let group = &*data.cast::<u8>().sub(index as usize).cast::<Self>();
+        group.ready.fetch_or(1 << index, Ordering::AcqRel);
+        group.waker.wake_or_defer(WakerRegistration::wake);
+    }
+
+    unsafe fn drop_member(_: *const ()) {}
+}
+
+impl<const K: usize> Default for ChannelGroup<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::time::Duration;
+
+    use super::ChannelGroup;
+    use crate::mpmc::MpMcQueue;
+
+    #[tokio::test]
+    async fn ready_member() {
+        static A: MpMcQueue<u32, 1, 4> = MpMcQueue::new();
+        static B: MpMcQueue<u32, 1, 4> = MpMcQueue::new();
+        static C: MpMcQueue<u32, 1, 4> = MpMcQueue::new();
+        static GROUP: ChannelGroup<3> = ChannelGroup::new();
+
+        let producer = tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            B.enqueue(1).await.unwrap();
+            C.enqueue(2).await.unwrap();
+        });
+
+        assert_eq!(GROUP.ready([&mut &A, &mut &B, &mut &C]).await, 1);
+        assert_eq!(B.dequeue().await, 1);
+        producer.await.unwrap();
+
+        assert_eq!(GROUP.ready([&mut &A, &mut &B, &mut &C]).await, 2);
+        assert_eq!(C.dequeue().await, 2);
+    }
+}
@@ -0,0 +1,149 @@
+//! Hooks for tracing what a queue does.
+//!
+//! A queue that was built with an [`Instrument`] calls it for every item that it
+//! enqueues, dequeues or drops, and whenever it wakes a waiting side. This is meant
+//! for feeding tracing tools, e.g. over RTT, so the hooks should return quickly:
+//!
+//! ```
+//! use core::sync::atomic::{AtomicUsize, Ordering};
+//! use heapless_async_queues::{builder::QueueBuilder, instrument::Instrument, mpmc::MpMcQueue};
+//!
+//! struct Tracer(AtomicUsize);
+//!
+//! impl Instrument for Tracer {
+//!     fn enqueued(&self, amount: usize) {
+//!         self.0.fetch_add(amount, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! static TRACER: Tracer = Tracer(AtomicUsize::new(0));
+//! static Q: MpMcQueue<u32, 2, 8> = QueueBuilder::new().instrument(&TRACER).build_mpmc();
+//! ```
+
+/// The side of a queue that is woken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The producer, or the enqueuers of an [`MpMcQueue`](crate::mpmc::MpMcQueue).
+    Enqueuers,
+    /// The consumer, or the dequeuers of an [`MpMcQueue`](crate::mpmc::MpMcQueue).
+    Dequeuers,
+}
+
+/// Callbacks on the events of a queue.
+///
+/// All of them do nothing by default. They may be called from any context that
+/// uses the queue, including interrupts.
+pub trait Instrument: Sync {
+    /// `amount` items were enqueued.
+    fn enqueued(&self, amount: usize) {
+        let _ = amount;
+    }
+
+    /// `amount` items were dequeued.
+    fn dequeued(&self, amount: usize) {
+        let _ = amount;
+    }
+
+    /// `amount` items were dropped due to the
+    /// [`OverflowPolicy`](crate::builder::OverflowPolicy) of the queue.
+    fn dropped(&self, amount: usize) {
+        let _ = amount;
+    }
+
+    /// The queue woke `side`, or deferred waking it to whoever is holding its waker.
+    fn woke(&self, side: Side) {
+        let _ = side;
+    }
+}
+
+/// The [`Instrument`] of a queue, if it has one.
+#[derive(Clone, Copy)]
+pub(crate) struct Hook(Option<&'static dyn Instrument>);
+
+impl Hook {
+    pub const NONE: Self = Self(None);
+
+    pub const fn new(instrument: &'static dyn Instrument) -> Self {
+        Self(Some(instrument))
+    }
+
+    pub fn get(&self) -> Option<&'static dyn Instrument> {
+        self.0
+    }
+}
+
+impl core::fmt::Debug for Hook {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(if self.0.is_some() { "Some(..)" } else { "None" })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::{Instrument, Side};
+    use crate::{
+        builder::{OverflowPolicy, QueueBuilder},
+        spsc::{Queue, Split},
+    };
+
+    struct Events {
+        enqueued: AtomicUsize,
+        dequeued: AtomicUsize,
+        dropped: AtomicUsize,
+        producer_wakes: AtomicUsize,
+        consumer_wakes: AtomicUsize,
+    }
+
+    impl Instrument for Events {
+        fn enqueued(&self, amount: usize) {
+            self.enqueued.fetch_add(amount, Ordering::Relaxed);
+        }
+
+        fn dequeued(&self, amount: usize) {
+            self.dequeued.fetch_add(amount, Ordering::Relaxed);
+        }
+
+        fn dropped(&self, amount: usize) {
+            self.dropped.fetch_add(amount, Ordering::Relaxed);
+        }
+
+        fn woke(&self, side: Side) {
+            match side {
+                Side::Enqueuers => self.producer_wakes.fetch_add(1, Ordering::Relaxed),
+                Side::Dequeuers => self.consumer_wakes.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    #[tokio::test]
+    async fn events() {
+        static EVENTS: Events = Events {
+            enqueued: AtomicUsize::new(0),
+            dequeued: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            producer_wakes: AtomicUsize::new(0),
+            consumer_wakes: AtomicUsize::new(0),
+        };
+
+        let mut queue: Queue<u32, 2> = QueueBuilder::new()
+            .overflow(OverflowPolicy::DropOldest)
+            .instrument(&EVENTS)
+            .build_spsc();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        tx.enqueue_iter(0..3).await;
+        assert_eq!(rx.dequeue().await, Ok(1));
+
+        assert_eq!(EVENTS.enqueued.load(Ordering::Relaxed), 3);
+        assert_eq!(EVENTS.dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(EVENTS.dequeued.load(Ordering::Relaxed), 1);
+        assert_eq!(EVENTS.producer_wakes.load(Ordering::Relaxed), 1);
+        // The items were enqueued without waiting, so the consumer was woken once
+        assert_eq!(EVENTS.consumer_wakes.load(Ordering::Relaxed), 1);
+    }
+}
@@ -2,10 +2,43 @@
 #![no_std]
 #![deny(missing_docs)]
 
+mod channel;
 mod mutex;
+mod wake_lock;
 mod waker;
+mod waker_set;
 
 pub(crate) mod log;
 
+pub mod arena;
+pub mod broadcast;
+pub mod builder;
+pub mod bus;
+pub mod debounce;
+pub mod descriptor;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+pub mod double_buffer;
+pub mod edf;
+pub mod exchanger;
+#[cfg(feature = "framing")]
+pub mod framing;
+pub mod group;
+pub mod instrument;
+pub mod metrics;
 pub mod mpmc;
+pub mod oneshot;
+pub mod pipeline;
+pub mod retry;
+pub mod scheduler;
 pub mod spsc;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod time;
+pub mod timestamped;
+pub mod triple_buffer;
+pub mod watchdog;
+
+/// The version of [`heapless`] that this crate is built against.
+#[cfg(feature = "reexport-heapless")]
+pub use heapless;
@@ -1,17 +1,19 @@
 pub use self::log::*;
 
 #[cfg(feature = "log-defmt")]
+#[allow(clippy::module_inception)]
 mod log {
-    pub use defmt::{debug, error, info, trace, warn};
+    pub use defmt::{debug, error, info, trace};
 }
 
 #[cfg(feature = "log-log")]
+#[allow(clippy::module_inception)]
 mod log {
-    pub use log::{debug, error, info, trace, warn};
+    pub use log::{debug, error, info, trace};
 }
 
 #[cfg(not(any(feature = "log-defmt", feature = "log-log")))]
-#[allow(missing_docs)]
+#[allow(missing_docs, clippy::module_inception)]
 mod log {
 
     #[macro_export]
@@ -32,5 +34,12 @@ mod log {
         ($($in:tt),*) => {};
     }
 
-    pub use {debug, info, trace};
+    #[macro_export]
+    macro_rules! error {
+        ($in:tt) => {};
+        ($($in:tt),*) => {};
+    }
+
+    #[allow(unused_imports)]
+    pub use {debug, error, info, trace};
 }
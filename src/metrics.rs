@@ -0,0 +1,313 @@
+//! Queue metrics.
+//!
+//! Metrics are only collected for queues that were built with
+//! [`QueueBuilder::metrics`](crate::builder::QueueBuilder::metrics) enabled.
+//!
+//! To get the metrics off the device, a [`MetricsReporter`] periodically reports the
+//! metrics of a set of queues to a [`MetricsSink`], e.g. a closure, the [`LogSink`], or
+//! the producer of a telemetry queue:
+//!
+//! ```
+//! use heapless_async_queues::{
+//!     builder::QueueBuilder,
+//!     metrics::{LogSink, MetricsReporter},
+//!     mpmc::MpMcQueue,
+//!     time::Clock,
+//! };
+//!
+//! static CLOCK: Clock = Clock::new();
+//! static COMMANDS: MpMcQueue<u32, 2, 4> = QueueBuilder::new().metrics(true).build_mpmc();
+//! static EVENTS: MpMcQueue<u32, 2, 4> = QueueBuilder::new().metrics(true).build_mpmc();
+//!
+//! async fn telemetry() {
+//!     let reporter = MetricsReporter::new([
+//!         ("commands", COMMANDS.metrics_source()),
+//!         ("events", EVENTS.metrics_source()),
+//!     ]);
+//!     reporter.report_every(&CLOCK, 1000, LogSink).await
+//! }
+//! ```
+
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+};
+
+use crate::{
+    instrument::{Hook, Side},
+    log::*,
+    spsc::{Producer, Storage},
+    time::{Clock, Instant},
+};
+
+/// A snapshot of the metrics of a queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Metrics {
+    /// The amount of items that were enqueued.
+    pub enqueued: usize,
+    /// The amount of items that were dequeued.
+    pub dequeued: usize,
+    /// The amount of items that were dropped due to the
+    /// [`OverflowPolicy`](crate::builder::OverflowPolicy) of the queue.
+    pub dropped: usize,
+}
+
+pub(crate) struct Counters {
+    enabled: bool,
+    hook: Hook,
+    enqueued: AtomicUsize,
+    dequeued: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+impl Counters {
+    pub const fn new(enabled: bool, hook: Hook) -> Self {
+        Self {
+            enabled,
+            hook,
+            enqueued: AtomicUsize::new(0),
+            dequeued: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    fn count(&self, counter: &AtomicUsize, amount: usize) {
+        if self.enabled {
+            counter.fetch_add(amount, Ordering::Relaxed);
+        }
+    }
+
+    pub fn enqueued(&self) {
+        self.enqueued_many(1)
+    }
+
+    pub fn enqueued_many(&self, amount: usize) {
+        if let Some(instrument) = self.hook.get() {
+            instrument.enqueued(amount);
+        }
+        self.count(&self.enqueued, amount)
+    }
+
+    pub fn dequeued(&self) {
+        self.dequeued_many(1)
+    }
+
+    pub fn dequeued_many(&self, amount: usize) {
+        if let Some(instrument) = self.hook.get() {
+            instrument.dequeued(amount);
+        }
+        self.count(&self.dequeued, amount)
+    }
+
+    pub fn dropped(&self) {
+        self.dropped_many(1)
+    }
+
+    pub fn dropped_many(&self, amount: usize) {
+        if let Some(instrument) = self.hook.get() {
+            instrument.dropped(amount);
+        }
+        self.count(&self.dropped, amount)
+    }
+
+    /// Report a wake of `side` to the instrument, as wakes are not counted.
+    pub fn woke(&self, side: Side) {
+        if let Some(instrument) = self.hook.get() {
+            instrument.woke(side);
+        }
+    }
+
+    pub fn snapshot(&self) -> Option<Metrics> {
+        self.enabled.then(|| Metrics {
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            dequeued: self.dequeued.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Reads the [`Metrics`] of a queue, independently of its handles.
+#[derive(Clone, Copy)]
+pub struct MetricsSource<'queue> {
+    counters: &'queue Counters,
+}
+
+impl<'queue> MetricsSource<'queue> {
+    pub(crate) fn new(counters: &'queue Counters) -> Self {
+        Self { counters }
+    }
+
+    /// Returns the [`Metrics`] of the queue, if it was built with metrics enabled.
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.counters.snapshot()
+    }
+}
+
+/// The [`Metrics`] of a single queue, as passed to a [`MetricsSink`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Report {
+    /// The name of the queue, as given to the [`MetricsReporter`].
+    pub name: &'static str,
+    /// The metrics of the queue.
+    pub metrics: Metrics,
+}
+
+/// Where a [`MetricsReporter`] sends its reports.
+pub trait MetricsSink {
+    /// Handle the report of a single queue.
+    fn report(&mut self, report: Report);
+}
+
+impl<F> MetricsSink for F
+where
+    F: FnMut(Report),
+{
+    fn report(&mut self, report: Report) {
+        self(report)
+    }
+}
+
+/// Enqueues every report into a telemetry queue, without waiting. A report
+/// that does not fit is dropped.
+impl<const N: usize, B> MetricsSink for Producer<'_, Report, N, B>
+where
+    B: Storage<Report, N>,
+{
+    fn report(&mut self, report: Report) {
+        if self.enqueue_or_defer(report).is_err() {
+            trace!("Dropping metrics report, the telemetry queue is full");
+        }
+    }
+}
+
+/// Logs every report with the logging backend of the crate, as selected by the
+/// `log-defmt` or `log-log` feature.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+impl MetricsSink for LogSink {
+    #[cfg_attr(
+        not(any(feature = "log-defmt", feature = "log-log")),
+        allow(unused_variables)
+    )]
+    fn report(&mut self, report: Report) {
+        let Report { name, metrics } = report;
+        let Metrics {
+            enqueued,
+            dequeued,
+            dropped,
+        } = metrics;
+        info!(
+            "{}: {} enqueued, {} dequeued, {} dropped",
+            name, enqueued, dequeued, dropped
+        );
+    }
+}
+
+/// Reports the [`Metrics`] of `K` queues to a [`MetricsSink`].
+///
+/// Queues that were built without metrics are skipped.
+pub struct MetricsReporter<'queue, const K: usize> {
+    sources: [(&'static str, MetricsSource<'queue>); K],
+}
+
+impl<'queue, const K: usize> MetricsReporter<'queue, K> {
+    /// Create a new [`MetricsReporter`] for the named `sources`.
+    pub const fn new(sources: [(&'static str, MetricsSource<'queue>); K]) -> Self {
+        Self { sources }
+    }
+
+    /// Report the current metrics of every queue to `sink`, once.
+    pub fn report(&self, sink: &mut impl MetricsSink) {
+        for &(name, source) in &self.sources {
+            if let Some(metrics) = source.metrics() {
+                sink.report(Report { name, metrics });
+            }
+        }
+    }
+
+    /// Report the metrics of every queue to `sink` every `ticks` ticks of `clock`.
+    ///
+    /// The returned future never resolves, so it is meant to be run as a task of
+    /// its own, or selected with whatever ends the reporting.
+    pub async fn report_every<const W: usize>(
+        &self,
+        clock: &Clock<W>,
+        ticks: u32,
+        mut sink: impl MetricsSink,
+    ) -> ! {
+        let mut next = clock.now();
+        loop {
+            // Deadlines are relative to the previous one, so reporting
+            // late does not make the reports drift.
+            next = Instant::from_ticks(next.ticks().wrapping_add(ticks));
+            let deadline = clock.at(next);
+            poll_fn(|cx| {
+                if deadline.poll_passed(cx.waker()) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+            self.report(&mut sink);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::boxed::Box;
+
+    use super::{Metrics, MetricsReporter, Report};
+    use crate::{
+        builder::QueueBuilder,
+        mpmc::MpMcQueue,
+        spsc::{Queue, Split},
+        time::Clock,
+    };
+
+    #[tokio::test]
+    async fn report_every() {
+        static CLOCK: Clock = Clock::new();
+        static JOBS: MpMcQueue<u32, 2, 4> = QueueBuilder::new().metrics(true).build_mpmc();
+        static UNMEASURED: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+        let telemetry: &'static mut Queue<Report, 4> = Box::leak(Box::default());
+        let Split {
+            producer,
+            consumer: mut reports,
+        } = telemetry.split();
+
+        let reporter = tokio::spawn(async move {
+            let reporter = MetricsReporter::new([
+                ("jobs", JOBS.metrics_source()),
+                ("unmeasured", UNMEASURED.metrics_source()),
+            ]);
+            reporter.report_every(&CLOCK, 2, producer).await
+        });
+
+        JOBS.enqueue(1).await.unwrap();
+        // Only the queue with metrics is reported, once every two ticks
+        for enqueued in 1..3 {
+            for _ in 0..2 {
+                tokio::task::yield_now().await;
+                CLOCK.tick();
+            }
+            let report = Report {
+                name: "jobs",
+                metrics: Metrics {
+                    enqueued,
+                    ..Default::default()
+                },
+            };
+            assert_eq!(reports.dequeue().await, Ok(report));
+            JOBS.enqueue(1).await.unwrap();
+        }
+        assert!(reports.is_empty());
+        reporter.abort();
+    }
+}
@@ -1,15 +1,28 @@
 use core::{future::Future, task::Poll};
 
-use crate::trace;
+#[cfg(feature = "futures")]
+use futures_core::future::FusedFuture;
+
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::WaiterName;
+use crate::{
+    log::*,
+    waker::{Name, NO_NAME},
+    waker_set::Registration,
+};
 
 use super::MpMcQueue;
 
+#[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct DequeueFuture<'queue, T, const W: usize, const N: usize>
 where
     T: Unpin,
 {
     inner: &'queue MpMcQueue<T, W, N>,
-    dequeued_value: Option<T>,
+    name: Name,
+    /// Where the waker of this future was registered.
+    registration: Option<Registration>,
+    terminated: bool,
 }
 
 impl<'queue, T, const W: usize, const N: usize> DequeueFuture<'queue, T, W, N>
@@ -19,9 +32,22 @@ where
     pub const fn new(queue: &'queue MpMcQueue<T, W, N>) -> Self {
         Self {
             inner: queue,
-            dequeued_value: None,
+            name: NO_NAME,
+            registration: None,
+            terminated: false,
         }
     }
+
+    pub(crate) fn with_name(mut self, name: Name) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Set the name that is reported for this future while it waits.
+    #[cfg(feature = "diagnostics")]
+    pub fn named(self, name: impl Into<WaiterName>) -> Self {
+        self.with_name(Some(name.into()))
+    }
 }
 
 impl<T, const W: usize, const N: usize> Future for DequeueFuture<'_, T, W, N>
@@ -33,37 +59,34 @@ where
     fn poll(
         self: core::pin::Pin<&mut Self>,
         cx: &mut core::task::Context<'_>,
-    ) -> core::task::Poll<Self::Output> {
-        let try_wake_producer = |me: &mut Self, value| {
-            if me.inner.try_wake_enqueuers() {
-                return Poll::Ready(value);
-            } else {
-                me.dequeued_value = Some(value);
-                cx.waker().wake_by_ref();
-                return Poll::Pending;
-            }
-        };
-
+    ) -> Poll<Self::Output> {
         trace!("Poll consumer");
         let me = self.get_mut();
-        let con = &mut me.inner;
 
-        if let Some(value) = me.dequeued_value.take() {
-            // Try to wake the producer because we managed to
-            // dequeue a value
-            return try_wake_producer(me, value);
-        }
+        let res = me.inner.poll_pop(cx, me.name, &mut me.registration);
+        me.terminated = res.is_ready();
+        res
+    }
+}
 
-        me.dequeued_value = con.inner.dequeue();
-        if let Some(value) = me.dequeued_value.take() {
-            // Try to wake the producer because we managed to
-            // dequeue a value
-            try_wake_producer(me, value)
-        } else {
-            if !me.inner.register_dequeuer_waker(cx.waker()) {
-                cx.waker().wake_by_ref()
-            }
-            Poll::Pending
+impl<T, const W: usize, const N: usize> Drop for DequeueFuture<'_, T, W, N>
+where
+    T: Unpin,
+{
+    fn drop(&mut self) {
+        if let Some(registration) = self.registration {
+            self.inner
+                .unregister_dequeuer_waker(registration, self.terminated);
         }
     }
 }
+
+#[cfg(feature = "futures")]
+impl<T, const W: usize, const N: usize> FusedFuture for DequeueFuture<'_, T, W, N>
+where
+    T: Unpin,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
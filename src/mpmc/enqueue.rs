@@ -1,13 +1,27 @@
 use core::{future::Future, task::Poll};
 
+#[cfg(feature = "futures")]
+use futures_core::future::FusedFuture;
+
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::WaiterName;
+use crate::{
+    waker::{Name, NO_NAME},
+    waker_set::Registration,
+};
+
 use super::MpMcQueue;
 
+#[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct EnqueueFuture<'queue, T, const W: usize, const N: usize>
 where
     T: Unpin,
 {
     inner: &'queue MpMcQueue<T, W, N>,
     value_to_enqueue: Option<T>,
+    name: Name,
+    /// Where the waker of this future was registered.
+    registration: Option<Registration>,
 }
 
 impl<'queue, T, const W: usize, const N: usize> EnqueueFuture<'queue, T, W, N>
@@ -18,47 +32,58 @@ where
         Self {
             inner: queue,
             value_to_enqueue: Some(value),
+            name: NO_NAME,
+            registration: None,
         }
     }
+
+    pub(crate) fn with_name(mut self, name: Name) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Set the name that is reported for this future while it waits.
+    #[cfg(feature = "diagnostics")]
+    pub fn named(self, name: impl Into<WaiterName>) -> Self {
+        self.with_name(Some(name.into()))
+    }
 }
 
 impl<T, const W: usize, const N: usize> Future for EnqueueFuture<'_, T, W, N>
 where
     T: Unpin,
 {
-    type Output = ();
+    type Output = Result<(), T>;
 
     fn poll(
         self: core::pin::Pin<&mut Self>,
         cx: &mut core::task::Context<'_>,
     ) -> Poll<Self::Output> {
-        let try_wake_dequeuers = |me: &mut Self| {
-            if me.inner.try_wake_dequeuers() {
-                Poll::Ready(())
-            } else {
-                cx.waker().wake_by_ref();
-                Poll::Pending
-            }
-        };
-
         let me = self.get_mut();
+        me.inner
+            .poll_push(cx, &mut me.value_to_enqueue, me.name, &mut me.registration)
+    }
+}
 
-        let value = if let Some(value) = me.value_to_enqueue.take() {
-            value
-        } else {
-            return try_wake_dequeuers(me);
-        };
-
-        let failed_to_enqueue_value = if let Err(value) = me.inner.inner.enqueue(value) {
-            value
-        } else {
-            return try_wake_dequeuers(me);
-        };
-
-        me.value_to_enqueue = Some(failed_to_enqueue_value);
-        if !me.inner.register_enqueuer_waker(cx.waker()) {
-            cx.waker().wake_by_ref();
+impl<T, const W: usize, const N: usize> Drop for EnqueueFuture<'_, T, W, N>
+where
+    T: Unpin,
+{
+    fn drop(&mut self) {
+        if let Some(registration) = self.registration {
+            let done = self.value_to_enqueue.is_none();
+            self.inner.unregister_enqueuer_waker(registration, done);
         }
-        Poll::Pending
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, const W: usize, const N: usize> FusedFuture for EnqueueFuture<'_, T, W, N>
+where
+    T: Unpin,
+{
+    fn is_terminated(&self) -> bool {
+        // The value is only taken for good once it was enqueued
+        self.value_to_enqueue.is_none()
     }
 }
@@ -0,0 +1,382 @@
+use core::{
+    future::Future,
+    sync::atomic::Ordering,
+    task::{Context, Poll},
+};
+
+use heapless::Vec;
+
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::WaiterName;
+use crate::{
+    group::GroupMember,
+    waker::{Name, NO_NAME},
+};
+
+use super::{EnqueueFuture, MpMcQueue};
+
+/// A handle for enqueueing into an [`MpMcQueue`], created with [`MpMcQueue::sender`].
+pub struct Sender<'queue, T, const W: usize, const N: usize>
+where
+    T: Unpin,
+{
+    queue: &'queue MpMcQueue<T, W, N>,
+    name: Name,
+}
+
+impl<'queue, T, const W: usize, const N: usize> Sender<'queue, T, W, N>
+where
+    T: Unpin,
+{
+    pub(crate) fn new(queue: &'queue MpMcQueue<T, W, N>) -> Self {
+        queue.senders.fetch_add(1, Ordering::AcqRel);
+        Self {
+            queue,
+            name: NO_NAME,
+        }
+    }
+
+    /// Set the name that is reported for this sender while it waits.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_name(&mut self, name: impl Into<WaiterName>) {
+        self.name = Some(name.into());
+    }
+
+    /// Enqueue `value` into the queue.
+    ///
+    /// The returned future resolves once the value was enqueued.
+    #[must_use = "the value is not enqueued unless the returned future is awaited"]
+    pub fn enqueue(&self, value: T) -> EnqueueFuture<'queue, T, W, N> {
+        self.queue.enqueue(value).with_name(self.name)
+    }
+
+    /// Create a [`WeakSender`], which does not count as a sender.
+    pub fn downgrade(&self) -> WeakSender<'queue, T, W, N> {
+        WeakSender { queue: self.queue }
+    }
+}
+
+impl<T, const W: usize, const N: usize> Clone for Sender<'_, T, W, N>
+where
+    T: Unpin,
+{
+    fn clone(&self) -> Self {
+        let mut sender = Self::new(self.queue);
+        sender.name = self.name;
+        sender
+    }
+}
+
+impl<T, const W: usize, const N: usize> Drop for Sender<'_, T, W, N>
+where
+    T: Unpin,
+{
+    fn drop(&mut self) {
+        if self.queue.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // The queue may be drained now
+            self.queue.wake_occupancy_waiters();
+        }
+    }
+}
+
+/// A reference to an [`MpMcQueue`] that can be upgraded to a [`Sender`], as long as
+/// another sender exists. Created with [`Sender::downgrade`].
+pub struct WeakSender<'queue, T, const W: usize, const N: usize>
+where
+    T: Unpin,
+{
+    queue: &'queue MpMcQueue<T, W, N>,
+}
+
+impl<'queue, T, const W: usize, const N: usize> WeakSender<'queue, T, W, N>
+where
+    T: Unpin,
+{
+    /// Create a [`Sender`], if any other sender still exists.
+    pub fn upgrade(&self) -> Option<Sender<'queue, T, W, N>> {
+        self.queue
+            .senders
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |senders| {
+                (senders > 0).then_some(senders + 1)
+            })
+            .ok()
+            .map(|_| Sender {
+                queue: self.queue,
+                name: NO_NAME,
+            })
+    }
+}
+
+impl<T, const W: usize, const N: usize> Clone for WeakSender<'_, T, W, N>
+where
+    T: Unpin,
+{
+    fn clone(&self) -> Self {
+        Self { queue: self.queue }
+    }
+}
+
+/// A handle for dequeueing from an [`MpMcQueue`], created with [`MpMcQueue::receiver`]
+/// or [`MpMcQueue::receiver_with_prefetch`].
+///
+/// Whenever it dequeues an item from the queue, it also takes up to `P` of the items that
+/// follow it, and keeps them in a local buffer. The next dequeues take items from that
+/// buffer, without touching the queue, until it is empty. This lets a receiver that is
+/// faster than the others take items in bursts, but items in the buffer can not be
+/// dequeued by other receivers.
+///
+/// Items that are still buffered when the receiver is dropped are enqueued again, like
+/// new items. They end up behind the items that are in the queue by then, so other
+/// receivers dequeue them out of order, and the overflow policy of the queue applies to
+/// them: if the queue was refilled in the meantime, the items that do not fit are
+/// dropped, even if it waits for room otherwise. Take them with
+/// [`Receiver::take_buffered`] before dropping the receiver to keep them.
+pub struct Receiver<'queue, T, const W: usize, const N: usize, const P: usize = 0>
+where
+    T: Unpin,
+{
+    queue: &'queue MpMcQueue<T, W, N>,
+    /// The prefetched items, in reverse order.
+    buffer: Vec<T, P>,
+    name: Name,
+}
+
+impl<'queue, T, const W: usize, const N: usize, const P: usize> Receiver<'queue, T, W, N, P>
+where
+    T: Unpin,
+{
+    pub(crate) fn new(queue: &'queue MpMcQueue<T, W, N>) -> Self {
+        queue.receivers.fetch_add(1, Ordering::AcqRel);
+        Self {
+            queue,
+            buffer: Vec::new(),
+            name: NO_NAME,
+        }
+    }
+
+    /// Set the name that is reported for this receiver while it waits.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_name(&mut self, name: impl Into<WaiterName>) {
+        self.name = Some(name.into());
+    }
+
+    /// Dequeue the next item.
+    ///
+    /// The returned future resolves immediately if an item is buffered, and once
+    /// an item was dequeued from the queue otherwise.
+    pub async fn dequeue(&mut self) -> T {
+        if let Some(value) = self.buffer.pop() {
+            return value;
+        }
+
+        let value = self.queue.dequeue().with_name(self.name).await;
+        self.prefetch();
+        value
+    }
+
+    /// Run `f` for every dequeued item.
+    ///
+    /// The next item is only dequeued once the future returned by `f` for the previous
+    /// item has completed. The queue can not be closed, so the returned future never
+    /// resolves. If it is dropped while waiting for an item, no item is lost. If it is
+    /// dropped while a future returned by `f` is running, the item that it was processing
+    /// is dropped with it.
+    pub async fn for_each<F, Fut>(&mut self, mut f: F)
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut budget = self.queue.core.budget();
+        loop {
+            let item = budget.spend(self.dequeue()).await;
+            f(item).await;
+        }
+    }
+
+    /// Create an adapter that dequeues items in batches of up to `K` items.
+    ///
+    /// Every [`ReadyChunks::next`] waits for an item, and then also takes every item that
+    /// is immediately available after it, until the batch is full.
+    pub fn ready_chunks<'me, const K: usize>(
+        &'me mut self,
+    ) -> ReadyChunks<'me, 'queue, T, W, N, P, K> {
+        ReadyChunks::new(self)
+    }
+
+    /// Returns the amount of items in the local buffer.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Take the items in the local buffer, in the order they were dequeued in.
+    ///
+    /// They are not enqueued again when this receiver is dropped.
+    pub fn take_buffered(&mut self) -> Vec<T, P> {
+        let mut buffer = core::mem::take(&mut self.buffer);
+        buffer.reverse();
+        buffer
+    }
+
+    /// Wait for the queue to be drained, like [`MpMcQueue::drained`].
+    ///
+    /// Items in the local buffers of receivers count as dequeued.
+    pub async fn drained(&self) {
+        self.queue.drained().await
+    }
+
+    /// Create a [`WeakReceiver`], which does not count as a receiver.
+    pub fn downgrade(&self) -> WeakReceiver<'queue, T, W, N, P> {
+        WeakReceiver { queue: self.queue }
+    }
+
+    /// Fill the local buffer with the items that are in the queue.
+    fn prefetch(&mut self) {
+        while !self.buffer.is_full() {
+            let Some(value) = self.queue.pop() else {
+                break;
+            };
+            // The buffer is not full
+            let _ = self.buffer.push(value);
+        }
+
+        if !self.buffer.is_empty() {
+            self.buffer.reverse();
+            self.queue.wake_enqueuers();
+        }
+    }
+}
+
+/// A reference to an [`MpMcQueue`] that can be upgraded to a [`Receiver`], as long as
+/// another receiver exists. Created with [`Receiver::downgrade`].
+pub struct WeakReceiver<'queue, T, const W: usize, const N: usize, const P: usize = 0>
+where
+    T: Unpin,
+{
+    queue: &'queue MpMcQueue<T, W, N>,
+}
+
+impl<'queue, T, const W: usize, const N: usize, const P: usize> WeakReceiver<'queue, T, W, N, P>
+where
+    T: Unpin,
+{
+    /// Create a [`Receiver`], if any other receiver still exists.
+    pub fn upgrade(&self) -> Option<Receiver<'queue, T, W, N, P>> {
+        self.queue
+            .receivers
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |receivers| {
+                (receivers > 0).then_some(receivers + 1)
+            })
+            .ok()
+            .map(|_| Receiver {
+                queue: self.queue,
+                buffer: Vec::new(),
+                name: NO_NAME,
+            })
+    }
+}
+
+impl<T, const W: usize, const N: usize, const P: usize> Clone for WeakReceiver<'_, T, W, N, P>
+where
+    T: Unpin,
+{
+    fn clone(&self) -> Self {
+        Self { queue: self.queue }
+    }
+}
+
+/// The adapter returned by [`Receiver::ready_chunks`].
+pub struct ReadyChunks<
+    'receiver,
+    'queue,
+    T,
+    const W: usize,
+    const N: usize,
+    const P: usize,
+    const K: usize,
+> where
+    T: Unpin,
+{
+    receiver: &'receiver mut Receiver<'queue, T, W, N, P>,
+}
+
+impl<'receiver, 'queue, T, const W: usize, const N: usize, const P: usize, const K: usize>
+    ReadyChunks<'receiver, 'queue, T, W, N, P, K>
+where
+    T: Unpin,
+{
+    /// Checked at compile time, when the adapter is created.
+    const VALID_SIZE: () = assert!(K > 0, "A batch must hold at least one item");
+
+    fn new(receiver: &'receiver mut Receiver<'queue, T, W, N, P>) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_SIZE;
+
+        Self { receiver }
+    }
+
+    /// Wait for the next batch of items.
+    ///
+    /// Buffered items are taken before the items in the queue.
+    pub async fn next(&mut self) -> Vec<T, K> {
+        let receiver = &mut *self.receiver;
+        let first = receiver.dequeue().await;
+
+        let mut batch = Vec::new();
+        // The batch holds at least one item
+        let _ = batch.push(first);
+        while let Some(item) = receiver.buffer.pop() {
+            if let Err(item) = batch.push(item) {
+                // Keep the rest of the buffer for the next batch
+                let _ = receiver.buffer.push(item);
+                return batch;
+            }
+        }
+
+        let mut dequeued = false;
+        while !batch.is_full() {
+            let Some(item) = receiver.queue.pop() else {
+                break;
+            };
+            let _ = batch.push(item);
+            dequeued = true;
+        }
+
+        if dequeued {
+            receiver.queue.wake_enqueuers();
+        }
+        batch
+    }
+}
+
+impl<T, const W: usize, const N: usize, const P: usize> GroupMember for Receiver<'_, T, W, N, P>
+where
+    T: Unpin,
+{
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if !self.buffer.is_empty() {
+            return Poll::Ready(());
+        }
+        let mut queue = self.queue;
+        queue.poll_ready(cx)
+    }
+}
+
+impl<T, const W: usize, const N: usize, const P: usize> Drop for Receiver<'_, T, W, N, P>
+where
+    T: Unpin,
+{
+    fn drop(&mut self) {
+        self.queue.receivers.fetch_sub(1, Ordering::AcqRel);
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        while let Some(value) = self.buffer.pop() {
+            if self.queue.push(value).is_err() {
+                // The queue was refilled in the meantime, and we can not wait for room
+                self.queue.core.metrics.dropped();
+            }
+        }
+        self.queue.wake_dequeuers();
+    }
+}
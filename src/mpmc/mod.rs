@@ -3,24 +3,49 @@
 mod dequeue;
 mod enqueue;
 
-use core::task::Waker;
+mod handle;
+pub use handle::{ReadyChunks, Receiver, Sender, WeakReceiver, WeakSender};
+
+mod sequenced;
+pub use sequenced::SeqMpMcQueue;
+
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicIsize, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
 
 use heapless::mpmc::MpMcQueue as HMpMcQueue;
 
-use crate::{mutex::Mutex, waker::WakerRegistration};
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::{Operation, Waiter};
+use crate::{
+    builder::{Config, OverflowPolicy, WakeStrategy},
+    channel::{Core, Eviction, Flavor},
+    group::GroupMember,
+    instrument::Side,
+    log::*,
+    metrics::{Metrics, MetricsSource},
+    time::Deadline,
+    waker::{Name, NO_NAME},
+    waker_set::{Registration, WakerSet},
+};
 
 use self::{dequeue::DequeueFuture, enqueue::EnqueueFuture};
 
 struct WakerStorage<const W: usize> {
-    dequeue_wakers: Mutex<[WakerRegistration; W]>,
-    enqueue_wakers: Mutex<[WakerRegistration; W]>,
+    dequeue_wakers: WakerSet<W>,
+    enqueue_wakers: WakerSet<W>,
+    /// Futures waiting for the queue to become empty or full.
+    occupancy_wakers: WakerSet<W>,
 }
 
 impl<const W: usize> WakerStorage<W> {
     pub const fn new() -> Self {
         Self {
-            dequeue_wakers: Mutex::new([WakerRegistration::EMPTY; W]),
-            enqueue_wakers: Mutex::new([WakerRegistration::EMPTY; W]),
+            dequeue_wakers: WakerSet::new(),
+            enqueue_wakers: WakerSet::new(),
+            occupancy_wakers: WakerSet::new(),
         }
     }
 }
@@ -32,6 +57,16 @@ where
 {
     inner: HMpMcQueue<T, N>,
     wakers: WakerStorage<W>,
+    core: Core,
+    /// The amount of [`Sender`] handles.
+    senders: AtomicUsize,
+    /// The amount of [`Receiver`] handles.
+    receivers: AtomicUsize,
+    /// The amount of items in the queue.
+    ///
+    /// It is updated after the backing queue, so it can briefly drop below zero
+    /// if an item is dequeued right after it was enqueued.
+    occupancy: AtomicIsize,
 }
 
 impl<T, const W: usize, const N: usize> MpMcQueue<T, W, N>
@@ -40,78 +75,518 @@ where
 {
     /// Create a new [`MpMcQueue`]
     pub const fn new() -> Self {
+        Self::with_config(Config::DEFAULT)
+    }
+
+    pub(crate) const fn with_config(config: Config) -> Self {
         Self {
             inner: HMpMcQueue::new(),
             wakers: WakerStorage::new(),
+            core: Core::new(config),
+            senders: AtomicUsize::new(0),
+            receivers: AtomicUsize::new(0),
+            occupancy: AtomicIsize::new(0),
         }
     }
 
+    /// Returns the [`Metrics`] of this queue, if it was
+    /// built with metrics enabled.
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.core.metrics()
+    }
+
+    /// Returns a [`MetricsSource`] for the metrics of this queue.
+    pub fn metrics_source(&self) -> MetricsSource<'_> {
+        MetricsSource::new(&self.core.metrics)
+    }
+
+    /// Take the backing [`heapless::mpmc::MpMcQueue`], with the items that are left in
+    /// the queue, e.g. to inspect them once all senders and receivers are done.
+    pub fn into_inner(self) -> HMpMcQueue<T, N> {
+        self.inner
+    }
+
     /// Enqueue an item into the [`MpMcQueue`].
     ///
-    /// The returned Future will resolve once the value is succesfully enqueued.
+    /// The returned Future will resolve once the value is succesfully enqueued. If the
+    /// queue is full and fails enqueues with [`OverflowPolicy::Fail`], it resolves right
+    /// away, handing the value back.
     ///
     /// If the value cannot be enqueued, and there are no unoccupied enqueuer waker
-    /// slots, the Future will request to be awoken immediately.
+    /// slots, the Future will request to be awoken immediately. Dropping it before it
+    /// resolves drops the value.
+    #[must_use = "the value is not enqueued unless the returned future is awaited"]
     pub fn enqueue<'me>(&'me self, value: T) -> EnqueueFuture<'me, T, W, N> {
         EnqueueFuture::new(self, value)
     }
 
+    /// Enqueue an item into the [`MpMcQueue`], unless there is no space for it
+    /// before `deadline`.
+    ///
+    /// The returned future resolves once the value was enqueued, or hands the value
+    /// back once the deadline has passed. The value is also handed back right away if
+    /// the queue fails enqueues with [`OverflowPolicy::Fail`].
+    pub async fn enqueue_before<const C: usize>(
+        &self,
+        value: T,
+        deadline: Deadline<'_, C>,
+    ) -> Result<(), T> {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            let v = value
+                .take()
+                .expect("the value is only taken for good once the future resolves");
+
+            let v = match self.push(v) {
+                Ok(()) => {
+                    self.wake_dequeuers();
+                    return Poll::Ready(Ok(()));
+                }
+                Err(v) => v,
+            };
+
+            let rejected = self.core.config.overflow == OverflowPolicy::Fail;
+            if rejected || deadline.poll_passed(cx.waker()) {
+                debug!("Deadline passed, handing back value");
+                return Poll::Ready(Err(v));
+            }
+
+            if !self.register_enqueuer_waker(cx.waker(), NO_NAME) {
+                cx.waker().wake_by_ref();
+            }
+
+            // Try again, in case a value was dequeued before we registered
+            match self.push(v) {
+                Ok(()) => {
+                    self.wake_dequeuers();
+                    Poll::Ready(Ok(()))
+                }
+                Err(v) => {
+                    value = Some(v);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
     /// Dequeue an item from the [`MpMcQueue`].
     ///
     /// The returned Future will resolve once the value is succesfully enqueued.
     ///
     /// If a value cannot be dequeued, and there are no unoccupied dequeuer waker
-    /// slots, the Future will request to be awoken immediately.    
+    /// slots, the Future will request to be awoken immediately.
+    ///
+    /// An item is only dequeued when the future resolves, so it can be dropped
+    /// at any time, e.g. in a `select`, without losing an item. Dropping it also frees
+    /// its waker slot, and passes a wake it did not act on to another dequeuer.
+    #[must_use = "no item is dequeued unless the returned future is awaited"]
     pub fn dequeue<'me>(&'me self) -> DequeueFuture<'me, T, W, N> {
         DequeueFuture::new(self)
     }
 
-    /// Try to wake the enqueuers.
+    /// Enqueue `value` from a hand-written future, without an [`MpMcQueue::enqueue`] future.
+    ///
+    /// Like the [`MpMcQueue::enqueue`] future, the value is taken out of `value` once it
+    /// was enqueued, or handed back if the queue rejected it. While this is pending, the
+    /// value stays in `value`, and the waker of `cx` is woken once there may be room for it.
+    pub fn poll_enqueue(&self, cx: &mut Context<'_>, value: &mut Option<T>) -> Poll<Result<(), T>> {
+        self.poll_push(cx, value, NO_NAME, &mut None)
+    }
+
+    /// Dequeue an item from a hand-written future, without an [`MpMcQueue::dequeue`] future.
     ///
-    /// Currently implemented as unfairly as can be by just waking
-    /// everyone in order.
-    pub(crate) fn try_wake_enqueuers(&self) -> bool {
+    /// If no item is available yet, the waker of `cx` is woken once one may be.
+    pub fn poll_dequeue(&self, cx: &mut Context<'_>) -> Poll<T> {
+        self.poll_pop(cx, NO_NAME, &mut None)
+    }
+
+    /// Enqueue the value in `value`, or register the waker of `cx` as an enqueuer named
+    /// `name`, storing where it was registered in `registration`.
+    ///
+    /// The value is only left in `value` if the enqueuer has to wait.
+    fn poll_push(
+        &self,
+        cx: &mut Context<'_>,
+        value: &mut Option<T>,
+        name: Name,
+        registration: &mut Option<Registration>,
+    ) -> Poll<Result<(), T>> {
+        let Some(v) = value.take() else {
+            return Poll::Ready(Ok(()));
+        };
+
+        let v = match self.push(v) {
+            Ok(()) => {
+                // Wake the dequeuers because we've enqueued our value
+                self.wake_dequeuers();
+                return Poll::Ready(Ok(()));
+            }
+            Err(v) => v,
+        };
+
+        if self.core.config.overflow == OverflowPolicy::Fail {
+            trace!("Queue full, failing enqueue");
+            return Poll::Ready(Err(v));
+        }
+
+        *registration = self.wakers.enqueue_wakers.register_slot(cx.waker(), name);
+        if registration.is_none() {
+            cx.waker().wake_by_ref();
+        }
+
+        // Try again, in case a value was dequeued before we registered
+        match self.push(v) {
+            Ok(()) => {
+                self.wake_dequeuers();
+                Poll::Ready(Ok(()))
+            }
+            Err(v) => {
+                *value = Some(v);
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Dequeue an item, or register the waker of `cx` as a dequeuer named `name`, storing
+    /// where it was registered in `registration`.
+    fn poll_pop(
+        &self,
+        cx: &mut Context<'_>,
+        name: Name,
+        registration: &mut Option<Registration>,
+    ) -> Poll<T> {
+        if let Some(value) = self.pop() {
+            // Wake the enqueuers because we managed to dequeue a value
+            self.wake_enqueuers();
+            return Poll::Ready(value);
+        }
+
+        *registration = self.wakers.dequeue_wakers.register_slot(cx.waker(), name);
+        if registration.is_none() {
+            cx.waker().wake_by_ref();
+        }
+
+        // Try again, in case a value was enqueued before we registered
+        match self.pop() {
+            Some(value) => {
+                self.wake_enqueuers();
+                Poll::Ready(value)
+            }
+            None => Poll::Pending,
+        }
+    }
+
+    /// Wait for the queue to become empty.
+    ///
+    /// The returned future resolves once all items that were enqueued so far have been
+    /// dequeued, e.g. to flush the queue before shutting down.
+    pub async fn wait_empty(&self) {
+        self.wait_occupancy(|occupancy| occupancy <= 0).await
+    }
+
+    /// Wait for the queue to be drained.
+    ///
+    /// The returned future resolves once every [`Sender`] was dropped, and all items have
+    /// been dequeued, e.g. to flush the queue before powering down whatever processes
+    /// its items. Items that are enqueued through the queue directly, rather than through
+    /// a sender, do not keep the queue from being drained once it is empty.
+    pub async fn drained(&self) {
+        self.wait_occupancy(|occupancy| occupancy <= 0 && self.sender_count() == 0)
+            .await
+    }
+
+    /// Wait for the queue to become full.
+    ///
+    /// The returned future resolves once the queue holds `N` items, e.g. to dequeue
+    /// them as one batch.
+    pub async fn wait_full(&self) {
+        self.wait_occupancy(|occupancy| occupancy >= N as isize)
+            .await
+    }
+
+    async fn wait_occupancy<F>(&self, reached: F)
+    where
+        F: Fn(isize) -> bool,
+    {
+        poll_fn(|cx| {
+            if reached(self.occupancy.load(Ordering::Acquire)) {
+                return Poll::Ready(());
+            }
+
+            if !self.wakers.occupancy_wakers.register(cx.waker()) {
+                cx.waker().wake_by_ref();
+            }
+
+            // Check again, in case the occupancy changed before we registered
+            if reached(self.occupancy.load(Ordering::Acquire)) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Account for `delta` items having been enqueued or dequeued.
+    fn occupy(&self, delta: isize) {
+        let occupancy = self.occupancy.fetch_add(delta, Ordering::AcqRel) + delta;
+        if occupancy <= 0 || occupancy >= N as isize {
+            self.wake_occupancy_waiters();
+        }
+    }
+
+    /// Wake everything that waits for the queue to reach some occupancy, or to be drained.
+    fn wake_occupancy_waiters(&self) {
+        self.wakers
+            .occupancy_wakers
+            .wake_or_defer(WakeStrategy::All);
+    }
+
+    /// Enqueue as many of `values` as fit into the queue, without waiting.
+    ///
+    /// This is meant for interrupt handlers that receive several items at once: the
+    /// dequeuers are only woken once, after all items were enqueued. Returns the amount
+    /// of items that were taken from the start of `values`, which includes the ones that
+    /// were dropped by the overflow policy of the queue.
+    pub fn enqueue_burst_from_isr(&self, values: &[T]) -> usize
+    where
+        T: Clone,
+    {
+        let taken = values
+            .iter()
+            .take_while(|value| self.push((*value).clone()).is_ok())
+            .count();
+
+        if taken > 0 {
+            self.wake_dequeuers();
+        }
+        taken
+    }
+
+    /// Create a [`Sender`] handle for this queue.
+    pub fn sender(&self) -> Sender<'_, T, W, N> {
+        Sender::new(self)
+    }
+
+    /// Create a [`Receiver`] handle for this queue.
+    pub fn receiver(&self) -> Receiver<'_, T, W, N> {
+        Receiver::new(self)
+    }
+
+    /// Create a [`Receiver`] handle for this queue, which prefetches up to `P` items.
+    pub fn receiver_with_prefetch<const P: usize>(&self) -> Receiver<'_, T, W, N, P> {
+        Receiver::new(self)
+    }
+
+    /// Returns the amount of [`Sender`] handles that currently exist for this queue.
+    pub fn sender_count(&self) -> usize {
+        self.senders.load(Ordering::Acquire)
+    }
+
+    /// Returns the amount of [`Receiver`] handles that currently exist for this queue.
+    pub fn receiver_count(&self) -> usize {
+        self.receivers.load(Ordering::Acquire)
+    }
+
+    /// Call `f` for every task that is currently waiting on this queue.
+    ///
+    /// Waiters of an operation whose wakers are being registered or woken at the
+    /// moment are not reported.
+    #[cfg(feature = "diagnostics")]
+    pub fn waiters(&self, mut f: impl FnMut(Waiter)) {
+        let sets = [
+            (&self.wakers.enqueue_wakers, Operation::Enqueue),
+            (&self.wakers.dequeue_wakers, Operation::Dequeue),
+            (&self.wakers.occupancy_wakers, Operation::Occupancy),
+        ];
+        for (wakers, operation) in sets {
+            wakers.waiting(|name| f(Waiter { name, operation }));
+        }
+    }
+
+    /// Enqueue `value` into the backing queue, applying the overflow
+    /// policy of the queue if it is full.
+    ///
+    /// Only returns the value if the enqueuer has to wait before it can be enqueued, or if
+    /// the queue rejects it.
+    pub(crate) fn push(&self, value: T) -> Result<(), T> {
+        let mut flavor = self;
+        self.core.push(&mut flavor, value)
+    }
+
+    /// Dequeue an item from the backing queue.
+    pub(crate) fn pop(&self) -> Option<T> {
+        let value = self.inner.dequeue();
+        if value.is_some() {
+            self.core.metrics.dequeued();
+            self.occupy(-1);
+        }
+        value
+    }
+
+    /// Wake the enqueuers, or defer it to whoever is holding their wakers.
+    pub(crate) fn wake_enqueuers(&self) {
+        self.core.metrics.woke(Side::Enqueuers);
         self.wakers
             .enqueue_wakers
-            .try_lock()
-            .map(|mut wks| wks.iter_mut().for_each(|wk| wk.wake()))
-            .is_some()
+            .wake_or_defer(self.core.config.wake)
     }
 
     /// Attempt to register `waker` as a dequeuer waker
-    pub(crate) fn register_dequeuer_waker(&self, waker: &Waker) -> bool {
-        let res = self.wakers.dequeue_wakers.try_lock().map(|mut wks| {
-            wks.iter_mut()
-                .find(|wk| wk.is_empty())
-                .map(|wk| wk.register(waker))
-                .is_some()
-        });
+    pub(crate) fn register_dequeuer_waker(&self, waker: &Waker, name: Name) -> bool {
+        self.wakers.dequeue_wakers.register_named(waker, name)
+    }
 
-        res == Some(true)
+    /// Remove the dequeuer waker of `registration` once its future is `done`, or pass
+    /// its wake on if the future is dropped before it is done.
+    pub(crate) fn unregister_dequeuer_waker(&self, registration: Registration, done: bool) {
+        let wakers = &self.wakers.dequeue_wakers;
+        if done {
+            wakers.unregister(registration)
+        } else {
+            wakers.cancel(registration, self.core.config.wake)
+        }
     }
 
-    /// Try to wake the dequeuers.
-    ///
-    /// Currently implemented as unfairly as can be by just waking
-    /// everyone in order.
-    pub(crate) fn try_wake_dequeuers(&self) -> bool {
+    /// Wake the dequeuers, or defer it to whoever is holding their wakers.
+    pub(crate) fn wake_dequeuers(&self) {
+        self.core.metrics.woke(Side::Dequeuers);
         self.wakers
             .dequeue_wakers
-            .try_lock()
-            .map(|mut wks| wks.iter_mut().for_each(|wk| wk.wake()))
-            .is_some()
+            .wake_or_defer(self.core.config.wake)
     }
 
     /// Attempt to register `waker` as an enqueuer waker
-    pub(crate) fn register_enqueuer_waker(&self, waker: &Waker) -> bool {
-        let res = self.wakers.enqueue_wakers.try_lock().map(|mut wks| {
-            wks.iter_mut()
-                .find(|wk| wk.is_empty())
-                .map(|wk| wk.register(waker))
-                .is_some()
-        });
+    pub(crate) fn register_enqueuer_waker(&self, waker: &Waker, name: Name) -> bool {
+        self.wakers.enqueue_wakers.register_named(waker, name)
+    }
+
+    /// Remove the enqueuer waker of `registration` once its future is `done`, or pass
+    /// its wake on if the future is dropped before it is done.
+    pub(crate) fn unregister_enqueuer_waker(&self, registration: Registration, done: bool) {
+        let wakers = &self.wakers.enqueue_wakers;
+        if done {
+            wakers.unregister(registration)
+        } else {
+            wakers.cancel(registration, self.core.config.wake)
+        }
+    }
+}
+
+impl<T, const W: usize, const N: usize> Flavor<T> for &MpMcQueue<T, W, N>
+where
+    T: Unpin,
+{
+    fn insert(&mut self, value: T) -> Result<(), T> {
+        self.inner.enqueue(value)?;
+        self.occupy(1);
+        Ok(())
+    }
+
+    fn evict(&mut self, mut value: T, _: Eviction) -> Result<usize, T> {
+        let mut dropped = 0;
+        loop {
+            trace!("Queue full, dropping oldest value");
+            if self.inner.dequeue().is_some() {
+                dropped += 1;
+                self.occupy(-1);
+            }
+
+            match self.insert(value) {
+                Ok(()) => return Ok(dropped),
+                // Another enqueuer took the space we made
+                Err(v) => value = v,
+            }
+        }
+    }
+}
+
+impl<T, const W: usize, const N: usize> GroupMember for &MpMcQueue<T, W, N>
+where
+    T: Unpin,
+{
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.occupancy.load(Ordering::Acquire) > 0 {
+            return Poll::Ready(());
+        }
+
+        if !self.register_dequeuer_waker(cx.waker(), NO_NAME) {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        // Check again, in case a value was enqueued before we registered
+        if self.occupancy.load(Ordering::Acquire) > 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<T, const W: usize, const N: usize> From<HMpMcQueue<T, N>> for MpMcQueue<T, W, N>
+where
+    T: Unpin,
+{
+    /// Create a new [`MpMcQueue`], holding the items of `queue`
+    fn from(queue: HMpMcQueue<T, N>) -> Self {
+        let mut me = Self::new();
+        while let Some(value) = queue.dequeue() {
+            // The queues have the same capacity
+            let _ = me.inner.enqueue(value);
+            *me.occupancy.get_mut() += 1;
+        }
+        me
+    }
+}
+
+impl<T, const W: usize, const N: usize> core::fmt::Debug for MpMcQueue<T, W, N>
+where
+    T: Unpin,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let wakers = &self.wakers;
+        // The occupancy can briefly drop below zero
+        let len = self.occupancy.load(Ordering::Acquire).max(0);
+        f.debug_struct("MpMcQueue")
+            .field("len", &len)
+            .field("capacity", &N)
+            .field("enqueue_wakers", &wakers.enqueue_wakers.registered())
+            .field("dequeue_wakers", &wakers.dequeue_wakers.registered())
+            .field("senders", &self.sender_count())
+            .field("receivers", &self.receiver_count())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T, const W: usize, const N: usize> defmt::Format for MpMcQueue<T, W, N>
+where
+    T: Unpin,
+{
+    fn format(&self, f: defmt::Formatter) {
+        let wakers = &self.wakers;
+        let len = self.occupancy.load(Ordering::Acquire).max(0);
+        defmt::write!(
+            f,
+            "MpMcQueue {{ len: {}, capacity: {}, enqueue_wakers: {}, dequeue_wakers: {}, senders: {}, receivers: {} }}",
+            len,
+            N,
+            wakers.enqueue_wakers.registered(),
+            wakers.dequeue_wakers.registered(),
+            self.sender_count(),
+            self.receiver_count(),
+        )
+    }
+}
 
-        res == Some(true)
+impl<T, const W: usize, const N: usize> Default for MpMcQueue<T, W, N>
+where
+    T: Unpin,
+{
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -120,13 +595,14 @@ mod test {
     extern crate std;
     use std::println;
     use std::time::Duration;
-    use std::vec::Vec;
+    use std::{pin::pin, vec::Vec};
 
-    use super::MpMcQueue;
+    use super::{MpMcQueue, Receiver, SeqMpMcQueue};
+    use crate::builder::{OverflowPolicy, QueueBuilder, WakeStrategy};
 
     #[tokio::test]
     async fn mpmc() {
-        static Q: MpMcQueue<u32, 1, 8> = MpMcQueue::new();
+        static Q: MpMcQueue<u32, 2, 8> = MpMcQueue::new();
 
         const MAX: u32 = 100;
         let mut data = Vec::new();
@@ -155,7 +631,7 @@ mod test {
                 let mut interval = tokio::time::interval(Duration::from_millis(1));
                 println!("{}: Enqueing...", name);
                 for i in data {
-                    Q.enqueue(i).await;
+                    Q.enqueue(i).await.unwrap();
                     interval.tick().await;
                     println!("{}: Succesfully enqueued {}", name, i);
                 }
@@ -179,4 +655,369 @@ mod test {
             );
         }
     }
+
+    #[test]
+    #[cfg_attr(
+        feature = "panic-on-waker-overflow",
+        should_panic(expected = "No free waker slot")
+    )]
+    fn waker_overflow() {
+        use core::{future::Future, pin::pin};
+        use std::sync::Arc;
+        use std::task::{Context, Wake, Waker};
+
+        struct Task;
+
+        impl Wake for Task {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let queue: MpMcQueue<u32, 1, 4> = MpMcQueue::new();
+        let first = Waker::from(Arc::new(Task));
+        let second = Waker::from(Arc::new(Task));
+
+        // Only the first dequeuer gets the single waker slot, the second
+        // one has to be re-polled.
+        let mut a = pin!(queue.dequeue());
+        assert!(a
+            .as_mut()
+            .poll(&mut Context::from_waker(&first))
+            .is_pending());
+        let mut b = pin!(queue.dequeue());
+        assert!(b
+            .as_mut()
+            .poll(&mut Context::from_waker(&second))
+            .is_pending());
+    }
+
+    #[tokio::test]
+    async fn dropped_futures() {
+        static Q: MpMcQueue<u32, 2, 4> = QueueBuilder::new()
+            .wake_strategy(WakeStrategy::One)
+            .build_mpmc();
+
+        // A dequeue that is dropped while waiting gives up its waker slot
+        let mut dropped = Q.dequeue();
+        assert!(embassy_futures::poll_once(&mut dropped).is_pending());
+        drop(dropped);
+
+        let waiter = tokio::task::spawn(Q.dequeue());
+        // Give the waiter time to register its waker
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        Q.enqueue(0).await.unwrap();
+        let dequeued = tokio::time::timeout(Duration::from_secs(1), waiter).await;
+        assert_eq!(dequeued.unwrap().unwrap(), 0);
+
+        // A dequeue that is dropped after it was woken passes the wake on
+        let mut woken = Q.dequeue();
+        assert!(embassy_futures::poll_once(&mut woken).is_pending());
+        let waiter = tokio::task::spawn(Q.dequeue());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        Q.enqueue(1).await.unwrap();
+        drop(woken);
+        let dequeued = tokio::time::timeout(Duration::from_secs(1), waiter).await;
+        assert_eq!(dequeued.unwrap().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn fail_policy() {
+        static Q: MpMcQueue<u32, 2, 2> = QueueBuilder::new()
+            .overflow(OverflowPolicy::Fail)
+            .build_mpmc();
+
+        Q.enqueue(0).await.unwrap();
+        Q.enqueue(1).await.unwrap();
+        // Resolves without waiting for space, handing the value back
+        assert_eq!(Q.enqueue(2).await, Err(2));
+        assert_eq!(Q.dequeue().await, 0);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        static Q: MpMcQueue<u32, 2, 4> = QueueBuilder::new()
+            .overflow(OverflowPolicy::DropOldest)
+            .wake_strategy(WakeStrategy::One)
+            .metrics(true)
+            .build_mpmc();
+
+        for i in 0..6 {
+            Q.enqueue(i).await.unwrap();
+        }
+
+        for i in 2..6 {
+            assert_eq!(Q.dequeue().await, i);
+        }
+
+        let metrics = Q.metrics().unwrap();
+        assert_eq!(metrics.enqueued, 6);
+        assert_eq!(metrics.dequeued, 4);
+        assert_eq!(metrics.dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn sequence_numbers() {
+        static Q: SeqMpMcQueue<u32, 1, 2> = SeqMpMcQueue::from_queue(
+            QueueBuilder::new()
+                .overflow(OverflowPolicy::DropOldest)
+                .build_mpmc(),
+        );
+
+        for i in 0..4 {
+            Q.enqueue(i * 10).await.unwrap();
+        }
+
+        // The first two items were dropped to make room
+        assert_eq!(Q.dequeue_seq().await, (2, 20));
+        assert_eq!(Q.dequeue_seq().await, (3, 30));
+    }
+
+    #[tokio::test]
+    async fn prefetch() {
+        static Q: MpMcQueue<u32, 2, 8> = MpMcQueue::new();
+
+        let tx = Q.sender();
+        for i in 0..6 {
+            tx.clone().enqueue(i).await.unwrap();
+        }
+
+        let mut fast: Receiver<_, 2, 8, 3> = Q.receiver_with_prefetch();
+        assert_eq!(fast.dequeue().await, 0);
+        assert_eq!(fast.buffered(), 3);
+
+        // The buffered items are not available to other receivers
+        let mut slow = Q.receiver();
+        assert_eq!(slow.dequeue().await, 4);
+
+        assert_eq!(fast.dequeue().await, 1);
+        assert_eq!(fast.dequeue().await, 2);
+
+        assert_eq!((Q.sender_count(), Q.receiver_count()), (1, 2));
+        let weak = tx.downgrade();
+        assert!(weak.upgrade().is_some());
+        drop(tx);
+        assert!(weak.upgrade().is_none());
+
+        // Buffered items are returned to the queue on drop, behind the ones still in it
+        drop(fast);
+        assert_eq!(Q.receiver_count(), 1);
+        assert_eq!(slow.dequeue().await, 5);
+        assert_eq!(slow.dequeue().await, 3);
+    }
+
+    #[tokio::test]
+    async fn prefetch_drop() {
+        static Q: MpMcQueue<u32, 2, 2> = QueueBuilder::new().metrics(true).build_mpmc();
+
+        Q.enqueue(0).await.unwrap();
+        Q.enqueue(1).await.unwrap();
+        let mut rx: Receiver<_, 2, 2, 1> = Q.receiver_with_prefetch();
+        assert_eq!(rx.dequeue().await, 0);
+        assert_eq!(rx.buffered(), 1);
+
+        // A buffered item that no longer fits into the queue is dropped
+        Q.enqueue(2).await.unwrap();
+        Q.enqueue(3).await.unwrap();
+        drop(rx);
+        assert_eq!(Q.metrics().unwrap().dropped, 1);
+        assert_eq!(Q.dequeue().await, 2);
+        assert_eq!(Q.dequeue().await, 3);
+
+        // Taking the buffered items keeps them, in order
+        Q.enqueue(4).await.unwrap();
+        Q.enqueue(5).await.unwrap();
+        let mut rx: Receiver<_, 2, 2, 2> = Q.receiver_with_prefetch();
+        assert_eq!(rx.dequeue().await, 4);
+        Q.enqueue(6).await.unwrap();
+        assert_eq!(rx.take_buffered(), [5]);
+        assert_eq!(rx.dequeue().await, 6);
+        assert_eq!(rx.buffered(), 0);
+    }
+
+    #[tokio::test]
+    async fn weak_receiver() {
+        static Q: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+
+        let rx = Q.receiver();
+        let weak = rx.downgrade();
+        let mut upgraded = weak.upgrade().unwrap();
+        assert_eq!(Q.receiver_count(), 2);
+
+        Q.enqueue(1).await.unwrap();
+        assert_eq!(upgraded.dequeue().await, 1);
+
+        // A weak receiver does not keep the queue's receivers alive
+        drop((rx, upgraded));
+        assert_eq!(Q.receiver_count(), 0);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[tokio::test]
+    async fn ready_chunks() {
+        static Q: MpMcQueue<u32, 2, 8> = MpMcQueue::new();
+
+        for i in 0..7 {
+            Q.enqueue(i).await.unwrap();
+        }
+
+        let mut receiver: Receiver<_, 2, 8, 2> = Q.receiver_with_prefetch();
+        let mut chunks = receiver.ready_chunks::<4>();
+        assert_eq!(chunks.next().await, [0, 1, 2, 3]);
+        assert_eq!(chunks.next().await, [4, 5, 6]);
+        assert_eq!(receiver.buffered(), 0);
+    }
+
+    #[tokio::test]
+    async fn burst() {
+        static Q: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+
+        let consumer = tokio::task::spawn(async {
+            let mut values = Vec::new();
+            for _ in 0..4 {
+                values.push(Q.dequeue().await);
+            }
+            values
+        });
+        tokio::task::yield_now().await;
+
+        // Only the items that fit are enqueued
+        assert_eq!(Q.enqueue_burst_from_isr(&[1, 2, 3, 4, 5, 6]), 4);
+        assert_eq!(consumer.await.unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn wait_empty_and_full() {
+        static Q: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+
+        let batcher = tokio::task::spawn(async {
+            Q.wait_full().await;
+            let mut batch = Vec::new();
+            for _ in 0..4 {
+                batch.push(Q.dequeue().await);
+            }
+            batch
+        });
+
+        for i in 0..4 {
+            Q.enqueue(i).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+        Q.wait_empty().await;
+        assert_eq!(batcher.await.unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn poll_fns() {
+        use core::future::poll_fn;
+
+        static Q: MpMcQueue<u32, 2, 2> = MpMcQueue::new();
+
+        let dequeuer = tokio::spawn(poll_fn(|cx| Q.poll_dequeue(cx)));
+        tokio::task::yield_now().await;
+        let mut value = Some(1);
+        poll_fn(|cx| Q.poll_enqueue(cx, &mut value)).await.unwrap();
+        assert_eq!(dequeuer.await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn drained() {
+        static Q: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+
+        let sender = Q.sender();
+        let receiver = Q.receiver();
+        sender.enqueue(1).await.unwrap();
+
+        let mut drained = pin!(receiver.drained());
+        // The queue is empty, but a sender is left
+        Q.dequeue().await;
+        assert!(embassy_futures::poll_once(&mut drained).is_pending());
+
+        sender.enqueue(2).await.unwrap();
+        drop(sender);
+        assert!(embassy_futures::poll_once(&mut drained).is_pending());
+        Q.dequeue().await;
+        drained.await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn select_loop() {
+        use embassy_futures::select::{select, Either};
+
+        static Q: MpMcQueue<u32, 4, 4> = QueueBuilder::new()
+            .wake_strategy(WakeStrategy::One)
+            .build_mpmc();
+
+        let dequeuers: Vec<_> = (0..2)
+            .map(|_| {
+                tokio::task::spawn(async {
+                    let mut values = Vec::new();
+                    loop {
+                        // Abandon the dequeue whenever the other branch is ready first
+                        match select(Q.dequeue(), tokio::task::yield_now()).await {
+                            Either::First(u32::MAX) => return values,
+                            Either::First(value) => values.push(value),
+                            Either::Second(()) => {}
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for i in (0..1000).chain([u32::MAX; 2]) {
+            Q.enqueue(i).await.unwrap();
+        }
+
+        let mut values = Vec::new();
+        for dequeuer in dequeuers {
+            values.extend(dequeuer.await.unwrap());
+        }
+        values.sort();
+        assert_eq!(values, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[tokio::test]
+    async fn waiters() {
+        use crate::diagnostics::{Operation, Waiter, WaiterName};
+
+        static Q: MpMcQueue<u32, 2, 4> = MpMcQueue::new();
+
+        let mut receiver = Q.receiver();
+        receiver.set_name(7);
+        let named = tokio::task::spawn(async move { receiver.dequeue().await });
+        let unnamed = tokio::task::spawn(Q.dequeue());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let mut waiters = Vec::new();
+        Q.waiters(|waiter| waiters.push(waiter));
+        assert_eq!(waiters.len(), 2);
+        assert!(waiters.contains(&Waiter {
+            name: Some(WaiterName::Id(7)),
+            operation: Operation::Dequeue,
+        }));
+        assert!(waiters.contains(&Waiter {
+            name: None,
+            operation: Operation::Dequeue,
+        }));
+
+        Q.enqueue(0).await.unwrap();
+        Q.enqueue(1).await.unwrap();
+        named.await.unwrap();
+        unnamed.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn into_inner() {
+        let source: heapless::mpmc::MpMcQueue<u32, 4> = heapless::mpmc::MpMcQueue::new();
+        source.enqueue(0).unwrap();
+        source.enqueue(1).unwrap();
+
+        let queue: MpMcQueue<u32, 1, 4> = MpMcQueue::from(source);
+        assert_eq!(queue.dequeue().await, 0);
+        queue.enqueue(2).await.unwrap();
+
+        let inner = queue.into_inner();
+        assert_eq!(inner.dequeue(), Some(1));
+        assert_eq!(inner.dequeue(), Some(2));
+        assert_eq!(inner.dequeue(), None);
+    }
 }
@@ -0,0 +1,64 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::{DequeueFuture, EnqueueFuture, MpMcQueue};
+
+/// An [`MpMcQueue`] that tags every item with a sequence number.
+///
+/// Sequence numbers are assigned in the order in which [`SeqMpMcQueue::enqueue`] is called,
+/// starting at 0. A dequeuer that observes a gap between the sequence numbers of consecutive
+/// items knows that items were dropped (for example by a lossy
+/// [`OverflowPolicy`](crate::builder::OverflowPolicy)), or dequeued by another dequeuer.
+///
+/// Sequence numbers are `u32`s, as 64-bit atomics are not available on many targets. They
+/// wrap around, so gaps should be computed using [`u32::wrapping_sub`].
+pub struct SeqMpMcQueue<T, const W: usize, const N: usize>
+where
+    T: Unpin,
+{
+    queue: MpMcQueue<(u32, T), W, N>,
+    next: AtomicU32,
+}
+
+impl<T, const W: usize, const N: usize> SeqMpMcQueue<T, W, N>
+where
+    T: Unpin,
+{
+    /// Create a new [`SeqMpMcQueue`]
+    pub const fn new() -> Self {
+        Self::from_queue(MpMcQueue::new())
+    }
+
+    /// Create a new [`SeqMpMcQueue`] on top of `queue`, for example one
+    /// that was configured using a [`QueueBuilder`](crate::builder::QueueBuilder).
+    pub const fn from_queue(queue: MpMcQueue<(u32, T), W, N>) -> Self {
+        Self {
+            queue,
+            next: AtomicU32::new(0),
+        }
+    }
+
+    /// Enqueue an item, tagged with the next sequence number.
+    ///
+    /// The sequence number is assigned immediately, so dropping the returned
+    /// future before it resolves leaves a gap.
+    #[must_use = "the value is not enqueued unless the returned future is awaited"]
+    pub fn enqueue(&self, value: T) -> EnqueueFuture<'_, (u32, T), W, N> {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        self.queue.enqueue((seq, value))
+    }
+
+    /// Dequeue an item, together with its sequence number.
+    #[must_use = "no item is dequeued unless the returned future is awaited"]
+    pub fn dequeue_seq(&self) -> DequeueFuture<'_, (u32, T), W, N> {
+        self.queue.dequeue()
+    }
+}
+
+impl<T, const W: usize, const N: usize> Default for SeqMpMcQueue<T, W, N>
+where
+    T: Unpin,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -9,8 +9,8 @@ pub struct Mutex<T> {
     value: UnsafeCell<T>,
 }
 
-unsafe impl<T> Send for Mutex<T> {}
-unsafe impl<T> Sync for Mutex<T> {}
+unsafe impl<T> Send for Mutex<T> where T: Send {}
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
 
 impl<T> Mutex<T> {
     pub const fn new(value: T) -> Self {
@@ -20,18 +20,37 @@ impl<T> Mutex<T> {
         }
     }
 
-    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        if let Ok(_) =
-            self.locked
-                .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_ok()
         {
             Some(MutexGuard { lock: self })
         } else {
             None
         }
     }
+
+    /// Create a guard for the mutex, after the guard that locked it was forgotten.
+    ///
+    /// # Safety
+    /// The mutex must be locked, and no guard for it may be alive.
+    pub unsafe fn adopt(&self) -> MutexGuard<'_, T> {
+        debug_assert!(self.locked.load(Ordering::SeqCst));
+        MutexGuard { lock: self }
+    }
+
+    /// Unlock the mutex, e.g. after the guard locking it was forgotten.
+    ///
+    /// # Safety
+    /// No guard for this mutex may be alive.
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::SeqCst);
+    }
 }
 
+#[must_use = "if unused the Mutex will immediately unlock"]
 pub struct MutexGuard<'lock, T> {
     lock: &'lock Mutex<T>,
 }
@@ -0,0 +1,312 @@
+//! A channel for sending a single value, e.g. the response to a request.
+
+use core::{
+    cell::UnsafeCell,
+    future::{poll_fn, Future},
+    mem::MaybeUninit,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{mutex::Mutex, waker::WakerRegistration};
+
+/// Set once the value was stored, and cleared once it was taken.
+const VALUE: u8 = 0b001;
+/// Set once the sender has sent the value, or was dropped.
+const SENDER_DONE: u8 = 0b010;
+/// Set once the receiver has taken the value, or was dropped.
+const RECEIVER_DONE: u8 = 0b100;
+
+/// The error returned by the [`Receiver`] if the [`Sender`] was dropped
+/// without sending a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+/// The storage of a oneshot channel.
+pub struct Oneshot<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    state: AtomicU8,
+    receiver_waker: Mutex<WakerRegistration>,
+    sender_waker: Mutex<WakerRegistration>,
+}
+
+unsafe impl<T> Sync for Oneshot<T> where T: Send {}
+
+/// The two halves of a split [`Oneshot`].
+pub struct Split<'channel, T> {
+    /// The sending half of the channel.
+    pub sender: Sender<'channel, T>,
+    /// The receiving half of the channel.
+    pub receiver: Receiver<'channel, T>,
+}
+
+impl<T> Oneshot<T> {
+    /// Create a new [`Oneshot`]
+    pub const fn new() -> Self {
+        Self {
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+            state: AtomicU8::new(0),
+            receiver_waker: Mutex::new(WakerRegistration::new()),
+            sender_waker: Mutex::new(WakerRegistration::new()),
+        }
+    }
+
+    /// Split the channel into a sender and receiver.
+    ///
+    /// If the channel was used before, it is reset first.
+    pub fn split(&mut self) -> Split<'_, T> {
+        self.reset();
+        let channel = &*self;
+        Split {
+            sender: Sender { channel },
+            receiver: Receiver { channel },
+        }
+    }
+
+    /// Store `value`, and wake the receiver.
+    ///
+    /// Returns true if the receiver was already done, in which case `value` is dropped.
+    ///
+    /// Must be called at most once, and not after [`Oneshot::close_sender`].
+    pub(crate) fn send(&self, value: T) -> bool {
+        // SAFETY: the receiver only reads the value once `VALUE` is set.
+        unsafe { (*self.value.get()).write(value) };
+
+        let previous = self.state.fetch_or(VALUE | SENDER_DONE, Ordering::AcqRel);
+        if previous & RECEIVER_DONE != 0 {
+            self.state.fetch_and(!VALUE, Ordering::AcqRel);
+            // SAFETY: the receiver is gone, so nobody else can access the value.
+            unsafe { (*self.value.get()).assume_init_drop() };
+            return true;
+        }
+
+        self.wake_receiver();
+        false
+    }
+
+    /// Mark the sender as done without sending a value.
+    ///
+    /// Returns true if the receiver was already done.
+    pub(crate) fn close_sender(&self) -> bool {
+        let previous = self.state.fetch_or(SENDER_DONE, Ordering::AcqRel);
+        if previous & SENDER_DONE == 0 {
+            self.wake_receiver();
+        }
+        previous & RECEIVER_DONE != 0
+    }
+
+    /// Take the value, or register the waker of `cx` to be woken once it is sent.
+    ///
+    /// Must not be called after [`Oneshot::close_receiver`].
+    pub(crate) fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<T, Canceled>> {
+        // Register before checking, so that a value sent in between can not be missed.
+        if let Some(mut wk) = self.receiver_waker.try_lock() {
+            wk.register(cx.waker());
+        } else {
+            cx.waker().wake_by_ref();
+        }
+
+        let state = self.state.load(Ordering::Acquire);
+        if state & VALUE != 0 {
+            // SAFETY: the value was stored, and the sender never touches it again.
+            let value = unsafe { (*self.value.get()).assume_init_read() };
+            self.state.fetch_and(!VALUE, Ordering::AcqRel);
+            Poll::Ready(Ok(value))
+        } else if state & SENDER_DONE != 0 {
+            Poll::Ready(Err(Canceled))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Mark the receiver as done, dropping the value if it was not taken.
+    ///
+    /// Returns true if the sender was already done.
+    pub(crate) fn close_receiver(&self) -> bool {
+        let previous = self.state.fetch_or(RECEIVER_DONE, Ordering::AcqRel);
+        if previous & VALUE != 0 {
+            // SAFETY: the value was stored, and the sender never touches it again.
+            unsafe { (*self.value.get()).assume_init_drop() };
+            self.state.fetch_and(!VALUE, Ordering::AcqRel);
+        }
+        if previous & SENDER_DONE == 0 {
+            self.wake_sender();
+        }
+        previous & SENDER_DONE != 0
+    }
+
+    /// Returns true if the receiver is done.
+    pub(crate) fn is_receiver_done(&self) -> bool {
+        self.state.load(Ordering::Acquire) & RECEIVER_DONE != 0
+    }
+
+    /// Resolve once the receiver is done, or register the waker of `cx` to be
+    /// woken once it is.
+    pub(crate) fn poll_receiver_done(&self, cx: &mut Context<'_>) -> Poll<()> {
+        // Register before checking, so that the receiver being dropped in between
+        // can not be missed.
+        if let Some(mut wk) = self.sender_waker.try_lock() {
+            wk.register(cx.waker());
+        } else {
+            cx.waker().wake_by_ref();
+        }
+
+        if self.is_receiver_done() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Reset the channel, so that it can be used again.
+    ///
+    /// Must only be called once both sides are done.
+    pub(crate) fn reset(&self) {
+        if self.state.swap(0, Ordering::AcqRel) & VALUE != 0 {
+            // SAFETY: both sides are done, so nobody else can access the value.
+            unsafe { (*self.value.get()).assume_init_drop() };
+        }
+    }
+
+    fn wake_receiver(&self) {
+        // If the waker is locked, the receiver is registering, and checks
+        // the state afterwards.
+        if let Some(mut wk) = self.receiver_waker.try_lock() {
+            wk.wake();
+        }
+    }
+
+    fn wake_sender(&self) {
+        // If the waker is locked, the sender is registering, and checks
+        // the state afterwards.
+        if let Some(mut wk) = self.sender_waker.try_lock() {
+            wk.wake();
+        }
+    }
+}
+
+impl<T> Default for Oneshot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Oneshot<T> {
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
+
+/// The sending half of a [`Oneshot`].
+///
+/// Dropping it without sending a value makes the [`Receiver`] resolve to [`Canceled`].
+pub struct Sender<'channel, T> {
+    channel: &'channel Oneshot<T>,
+}
+
+impl<T> Sender<'_, T> {
+    /// Send `value` to the [`Receiver`].
+    ///
+    /// Returns the value if the receiver was already dropped.
+    pub fn send(self, value: T) -> Result<(), T> {
+        let channel = self.channel;
+        core::mem::forget(self);
+
+        if channel.state.load(Ordering::Acquire) & RECEIVER_DONE != 0 {
+            channel.close_sender();
+            return Err(value);
+        }
+
+        channel.send(value);
+        Ok(())
+    }
+
+    /// Returns true if the [`Receiver`] was dropped, so that a sent value
+    /// would not be received.
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_receiver_done()
+    }
+
+    /// Wait until the [`Receiver`] is dropped, e.g. to abandon the work of
+    /// producing the value once nobody is waiting for it.
+    pub async fn closed(&mut self) {
+        let channel = self.channel;
+        poll_fn(|cx| channel.poll_receiver_done(cx)).await
+    }
+}
+
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        self.channel.close_sender();
+    }
+}
+
+/// The receiving half of a [`Oneshot`].
+///
+/// It is a future, resolving to the sent value.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct Receiver<'channel, T> {
+    channel: &'channel Oneshot<T>,
+}
+
+impl<T> Future for Receiver<'_, T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.channel.poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<'_, T> {
+    fn drop(&mut self) {
+        self.channel.close_receiver();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::{boxed::Box, time::Duration};
+
+    use super::{Canceled, Oneshot, Split};
+
+    #[tokio::test]
+    async fn send_and_cancel() {
+        let channel: &'static mut Oneshot<u32> = Box::leak(Box::default());
+
+        let Split { sender, receiver } = channel.split();
+        let (received, ()) = tokio::join!(receiver, async { sender.send(42).unwrap() });
+        assert_eq!(received, Ok(42));
+
+        let Split { sender, receiver } = channel.split();
+        drop(sender);
+        assert_eq!(receiver.await, Err(Canceled));
+
+        let Split { sender, receiver } = channel.split();
+        drop(receiver);
+        assert!(sender.is_closed());
+        assert_eq!(sender.send(7), Err(7));
+    }
+
+    #[tokio::test]
+    async fn closed() {
+        let channel: &'static mut Oneshot<u32> = Box::leak(Box::default());
+
+        let Split {
+            mut sender,
+            receiver,
+        } = channel.split();
+        let requester = tokio::spawn(async move {
+            // The request is canceled before it is answered
+            tokio::time::timeout(Duration::from_millis(10), receiver)
+                .await
+                .unwrap_err();
+        });
+
+        assert!(!sender.is_closed());
+        sender.closed().await;
+        assert!(sender.is_closed());
+        requester.await.unwrap();
+    }
+}
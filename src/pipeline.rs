@@ -0,0 +1,197 @@
+//! Processing pipelines built from [`spsc`](crate::spsc) queues.
+//!
+//! A pipeline is a chain of queues, connected by [`Stage`]s. Every stage dequeues an item
+//! from its input queue, transforms it, and enqueues the result into its output queue,
+//! which is the input queue of the next stage. As every queue is bounded, a slow stage
+//! applies backpressure to all stages before it.
+//!
+//! Every stage is driven by its own future, returned by [`Stage::run`], which can be spawned
+//! as a separate task or joined with the other stages. Finishing the first queue of a
+//! pipeline finishes every following queue, after all items have passed through.
+//!
+//! A [`Tee`] splits a pipeline into two, by duplicating every item into two output queues.
+
+use core::future::Future;
+
+use crate::spsc::{Consumer, Producer};
+
+/// A stage of a pipeline.
+pub struct Stage<'input, 'output, I, O, F, const NI: usize, const NO: usize>
+where
+    I: Unpin,
+    O: Unpin,
+{
+    input: Consumer<'input, I, NI>,
+    output: Producer<'output, O, NO>,
+    transform: F,
+}
+
+impl<'input, 'output, I, O, F, Fut, const NI: usize, const NO: usize>
+    Stage<'input, 'output, I, O, F, NI, NO>
+where
+    I: Unpin,
+    O: Unpin,
+    F: FnMut(I) -> Fut,
+    Fut: Future<Output = O>,
+{
+    /// Create a new stage, which transforms the items from `input` using `transform`
+    /// and enqueues the results into `output`.
+    pub fn new(
+        input: Consumer<'input, I, NI>,
+        output: Producer<'output, O, NO>,
+        transform: F,
+    ) -> Self {
+        Self {
+            input,
+            output,
+            transform,
+        }
+    }
+
+    /// Drive this stage.
+    ///
+    /// The returned future resolves once the input is finished, after finishing the output.
+    pub async fn run(mut self) {
+        while let Ok(item) = self.input.dequeue().await {
+            let result = (self.transform)(item).await;
+            // A result that the output rejects is dropped, like with a dropping policy
+            let _ = self.output.enqueue(result).await;
+        }
+        self.output.finish().await;
+    }
+}
+
+/// A splitter of a pipeline, duplicating every item to two outputs.
+///
+/// By default, the slowest output gates progress: an item is only dequeued from the
+/// input once the previous one was enqueued into both outputs. To keep a slow output
+/// from stalling the other one, build its queue with a dropping
+/// [`OverflowPolicy`](crate::builder::OverflowPolicy), which makes that output lossy.
+pub struct Tee<'input, 'first, 'second, T, const NI: usize, const NF: usize, const NS: usize>
+where
+    T: Unpin + Clone,
+{
+    input: Consumer<'input, T, NI>,
+    first: Producer<'first, T, NF>,
+    second: Producer<'second, T, NS>,
+}
+
+impl<'input, 'first, 'second, T, const NI: usize, const NF: usize, const NS: usize>
+    Tee<'input, 'first, 'second, T, NI, NF, NS>
+where
+    T: Unpin + Clone,
+{
+    /// Create a new tee, which duplicates the items from `input` into `first` and `second`.
+    pub fn new(
+        input: Consumer<'input, T, NI>,
+        first: Producer<'first, T, NF>,
+        second: Producer<'second, T, NS>,
+    ) -> Self {
+        Self {
+            input,
+            first,
+            second,
+        }
+    }
+
+    /// Drive this tee.
+    ///
+    /// The returned future resolves once the input is finished, after finishing both outputs.
+    pub async fn run(mut self) {
+        while let Ok(item) = self.input.dequeue().await {
+            // An output that rejects an item misses it, like with a dropping policy
+            let _ = self.first.enqueue(item.clone()).await;
+            let _ = self.second.enqueue(item).await;
+        }
+        self.first.finish().await;
+        self.second.finish().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::boxed::Box;
+
+    use super::{Stage, Tee};
+    use crate::{
+        builder::{OverflowPolicy, QueueBuilder},
+        spsc::{Finished, Queue, Split},
+    };
+
+    #[tokio::test]
+    async fn two_stages() {
+        let first: &'static mut Queue<u32, 4> = Box::leak(Box::default());
+        let second: &'static mut Queue<u32, 4> = Box::leak(Box::default());
+        let third: &'static mut Queue<u64, 4> = Box::leak(Box::default());
+
+        let Split {
+            producer: mut source,
+            consumer: first_rx,
+        } = first.split();
+        let Split {
+            producer: second_tx,
+            consumer: second_rx,
+        } = second.split();
+        let Split {
+            producer: third_tx,
+            consumer: mut sink,
+        } = third.split();
+
+        tokio::task::spawn(Stage::new(first_rx, second_tx, |x| async move { x * 2 }).run());
+        tokio::task::spawn(Stage::new(second_rx, third_tx, |x| async move { x as u64 + 1 }).run());
+
+        tokio::task::spawn(async move {
+            for i in 0..32 {
+                source.enqueue(i).await.unwrap();
+            }
+            source.finish().await;
+        });
+
+        for i in 0..32 {
+            assert_eq!(sink.dequeue().await, Ok(i * 2 + 1));
+        }
+        assert_eq!(sink.dequeue().await, Err(Finished));
+    }
+
+    #[tokio::test]
+    async fn tee_lossy_output() {
+        let input: &'static mut Queue<u32, 4> = Box::leak(Box::default());
+        let processor: &'static mut Queue<u32, 4> = Box::leak(Box::default());
+        let logger: &'static mut Queue<u32, 3> = Box::leak(Box::new(
+            QueueBuilder::new()
+                .overflow(OverflowPolicy::DropNewest)
+                .metrics(true)
+                .build_spsc(),
+        ));
+
+        let Split {
+            producer: mut source,
+            consumer: input_rx,
+        } = input.split();
+        let Split {
+            producer: processor_tx,
+            consumer: mut processor_rx,
+        } = processor.split();
+        let Split {
+            producer: logger_tx,
+            consumer: logger_rx,
+        } = logger.split();
+
+        tokio::task::spawn(Tee::new(input_rx, processor_tx, logger_tx).run());
+        tokio::task::spawn(async move {
+            for i in 0..16 {
+                source.enqueue(i).await.unwrap();
+            }
+        });
+
+        // The logger is never read, but does not stall the processor
+        for i in 0..16 {
+            assert_eq!(processor_rx.dequeue().await, Ok(i));
+        }
+
+        let metrics = logger_rx.metrics().unwrap();
+        assert_eq!(metrics.enqueued, 3);
+        assert!(metrics.dropped >= 12);
+    }
+}
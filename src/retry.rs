@@ -0,0 +1,108 @@
+//! Retrying `try_` operations without spinning.
+//!
+//! The `try_` operations of the queues fail with `WouldBlock` while the other side holds
+//! a lock, or because the queue is full or empty. Retrying them in a tight loop only
+//! works if whatever makes them succeed preempts the loop. [`retry`] instead gives other
+//! tasks a chance to run between attempts: it yields to the executor with exponentially
+//! growing gaps at first, and then only tries again on every tick of a [`Clock`].
+//!
+//! ```
+//! use core::task::Poll;
+//! use heapless_async_queues::{
+//!     retry::retry,
+//!     spsc::{ConsumerError, Finished, Queue, Split},
+//!     time::Clock,
+//! };
+//!
+//! static CLOCK: Clock = Clock::new();
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let mut queue: Queue<u32, 4> = Queue::new();
+//! let Split { producer: mut tx, consumer: mut rx } = queue.split();
+//! # let _ = tx.try_enqueue(1);
+//!
+//! let value = retry(&CLOCK, 8, || match rx.try_dequeue() {
+//!     Ok(value) | Err(ConsumerError::WouldBlock(Some(value))) => Poll::Ready(Ok(value)),
+//!     Err(ConsumerError::Finished | ConsumerError::Disconnected) => Poll::Ready(Err(Finished)),
+//!     Err(_) => Poll::Pending,
+//! })
+//! .await;
+//! assert_eq!(value, Ok(1));
+//! # });
+//! ```
+
+use core::{future::poll_fn, task::Poll};
+
+use crate::{channel::yield_now, log::*, time::Clock};
+
+/// Run `op` until it returns [`Poll::Ready`], and resolve to its output.
+///
+/// After every failed attempt, `retry` yields to the executor: once after the first
+/// attempt, twice after the second one, and so on, doubling the gap until it has yielded
+/// `yields` times in total. From then on, it waits for the next tick of `clock` before
+/// every attempt, so that a task that keeps failing does not keep the executor busy.
+pub async fn retry<O, F, const W: usize>(clock: &Clock<W>, yields: u32, mut op: F) -> O
+where
+    F: FnMut() -> Poll<O>,
+{
+    let mut gap = 1u32;
+    let mut left = yields;
+    loop {
+        if let Poll::Ready(output) = op() {
+            return output;
+        }
+
+        if left > 0 {
+            let yields = gap.min(left);
+            for _ in 0..yields {
+                yield_now().await;
+            }
+            left -= yields;
+            gap = gap.saturating_mul(2);
+        } else {
+            trace!("Retrying on the next tick");
+            let deadline = clock.after(1);
+            poll_fn(|cx| {
+                if deadline.poll_passed(cx.waker()) {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use core::task::Poll;
+    use std::time::Duration;
+
+    use super::retry;
+    use crate::time::Clock;
+
+    #[tokio::test]
+    async fn backoff() {
+        static CLOCK: Clock = Clock::new();
+
+        let ticker = tokio::spawn(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            CLOCK.tick();
+        });
+
+        // Gaps of one and two yields, and then a wait for the tick
+        let mut attempts = 0;
+        let retried = retry(&CLOCK, 3, || {
+            attempts += 1;
+            if CLOCK.now().ticks() > 0 {
+                Poll::Ready(attempts)
+            } else {
+                Poll::Pending
+            }
+        });
+        assert_eq!(retried.await, 4);
+        ticker.await.unwrap();
+    }
+}
@@ -0,0 +1,505 @@
+//! A bounded job scheduler, running jobs on a pool of workers.
+//!
+//! Jobs are submitted with [`Scheduler::spawn`], which waits for one of the `N` job slots
+//! to become free, and returns a [`JobHandle`] resolving to the result of the job. Jobs are
+//! processed by the futures returned by [`Worker::run`], which can be spawned as separate
+//! tasks, or joined with each other.
+//!
+//! A job can be anything that fits into the queue, e.g. a command enum or a function
+//! pointer; the workers turn jobs into results using the handler they were created with.
+//!
+//! Jobs can be submitted at one of `P` priority levels with [`Scheduler::spawn_with_priority`],
+//! and workers always take the job with the highest priority first. A job that is no longer
+//! needed can be canceled with [`JobHandle::cancel`].
+//!
+//! After [`Scheduler::shutdown`], no new jobs are accepted, but the jobs that were already
+//! submitted are still processed. Every worker stops once no jobs are left.
+
+use core::{
+    future::{poll_fn, Future},
+    pin::{pin, Pin},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{
+    builder::WakeStrategy,
+    log::*,
+    mpmc::MpMcQueue,
+    oneshot::{Canceled, Oneshot},
+    waker::NO_NAME,
+    waker_set::WakerSet,
+};
+
+/// The storage for the completion of a single job.
+struct Slot<R> {
+    in_use: AtomicBool,
+    /// Set if the job of the slot was canceled by its handle.
+    canceled: AtomicBool,
+    result: Oneshot<R>,
+}
+
+impl<R> Slot<R> {
+    const fn new() -> Self {
+        Self {
+            in_use: AtomicBool::new(false),
+            canceled: AtomicBool::new(false),
+            result: Oneshot::new(),
+        }
+    }
+}
+
+/// A job scheduler.
+///
+/// At most `N` jobs can be in flight at the same time, and `W` futures can wait on
+/// either side, i.e. there can be at most `W` workers and `W` waiting spawners. `N`
+/// must be a power of two. Jobs are submitted at one of `P` priority levels, where
+/// higher levels are taken by the workers first.
+pub struct Scheduler<J, R, const W: usize, const N: usize, const P: usize = 1>
+where
+    J: Unpin,
+{
+    /// The submitted jobs, by their priority.
+    jobs: [MpMcQueue<(J, usize), W, N>; P],
+    slots: [Slot<R>; N],
+    slot_wakers: WakerSet<W>,
+    shutdown: AtomicBool,
+    /// The amount of spawners that are submitting a job right now.
+    submitting: AtomicUsize,
+}
+
+impl<J, R, const W: usize, const N: usize, const P: usize> Scheduler<J, R, W, N, P>
+where
+    J: Unpin,
+{
+    const VALID_PRIORITIES: () = assert!(P > 0, "A scheduler needs at least one priority level");
+
+    /// Create a new [`Scheduler`]
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_PRIORITIES;
+
+        Self {
+            jobs: [const { MpMcQueue::new() }; P],
+            slots: [const { Slot::new() }; N],
+            slot_wakers: WakerSet::new(),
+            shutdown: AtomicBool::new(false),
+            submitting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Submit `job` to the workers.
+    ///
+    /// The returned future resolves once a job slot was free, to a [`JobHandle`] for the
+    /// result of the job, or to `Err(job)` if the scheduler was shut down. Dropping the
+    /// handle does not cancel the job.
+    ///
+    /// The job is submitted at the lowest priority level.
+    #[must_use = "the job is not submitted unless the returned future is awaited"]
+    pub fn spawn(&self, job: J) -> SpawnFuture<'_, J, R, W, N, P> {
+        self.spawn_with_priority(job, 0)
+    }
+
+    /// Submit `job` to the workers, at `priority`.
+    ///
+    /// Like [`Scheduler::spawn`], but workers take the job before any job of a lower
+    /// priority that is still waiting. Jobs of the same priority are taken in the order
+    /// they were submitted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `priority` is not below `P`.
+    #[must_use = "the job is not submitted unless the returned future is awaited"]
+    pub fn spawn_with_priority(&self, job: J, priority: usize) -> SpawnFuture<'_, J, R, W, N, P> {
+        assert!(priority < P, "priority {} out of range", priority);
+        SpawnFuture {
+            scheduler: self,
+            job: Some(job),
+            priority,
+        }
+    }
+
+    /// Create a worker, which turns jobs into results using `handler`.
+    pub fn worker<F, Fut>(&self, handler: F) -> Worker<'_, J, R, F, W, N, P>
+    where
+        F: FnMut(J) -> Fut,
+        Fut: Future<Output = R>,
+    {
+        Worker {
+            scheduler: self,
+            handler,
+        }
+    }
+
+    /// Stop accepting new jobs.
+    ///
+    /// Jobs that were already submitted are still processed, after which the workers stop.
+    pub fn shutdown(&self) {
+        debug!("Shutting down scheduler");
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.wake_workers();
+        self.slot_wakers.wake(WakeStrategy::All);
+    }
+
+    /// Returns true if the scheduler was shut down.
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Claim a free slot, returning its index.
+    fn claim_slot(&self) -> Option<usize> {
+        self.slots.iter().position(|slot| {
+            slot.in_use
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        })
+    }
+
+    /// Free the slot at `idx`, once both its job handle and its worker are done with it.
+    fn free_slot(&self, idx: usize) {
+        self.slots[idx].result.reset();
+        self.slots[idx].canceled.store(false, Ordering::Release);
+        self.slots[idx].in_use.store(false, Ordering::Release);
+        self.slot_wakers.wake(WakeStrategy::All);
+    }
+
+    /// Wait for the next job, resolving to `None` once the scheduler is shut
+    /// down and no jobs are left.
+    fn poll_job(&self, cx: &mut Context<'_>) -> Poll<Option<(J, usize)>> {
+        if let Some(job) = self.pop_job() {
+            return Poll::Ready(Some(job));
+        }
+
+        for jobs in &self.jobs {
+            if !jobs.register_dequeuer_waker(cx.waker(), NO_NAME) {
+                cx.waker().wake_by_ref();
+            }
+        }
+
+        // Try again, in case a job was submitted before we registered
+        if let Some(job) = self.pop_job() {
+            return Poll::Ready(Some(job));
+        }
+
+        // A spawner that started submitting before the shutdown may not have
+        // pushed its job yet.
+        if self.is_shut_down() && self.submitting.load(Ordering::SeqCst) == 0 {
+            Poll::Ready(self.pop_job())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Take the waiting job with the highest priority.
+    fn pop_job(&self) -> Option<(J, usize)> {
+        self.jobs.iter().rev().find_map(MpMcQueue::pop)
+    }
+
+    fn wake_workers(&self) {
+        for jobs in &self.jobs {
+            jobs.wake_dequeuers();
+        }
+    }
+}
+
+impl<J, R, const W: usize, const N: usize, const P: usize> Default for Scheduler<J, R, W, N, P>
+where
+    J: Unpin,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The future returned by [`Scheduler::spawn`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SpawnFuture<'scheduler, J, R, const W: usize, const N: usize, const P: usize = 1>
+where
+    J: Unpin,
+{
+    scheduler: &'scheduler Scheduler<J, R, W, N, P>,
+    job: Option<J>,
+    priority: usize,
+}
+
+impl<'scheduler, J, R, const W: usize, const N: usize, const P: usize> Future
+    for SpawnFuture<'scheduler, J, R, W, N, P>
+where
+    J: Unpin,
+{
+    type Output = Result<JobHandle<'scheduler, J, R, W, N, P>, J>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.get_mut();
+        let scheduler = me.scheduler;
+
+        scheduler.submitting.fetch_add(1, Ordering::SeqCst);
+        let slot = if scheduler.is_shut_down() {
+            None
+        } else if let Some(slot) = scheduler.claim_slot() {
+            Some(slot)
+        } else {
+            if !scheduler.slot_wakers.register(cx.waker()) {
+                cx.waker().wake_by_ref();
+            }
+
+            // Try again, in case a slot was freed before we registered
+            match scheduler.claim_slot() {
+                Some(slot) => Some(slot),
+                None => {
+                    scheduler.submitting.fetch_sub(1, Ordering::SeqCst);
+                    return Poll::Pending;
+                }
+            }
+        };
+
+        let job = me.job.take().expect("polled after completion");
+        let Some(slot) = slot else {
+            scheduler.submitting.fetch_sub(1, Ordering::SeqCst);
+            return Poll::Ready(Err(job));
+        };
+
+        // There are as many places in the queue as there are slots.
+        if scheduler.jobs[me.priority].push((job, slot)).is_err() {
+            unreachable!("job queue is full while a slot was free");
+        }
+        scheduler.submitting.fetch_sub(1, Ordering::SeqCst);
+        scheduler.jobs[me.priority].wake_dequeuers();
+
+        trace!("Submitted job in slot {}", slot);
+        Poll::Ready(Ok(JobHandle { scheduler, slot }))
+    }
+}
+
+/// A handle for a submitted job.
+///
+/// It is a future, resolving to the result of the job, or to [`Canceled`] if the
+/// worker running the job was dropped before finishing it.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct JobHandle<'scheduler, J, R, const W: usize, const N: usize, const P: usize = 1>
+where
+    J: Unpin,
+{
+    scheduler: &'scheduler Scheduler<J, R, W, N, P>,
+    slot: usize,
+}
+
+impl<J, R, const W: usize, const N: usize, const P: usize> JobHandle<'_, J, R, W, N, P>
+where
+    J: Unpin,
+{
+    /// Cancel the job.
+    ///
+    /// A job that no worker has taken yet is dropped without running, and a job that
+    /// is running is aborted by dropping the future of its handler.
+    pub fn cancel(self) {
+        let slot = self.slot;
+        trace!("Canceling job in slot {}", slot);
+        // Dropping the handle wakes the worker, which then checks the flag.
+        self.scheduler.slots[slot]
+            .canceled
+            .store(true, Ordering::Release);
+    }
+}
+
+impl<J, R, const W: usize, const N: usize, const P: usize> Future for JobHandle<'_, J, R, W, N, P>
+where
+    J: Unpin,
+{
+    type Output = Result<R, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.scheduler.slots[self.slot].result.poll_recv(cx)
+    }
+}
+
+impl<J, R, const W: usize, const N: usize, const P: usize> Drop for JobHandle<'_, J, R, W, N, P>
+where
+    J: Unpin,
+{
+    fn drop(&mut self) {
+        if self.scheduler.slots[self.slot].result.close_receiver() {
+            self.scheduler.free_slot(self.slot);
+        }
+    }
+}
+
+/// A worker of a [`Scheduler`], created with [`Scheduler::worker`].
+pub struct Worker<'scheduler, J, R, F, const W: usize, const N: usize, const P: usize = 1>
+where
+    J: Unpin,
+{
+    scheduler: &'scheduler Scheduler<J, R, W, N, P>,
+    handler: F,
+}
+
+impl<J, R, F, Fut, const W: usize, const N: usize, const P: usize> Worker<'_, J, R, F, W, N, P>
+where
+    J: Unpin,
+    F: FnMut(J) -> Fut,
+    Fut: Future<Output = R>,
+{
+    /// Drive this worker.
+    ///
+    /// The returned future resolves once the scheduler is shut down and no jobs are left.
+    pub async fn run(mut self) {
+        let scheduler = self.scheduler;
+
+        while let Some((job, slot)) = poll_fn(|cx| scheduler.poll_job(cx)).await {
+            let running = Running { scheduler, slot };
+            if running.is_canceled(None) {
+                debug!("Dropping canceled job in slot {}", slot);
+                continue;
+            }
+
+            trace!("Running job in slot {}", slot);
+            let mut work = pin!((self.handler)(job));
+            let result = poll_fn(|cx| {
+                if running.is_canceled(Some(cx)) {
+                    return Poll::Ready(None);
+                }
+                work.as_mut().poll(cx).map(Some)
+            })
+            .await;
+            let Some(result) = result else {
+                debug!("Aborted canceled job in slot {}", slot);
+                continue;
+            };
+            core::mem::forget(running);
+
+            if scheduler.slots[slot].result.send(result) {
+                scheduler.free_slot(slot);
+            }
+        }
+
+        debug!("Worker stopped");
+    }
+}
+
+impl<J, R, const W: usize, const N: usize, const P: usize> Running<'_, J, R, W, N, P>
+where
+    J: Unpin,
+{
+    /// Returns true if the job was canceled by its handle. With `cx`, the worker is woken
+    /// once the handle is dropped, if it was not yet.
+    fn is_canceled(&self, cx: Option<&mut Context<'_>>) -> bool {
+        let slot = &self.scheduler.slots[self.slot];
+        let dropped = match cx {
+            Some(cx) => slot.result.poll_receiver_done(cx).is_ready(),
+            None => slot.result.is_receiver_done(),
+        };
+        // The flag is set before the handle is dropped
+        dropped && slot.canceled.load(Ordering::Acquire)
+    }
+}
+
+/// Completes the job in `slot` as canceled if the worker is dropped while running it,
+/// or once it was canceled.
+struct Running<'scheduler, J, R, const W: usize, const N: usize, const P: usize>
+where
+    J: Unpin,
+{
+    scheduler: &'scheduler Scheduler<J, R, W, N, P>,
+    slot: usize,
+}
+
+impl<J, R, const W: usize, const N: usize, const P: usize> Drop for Running<'_, J, R, W, N, P>
+where
+    J: Unpin,
+{
+    fn drop(&mut self) {
+        if self.scheduler.slots[self.slot].result.close_sender() {
+            self.scheduler.free_slot(self.slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::{sync::Mutex, vec::Vec};
+
+    use super::Scheduler;
+
+    #[derive(Debug)]
+    enum Command {
+        Square(u32),
+        Negate(i32),
+    }
+
+    async fn handle(command: Command) -> i64 {
+        tokio::task::yield_now().await;
+        match command {
+            Command::Square(x) => x as i64 * x as i64,
+            Command::Negate(x) => -(x as i64),
+        }
+    }
+
+    #[tokio::test]
+    async fn worker_pool() {
+        static S: Scheduler<Command, i64, 4, 4> = Scheduler::new();
+
+        let workers: Vec<_> = (0..2)
+            .map(|_| tokio::task::spawn(S.worker(handle).run()))
+            .collect();
+
+        let mut handles = Vec::new();
+        for i in 0..4 {
+            handles.push(S.spawn(Command::Square(i)).await.unwrap());
+        }
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.await, Ok(i as i64 * i as i64));
+        }
+        // More jobs than slots, so spawning has to wait for completed jobs
+        for i in 0..8 {
+            // Dropping the handle does not cancel the job
+            drop(S.spawn(Command::Negate(i)).await.unwrap());
+        }
+
+        let last = S.spawn(Command::Negate(8)).await.unwrap();
+        S.shutdown();
+        assert!(S.spawn(Command::Square(9)).await.is_err());
+
+        // Jobs submitted before the shutdown are still processed
+        assert_eq!(last.await, Ok(-8));
+        for worker in workers {
+            worker.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn priority_and_cancel() {
+        static S: Scheduler<u32, u32, 4, 4, 2> = Scheduler::new();
+        static STARTED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+        let low = S.spawn(1).await.unwrap();
+        let canceled = S.spawn(2).await.unwrap();
+        let high = S.spawn_with_priority(3, 1).await.unwrap();
+        canceled.cancel();
+
+        let worker = tokio::task::spawn(
+            S.worker(|job| async move {
+                STARTED.lock().unwrap().push(job);
+                if job == 4 {
+                    // Runs until it is canceled
+                    core::future::pending::<()>().await;
+                }
+                job * 10
+            })
+            .run(),
+        );
+
+        // The job with the higher priority is taken first, and the canceled one never runs
+        assert_eq!(high.await, Ok(30));
+        assert_eq!(low.await, Ok(10));
+
+        let running = S.spawn(4).await.unwrap();
+        while STARTED.lock().unwrap().len() < 3 {
+            tokio::task::yield_now().await;
+        }
+        running.cancel();
+        S.shutdown();
+        worker.await.unwrap();
+        assert_eq!(*STARTED.lock().unwrap(), [3, 1, 4]);
+    }
+}
@@ -0,0 +1,50 @@
+use heapless::spsc::Queue as HQueue;
+
+use super::{Queue, Split};
+
+/// Async behavior for an existing [`heapless::spsc::Queue`].
+///
+/// On creation, all items are moved from the wrapped queue into an async [`Queue`]. Once the
+/// [`AsyncRef`] is dropped, the items that are left are moved back into the wrapped queue. This
+/// allows code that owns a [`heapless::spsc::Queue`] to hand it to async code temporarily.
+///
+/// Note that an [`AsyncRef`] stores its own copy of the queue storage.
+pub struct AsyncRef<'a, T, const N: usize>
+where
+    T: Unpin,
+{
+    source: &'a mut HQueue<T, N>,
+    queue: Queue<T, N>,
+}
+
+impl<'a, T, const N: usize> AsyncRef<'a, T, N>
+where
+    T: Unpin,
+{
+    /// Wrap `source` with async behavior.
+    pub fn new(source: &'a mut HQueue<T, N>) -> Self {
+        let mut queue = Queue::from(core::mem::take(source));
+        // The items have to fit back into `source`, which keeps one slot free.
+        queue.inner.limit(source.capacity());
+        Self { source, queue }
+    }
+
+    /// Split the wrapped queue into a producer and consumer
+    pub fn split(&mut self) -> Split<'_, T, N> {
+        self.queue.split()
+    }
+}
+
+impl<T, const N: usize> Drop for AsyncRef<'_, T, N>
+where
+    T: Unpin,
+{
+    fn drop(&mut self) {
+        // SAFETY: we have exclusive access to the queue.
+        while let Some(value) = unsafe { self.queue.inner.dequeue() } {
+            // SAFETY: `source` was emptied on creation, and has the same capacity
+            // as `queue`.
+            unsafe { self.source.enqueue_unchecked(value) };
+        }
+    }
+}
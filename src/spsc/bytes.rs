@@ -0,0 +1,582 @@
+//! Helpers for queues of bytes, e.g. a buffer between a UART and its parser.
+
+use core::{future::poll_fn, mem::MaybeUninit, task::Poll};
+
+use heapless::Vec;
+
+use super::{Consumer, Producer, Storage};
+
+/// The error returned by [`Consumer::read_until`] and [`Consumer::read_line`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineError {
+    /// The line does not fit into the buffer.
+    ///
+    /// The bytes that did fit were appended to the buffer, and the rest of the
+    /// line, up to and including the delimiter, was discarded.
+    TooLong,
+    /// The stream was finished before the delimiter was found.
+    ///
+    /// The bytes of the incomplete line were appended to the buffer.
+    Finished,
+}
+
+impl<const N: usize, B> Producer<'_, u8, N, B>
+where
+    B: Storage<u8, N>,
+{
+    /// Wait for free space, and borrow the contiguous free region at the tail of the queue.
+    ///
+    /// Bytes written into the window are added to the queue by [`Producer::commit`]. If the
+    /// free space wraps around the end of the backing buffer, the window only reaches up to
+    /// the end of the buffer, and the next window starts at its beginning. The window is
+    /// not cleared, so it holds the bytes that were last written into its slots, or zeroes.
+    pub async fn write_window(&mut self) -> &mut [u8] {
+        self.wait_for_capacity(1).await;
+
+        if !self.initialized {
+            // The slots may never have been written, and bytes have no invalid values. Once
+            // they were, they stay initialized, as dequeueing a byte leaves it in its slot.
+            // SAFETY: we are the only producer, and no window is in use.
+            unsafe { self.queue.inner.fill_free(0) };
+            self.initialized = true;
+        }
+
+        // SAFETY: we are the only producer.
+        let window = unsafe { self.queue.inner.tail_region() };
+        // SAFETY: all slots that hold no item were initialized.
+        unsafe { &mut *(window as *mut [MaybeUninit<u8>] as *mut [u8]) }
+    }
+
+    /// Add the first `amount` bytes of the last [`Producer::write_window`] to the queue.
+    ///
+    /// Returns false if the consumer should have been woken, but waking failed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `amount` is larger than the window.
+    pub fn commit(&mut self, amount: usize) -> bool {
+        self.commit_window(amount);
+        self.notify_consumer()
+    }
+
+    /// Enqueue the bytes of `bufs`, one after the other, without copying them into a
+    /// single buffer first.
+    ///
+    /// The returned future resolves once there is free space, and enqueues as many bytes
+    /// as fit at that point. Returns the amount of bytes that were enqueued, which is
+    /// only zero if `bufs` holds no bytes, or once the [`Consumer`] was dropped.
+    pub async fn write_vectored(&mut self, bufs: &[&[u8]]) -> usize {
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let mut written = 0;
+
+        while written < total {
+            if (written > 0 && self.queue.inner.is_full()) || self.is_disconnected() {
+                break;
+            }
+            let window = self.write_window().await;
+            let amount = gather(bufs, written, window);
+            self.commit_window(amount);
+            written += amount;
+        }
+
+        if written > 0 {
+            self.notify_consumer_or_defer();
+        }
+        written
+    }
+
+    fn commit_window(&mut self, amount: usize) {
+        let queue = self.queue;
+
+        // SAFETY: we are the only producer, and the window was initialized.
+        unsafe {
+            let window = queue.inner.tail_region();
+            assert!(
+                amount <= window.len(),
+                "committed more bytes than the window"
+            );
+            queue.inner.commit(amount);
+        }
+        queue.core.metrics.enqueued_many(amount);
+    }
+}
+
+impl<const N: usize, B> Consumer<'_, u8, N, B>
+where
+    B: Storage<u8, N>,
+{
+    /// Wait for bytes, and borrow the contiguous region of bytes at the head of the queue.
+    ///
+    /// The bytes stay in the queue until they are removed by [`Consumer::release`]. If the
+    /// bytes wrap around the end of the backing buffer, the window only reaches up to the
+    /// end of the buffer, and the next window starts at its beginning. Resolves to an empty
+    /// window once the stream is finished.
+    ///
+    /// If the producer drops or replaces items, it waits for space while
+    /// a window is open.
+    pub async fn read_window(&mut self) -> &[u8] {
+        poll_fn(|cx| {
+            if self.changed() {
+                return Poll::Ready(());
+            }
+            if self.try_register_waker(cx.waker()).is_none() || self.changed() {
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        })
+        .await;
+
+        if !self.window {
+            let head = poll_fn(|cx| match self.lock_head() {
+                Some(head) => Poll::Ready(head),
+                None => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+            // The lock is released by `release`, or by splitting the queue again.
+            core::mem::forget(head);
+            self.window = true;
+        }
+
+        // SAFETY: we are the only consumer, and hold the head lock.
+        unsafe { self.queue.inner.head_region() }
+    }
+
+    /// Remove the first `amount` bytes of the last [`Consumer::read_window`] from the queue,
+    /// and close the window.
+    ///
+    /// Returns false if the producer should have been woken, but waking failed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `amount` is larger than the window, or if no window is open.
+    pub fn release(&mut self, amount: usize) -> bool {
+        self.release_window(amount);
+        self.notify_producer()
+    }
+
+    /// Dequeue bytes into `bufs`, filling one after the other, without copying them out
+    /// of a single buffer afterwards.
+    ///
+    /// The returned future resolves once there are bytes in the queue, and dequeues as
+    /// many as are available at that point and fit into `bufs`. Returns the amount of
+    /// bytes that were dequeued, which is zero once the stream is finished, or if `bufs`
+    /// has no room.
+    pub async fn read_vectored(&mut self, bufs: &mut [&mut [u8]]) -> usize {
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let mut read = 0;
+
+        while read < total {
+            if read > 0 && self.is_empty() {
+                break;
+            }
+            let window = self.read_window().await;
+            if window.is_empty() {
+                // The stream is finished
+                self.release_window(0);
+                break;
+            }
+            let amount = scatter(window, bufs, read);
+            self.release_window(amount);
+            read += amount;
+        }
+
+        if read > 0 {
+            self.notify_producer_or_defer();
+        }
+        read
+    }
+
+    fn release_window(&mut self, amount: usize) {
+        let queue = self.queue;
+        assert!(self.window, "released bytes without an open window");
+
+        // SAFETY: we are the only consumer, and hold the head lock.
+        unsafe {
+            let window = queue.inner.head_region();
+            assert!(
+                amount <= window.len(),
+                "released more bytes than the window"
+            );
+            queue.inner.consume(amount);
+        }
+        queue.core.metrics.dequeued_many(amount);
+
+        // SAFETY: the guard was forgotten when the window was opened.
+        unsafe { queue.head_lock.force_unlock() };
+        self.window = false;
+    }
+
+    /// Wait until `ready` accepts the bytes at the head of the queue, and open a window
+    /// over all of them.
+    ///
+    /// `ready` is called with the bytes up to the end of the backing buffer, the bytes that
+    /// wrap around to its start, and whether no more bytes can arrive before some are
+    /// released, because the queue is full or the stream is finished. The window is closed
+    /// by [`Consumer::release_regions`].
+    #[cfg(feature = "framing")]
+    pub(crate) async fn read_regions(
+        &mut self,
+        mut ready: impl FnMut(&[u8], &[u8], bool) -> bool,
+    ) -> (&mut [u8], &mut [u8]) {
+        poll_fn(|cx| {
+            if !self.window {
+                let Some(head) = self.lock_head() else {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                };
+                // The lock is released by `release_regions`, or by splitting the queue again.
+                core::mem::forget(head);
+                self.window = true;
+            }
+
+            if self.regions_ready(&mut ready) {
+                return Poll::Ready(());
+            }
+            if self.try_register_waker(cx.waker()).is_none() || self.regions_ready(&mut ready) {
+                // Check again after registering, in case the producer
+                // woke the old waker in between.
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        })
+        .await;
+
+        self.regions()
+    }
+
+    #[cfg(feature = "framing")]
+    fn regions_ready(&self, ready: &mut impl FnMut(&[u8], &[u8], bool) -> bool) -> bool {
+        let queue = self.queue;
+        let stuck = queue.inner.is_full() || queue.core.is_finished();
+        // SAFETY: we are the only consumer, and hold the head lock.
+        let (first, second) = unsafe { queue.inner.head_regions() };
+        ready(first, second, stuck)
+    }
+
+    /// The bytes in the window opened by [`Consumer::read_regions`].
+    #[cfg(feature = "framing")]
+    pub(crate) fn regions(&mut self) -> (&mut [u8], &mut [u8]) {
+        debug_assert!(self.window);
+        // SAFETY: we are the only consumer, and hold the head lock.
+        unsafe { self.queue.inner.head_regions() }
+    }
+
+    /// Remove the first `amount` bytes of the window opened by [`Consumer::read_regions`]
+    /// from the queue, and close the window.
+    #[cfg(feature = "framing")]
+    pub(crate) fn release_regions(&mut self, amount: usize) {
+        let queue = self.queue;
+        assert!(amount <= self.len(), "released more bytes than the window");
+
+        // SAFETY: we are the only consumer, and hold the head lock.
+        unsafe {
+            let first = queue.inner.head_region().len().min(amount);
+            queue.inner.consume(first);
+            queue.inner.consume(amount - first);
+        }
+        queue.core.metrics.dequeued_many(amount);
+
+        debug_assert!(self.window);
+        // SAFETY: the guard was forgotten when the window was opened.
+        unsafe { queue.head_lock.force_unlock() };
+        self.window = false;
+        self.notify_producer_or_defer();
+    }
+
+    /// Dequeue bytes into `buf` until `delimiter` is found.
+    ///
+    /// The delimiter is appended to `buf` as well. Returns the amount of bytes
+    /// that were appended, or a [`LineError`] if `buf` ran out of space or the
+    /// stream was finished first.
+    pub async fn read_until<const M: usize>(
+        &mut self,
+        delimiter: u8,
+        buf: &mut Vec<u8, M>,
+    ) -> Result<usize, LineError> {
+        let mut appended = 0;
+        let mut too_long = false;
+
+        loop {
+            let Ok(byte) = self.dequeue().await else {
+                return Err(LineError::Finished);
+            };
+
+            if !too_long {
+                if buf.push(byte).is_ok() {
+                    appended += 1;
+                } else {
+                    too_long = true;
+                }
+            }
+
+            if byte == delimiter {
+                break;
+            }
+        }
+
+        if too_long {
+            Err(LineError::TooLong)
+        } else {
+            Ok(appended)
+        }
+    }
+
+    /// Dequeue a line into `buf`.
+    ///
+    /// Like [`Consumer::read_until`] with `b'\n'`, but the line ending, `\n` or `\r\n`,
+    /// is not kept in `buf`. Returns the length of the line without its ending.
+    pub async fn read_line<const M: usize>(
+        &mut self,
+        buf: &mut Vec<u8, M>,
+    ) -> Result<usize, LineError> {
+        let mut len = self.read_until(b'\n', buf).await?;
+
+        for ending in [b'\n', b'\r'] {
+            if buf.last() == Some(&ending) {
+                buf.pop();
+                len -= 1;
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+/// Copy the bytes of `bufs`, skipping the first `skip`, into `dst`.
+///
+/// Returns the amount of bytes that were copied.
+fn gather(bufs: &[&[u8]], mut skip: usize, dst: &mut [u8]) -> usize {
+    let mut copied = 0;
+    for buf in bufs {
+        if skip >= buf.len() {
+            skip -= buf.len();
+            continue;
+        }
+        let src = &buf[skip..];
+        skip = 0;
+        let amount = src.len().min(dst.len() - copied);
+        dst[copied..copied + amount].copy_from_slice(&src[..amount]);
+        copied += amount;
+        if copied == dst.len() {
+            break;
+        }
+    }
+    copied
+}
+
+/// Copy `src` into `bufs`, skipping the first `skip` bytes of room.
+///
+/// Returns the amount of bytes that were copied.
+fn scatter(src: &[u8], bufs: &mut [&mut [u8]], mut skip: usize) -> usize {
+    let mut copied = 0;
+    for buf in bufs {
+        if skip >= buf.len() {
+            skip -= buf.len();
+            continue;
+        }
+        let dst = &mut buf[skip..];
+        skip = 0;
+        let amount = dst.len().min(src.len() - copied);
+        dst[..amount].copy_from_slice(&src[copied..copied + amount]);
+        copied += amount;
+        if copied == src.len() {
+            break;
+        }
+    }
+    copied
+}
+
+#[cfg(feature = "embedded-io")]
+impl<const N: usize, B> embedded_io_async::ErrorType for Producer<'_, u8, N, B>
+where
+    B: Storage<u8, N>,
+{
+    type Error = core::convert::Infallible;
+}
+
+/// Writes bytes with [`Producer::write_vectored`], so a write returns `Ok(0)` once the
+/// [`Consumer`] was dropped. Flushing waits like [`Producer::flush`].
+#[cfg(feature = "embedded-io")]
+impl<const N: usize, B> embedded_io_async::Write for Producer<'_, u8, N, B>
+where
+    B: Storage<u8, N>,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.write_vectored(&[buf]).await)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Producer::flush(self).await;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+impl<const N: usize, B> embedded_io_async::ErrorType for Consumer<'_, u8, N, B>
+where
+    B: Storage<u8, N>,
+{
+    type Error = core::convert::Infallible;
+}
+
+/// Reads bytes with [`Consumer::read_vectored`], so a read returns `Ok(0)` once the
+/// stream is finished.
+#[cfg(feature = "embedded-io")]
+impl<const N: usize, B> embedded_io_async::Read for Consumer<'_, u8, N, B>
+where
+    B: Storage<u8, N>,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.read_vectored(&mut [buf]).await)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::boxed::Box;
+
+    use heapless::Vec;
+
+    use super::LineError;
+    use crate::spsc::{Queue, Split};
+
+    #[tokio::test]
+    async fn lines() {
+        let queue: &'static mut Queue<u8, 8> = Box::leak(Box::default());
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        tokio::task::spawn(async move {
+            for &byte in b"OK\r\n+CSQ: 31,99\r\nERROR\nRI" {
+                tx.enqueue(byte).await.unwrap();
+            }
+            tx.finish().await;
+        });
+
+        let mut line: Vec<u8, 8> = Vec::new();
+        assert_eq!(rx.read_line(&mut line).await, Ok(2));
+        assert_eq!(line, b"OK");
+
+        // The rest of the overlong line is discarded
+        line.clear();
+        assert_eq!(rx.read_line(&mut line).await, Err(LineError::TooLong));
+        assert_eq!(line, b"+CSQ: 31");
+
+        line.clear();
+        assert_eq!(rx.read_until(b'\n', &mut line).await, Ok(6));
+        assert_eq!(line, b"ERROR\n");
+
+        line.clear();
+        assert_eq!(rx.read_line(&mut line).await, Err(LineError::Finished));
+        assert_eq!(line, b"RI");
+    }
+
+    #[tokio::test]
+    async fn windows() {
+        let queue: &'static mut Queue<u8, 8> = Box::leak(Box::default());
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let window = tx.write_window().await;
+        assert_eq!(window.len(), 8);
+        window[..5].copy_from_slice(b"hello");
+        tx.commit(5);
+
+        assert_eq!(rx.read_window().await, b"hello");
+        rx.release(3);
+
+        // The free space wraps around, so the window ends at the end of the buffer
+        let window = tx.write_window().await;
+        assert_eq!(window.len(), 3);
+        window.copy_from_slice(b" wo");
+        tx.commit(3);
+        let window = tx.write_window().await;
+        assert_eq!(window.len(), 3);
+        window[..2].copy_from_slice(b"rl");
+        tx.commit(2);
+
+        assert_eq!(rx.read_window().await, b"lo wo");
+        rx.release(5);
+        assert_eq!(rx.read_window().await, b"rl");
+        rx.release(2);
+
+        let reader = tokio::task::spawn(async move {
+            let window = rx.read_window().await;
+            assert_eq!(window, b"d");
+            rx.release(1);
+            assert!(rx.read_window().await.is_empty());
+        });
+
+        tokio::task::yield_now().await;
+        tx.write_window().await[0] = b'd';
+        tx.commit(1);
+        tx.finish().await;
+        reader.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn vectored() {
+        let queue: &'static mut Queue<u8, 8> = Box::leak(Box::default());
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        assert_eq!(tx.write_vectored(&[b"hd", b"", b"body"]).await, 6);
+        let (mut header, mut body) = ([0; 2], [0; 3]);
+        assert_eq!(rx.read_vectored(&mut [&mut header, &mut body]).await, 5);
+        assert_eq!((&header, &body), (b"hd", b"bod"));
+
+        // The bytes that fit are enqueued, wrapping around the end of the buffer
+        assert_eq!(tx.write_vectored(&[b"HD", b"payload!"]).await, 7);
+        let mut all = [0; 16];
+        assert_eq!(rx.read_vectored(&mut [&mut all]).await, 8);
+        assert_eq!(&all[..8], b"yHDpaylo");
+
+        tx.finish().await;
+        assert_eq!(rx.read_vectored(&mut [&mut all]).await, 0);
+    }
+
+    #[cfg(feature = "embedded-io")]
+    #[tokio::test]
+    async fn embedded_io() {
+        use embedded_io_async::{Read, Write};
+
+        let queue: &'static mut Queue<u8, 4> = Box::leak(Box::default());
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let reader = tokio::task::spawn(async move {
+            let mut buf = [0; 6];
+            rx.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello!");
+            // The stream is finished once everything was read
+            assert_eq!(rx.read(&mut buf).await, Ok(0));
+        });
+
+        tx.write_all(b"hello!").await.unwrap();
+        Write::flush(&mut tx).await.unwrap();
+        tx.finish().await;
+        reader.await.unwrap();
+
+        // Nothing is written once the consumer is gone, even if the queue is full
+        let queue: &'static mut Queue<u8, 1> = Box::leak(Box::default());
+        let Split {
+            producer: mut tx,
+            consumer: rx,
+        } = queue.split();
+        assert_eq!(tx.write(b"ab").await, Ok(1));
+        drop(rx);
+        assert_eq!(tx.write(b"b").await, Ok(0));
+    }
+}
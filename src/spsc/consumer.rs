@@ -1,21 +1,47 @@
 use core::{
-    future::Future,
-    task::{Poll, Waker},
+    future::{poll_fn, Future},
+    mem::MaybeUninit,
+    ops::{ControlFlow, Deref, DerefMut},
+    sync::atomic::Ordering,
+    task::{Context, Poll, Waker},
 };
 
-use heapless::spsc::Consumer as HConsumer;
+#[cfg(feature = "futures")]
+use futures_core::future::FusedFuture;
 
-use crate::{log::*, mutex::Mutex, waker::WakerRegistration};
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::{Waiter, WaiterName};
+use crate::{
+    group::GroupMember,
+    instrument::Side,
+    log::*,
+    metrics::{Metrics, MetricsSource},
+    mutex::MutexGuard,
+    waker::{Name, WakerRegistration, NO_NAME},
+};
+
+use heapless::Vec;
+
+use super::{Owned, Queue, Storage, WakerState};
 
 /// This error may be returned by [`Consumer::try_dequeue`].
+///
+/// Instead of retrying in a loop, an operation that should be
+/// retried can be passed to [`retry`](crate::retry::retry).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ConsumerError<T> {
     /// Waking the producer would block.
     ///
+    /// Holds the value if one was dequeued anyway, in which case it is not lost,
+    /// but the producer may not notice the free slot until it is woken with
+    /// [`Consumer::try_wake_producer`].
+    ///
     /// Retrying is possible, but is highly discouraged as it
     /// may block forever.
     ///
     /// It only works if releasing the lock held by the producer
-    /// preempts the code that performs the retries.    
+    /// preempts the code that performs the retries.
     WouldBlock(Option<T>),
     /// The queue is empty.
     ///
@@ -25,62 +51,560 @@ pub enum ConsumerError<T> {
     /// It only works if enqueueing an item into the backing
     /// queue preempts the code that performs the retries.
     Empty,
+    /// The queue is empty, and the producer has finished the stream.
+    Finished,
+    /// The queue is empty, and the producer was dropped without finishing the stream.
+    Disconnected,
+}
+
+impl<T> ConsumerError<T> {
+    /// Returns the value that was dequeued anyway, if any.
+    pub fn value(&self) -> Option<&T> {
+        match self {
+            Self::WouldBlock(value) => value.as_ref(),
+            Self::Empty | Self::Finished | Self::Disconnected => None,
+        }
+    }
+
+    /// Take the value that was dequeued anyway, if any.
+    pub fn into_value(self) -> Option<T> {
+        match self {
+            Self::WouldBlock(value) => value,
+            Self::Empty | Self::Finished | Self::Disconnected => None,
+        }
+    }
+}
+
+impl<T> core::fmt::Display for ConsumerError<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::WouldBlock(_) => "waking the producer would block",
+            Self::Empty => "the queue is empty",
+            Self::Finished => "the stream is finished",
+            Self::Disconnected => "the producer was dropped",
+        })
+    }
+}
+
+impl<T> core::error::Error for ConsumerError<T> where T: core::fmt::Debug {}
+
+/// The error that dequeues resolve to once the [`Producer`](super::Producer) has
+/// finished the stream, and all items have been dequeued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Finished;
+
+impl core::fmt::Display for Finished {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("the stream is finished")
+    }
 }
 
+impl core::error::Error for Finished {}
+
 /// An async consumer
-pub struct Consumer<'queue, T, const N: usize>
+///
+/// Once it is dropped, the [`Producer`](super::Producer) no longer waits for room.
+pub struct Consumer<'queue, T, const N: usize, B = Owned<T, N>>
 where
     T: Unpin,
+    B: Storage<T, N>,
 {
-    inner: HConsumer<'queue, T, N>,
-    producer_waker: &'queue Mutex<WakerRegistration>,
-    consumer_waker: &'queue Mutex<WakerRegistration>,
+    pub(super) queue: &'queue Queue<T, N, B>,
+    /// Whether a read window is open. The head lock is held while it is.
+    pub(super) window: bool,
+    /// Whether the head lock is held since the last [`Consumer::peek`].
+    peeked: bool,
+    name: Name,
 }
 
-impl<'queue, T, const N: usize> Consumer<'queue, T, N>
+impl<'queue, T, const N: usize, B> Consumer<'queue, T, N, B>
 where
     T: Unpin,
+    B: Storage<T, N>,
 {
-    pub(crate) fn new(
-        consumer: HConsumer<'queue, T, N>,
-        producer_waker: &'queue Mutex<WakerRegistration>,
-        consumer_waker: &'queue Mutex<WakerRegistration>,
-    ) -> Self {
+    pub(crate) fn new(queue: &'queue Queue<T, N, B>) -> Self {
         Self {
-            inner: consumer,
-            producer_waker,
-            consumer_waker,
+            queue,
+            window: false,
+            peeked: false,
+            name: NO_NAME,
         }
     }
 
+    /// Set the name that is reported for this consumer while it waits.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_name(&mut self, name: impl Into<WaiterName>) {
+        self.name = Some(name.into());
+    }
+
+    /// Call `f` for every task that is waiting on the backing queue.
+    #[cfg(feature = "diagnostics")]
+    pub fn waiters(&self, f: impl FnMut(Waiter)) {
+        self.queue.waiters(f)
+    }
+
     /// Check if there are any items to dequeue.
     ///
     /// When this returns true, at least the first subsequent [`Self::dequeue`] will succeed immediately
     pub fn ready(&self) -> bool {
-        self.inner.ready()
+        !self.is_empty()
+    }
+
+    /// Returns true if the [`Producer`](super::Producer) has finished the stream,
+    /// and all items have been dequeued.
+    ///
+    /// Dropping the producer finishes the stream too, which [`Consumer::is_disconnected`]
+    /// tells apart.
+    pub fn is_finished(&self) -> bool {
+        self.queue.core.is_finished() && self.is_empty()
+    }
+
+    /// Returns true if the queue was closed, with [`Producer::close`](super::Producer::close)
+    /// or [`Producer::finish`](super::Producer::finish), or by dropping the producer.
+    ///
+    /// Unlike [`Consumer::is_finished`], this is true as soon as the stream is finished,
+    /// while there may still be items to dequeue.
+    pub fn is_closed(&self) -> bool {
+        self.queue.core.is_finished()
+    }
+
+    /// Returns true if the [`Producer`](super::Producer) was dropped without finishing
+    /// the stream.
+    ///
+    /// The items it enqueued before that can still be dequeued. Once they are, the
+    /// dequeues resolve to [`Finished`] like for a finished stream, but
+    /// [`Consumer::try_dequeue`] returns [`ConsumerError::Disconnected`] instead of
+    /// [`ConsumerError::Finished`].
+    pub fn is_disconnected(&self) -> bool {
+        self.queue.producer_dropped.load(Ordering::Acquire)
     }
 
     /// Returns the maximum number of elements the queue can hold
     pub fn capacity(&self) -> usize {
-        self.inner.capacity()
+        self.queue.inner.capacity()
     }
 
     /// Returns the amount of elements currently in the queue
     pub fn len(&self) -> usize {
-        self.inner.len()
+        self.queue.inner.len()
+    }
+
+    /// Returns true if the queue currently holds no elements
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the [`Metrics`] of the backing queue, if it was
+    /// built with metrics enabled.
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.queue.core.metrics()
+    }
+
+    /// Returns a [`MetricsSource`] for the metrics of the backing queue, which can
+    /// be kept after this handle was moved elsewhere.
+    pub fn metrics_source(&self) -> MetricsSource<'queue> {
+        MetricsSource::new(&self.queue.core.metrics)
     }
 
     /// Dequeue an item from the backing queue.
     ///
     /// The returned future only resolves once an item was succesfully
-    /// dequeued.
-    pub fn dequeue<'me>(&'me mut self) -> ConsumerFuture<'me, 'queue, T, N> {
+    /// dequeued, or to [`Finished`] once the [`Producer`](super::Producer) has
+    /// finished the stream and all items have been dequeued.
+    ///
+    /// An item is only dequeued when the future resolves, so it can be dropped
+    /// at any time, e.g. in a `select`, without losing an item.
+    #[must_use = "no item is dequeued unless the returned future is awaited"]
+    pub fn dequeue<'me>(&'me mut self) -> ConsumerFuture<'me, 'queue, T, N, B> {
         ConsumerFuture {
             consumer: self,
-            dequeued_value: None,
+            registration: None,
+            terminated: false,
         }
     }
 
+    /// Dequeue an item from a hand-written future, without a [`Consumer::dequeue`] future.
+    ///
+    /// If no item is available yet, the waker of `cx` is woken once one may be.
+    pub fn poll_dequeue(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, Finished>> {
+        self.poll_pop(cx, &mut None)
+    }
+
+    /// Dequeue up to `max` items at once, by passing them to `f` in place.
+    ///
+    /// The returned future resolves once at least one item is available. `f` is then
+    /// called with the contiguous region of items at the head of the queue, and returns
+    /// how many of the items at the start of the region it consumed. The consumed items
+    /// are dropped and removed from the queue. If the items wrap around the end of the
+    /// backing buffer and `f` consumed all items of the first region, it is called again
+    /// with the rest.
+    ///
+    /// Resolves to the total amount of items that were consumed, which is only zero
+    /// once the stream is finished.
+    #[must_use = "no items are dequeued unless the returned future is awaited"]
+    pub fn dequeue_n_with<'me, F>(
+        &'me mut self,
+        max: usize,
+        f: F,
+    ) -> DequeueNWithFuture<'me, 'queue, T, N, F, B>
+    where
+        F: FnMut(&mut [T]) -> usize,
+    {
+        DequeueNWithFuture {
+            consumer: self,
+            max,
+            f,
+            registration: None,
+            terminated: false,
+        }
+    }
+
+    /// Move the items that are immediately available into `buf`, without waiting.
+    ///
+    /// Returns how many items were moved into the start of `buf`, which are initialized
+    /// afterwards. This is zero if the queue is empty, or if the producer is currently
+    /// dropping or replacing an item to make room for a new one.
+    pub fn dequeue_many(&mut self, buf: &mut [MaybeUninit<T>]) -> usize {
+        let moved = self.pop_many(buf).unwrap_or(0);
+        if moved > 0 {
+            self.notify_producer_or_defer();
+        }
+        moved
+    }
+
+    /// Move items into `buf` until at least `n` of them were moved.
+    ///
+    /// The returned future takes every item that is available whenever it is polled,
+    /// and resolves once at least `n` items were moved into the start of `buf`, or once
+    /// the stream is finished. Resolves to the amount of items that were moved, which is
+    /// only less than `n` if the stream is finished.
+    ///
+    /// If the future is dropped before it resolves, the items that were already moved
+    /// into `buf` are not dropped.
+    ///
+    /// # Panics
+    /// Panics if `n` is larger than the length of `buf`.
+    pub async fn dequeue_at_least(&mut self, buf: &mut [MaybeUninit<T>], n: usize) -> usize {
+        assert!(n <= buf.len(), "The buffer can not hold {} items", n);
+
+        let mut moved = 0;
+        poll_fn(|cx| loop {
+            // The producer finishes after its last enqueue, so the queue has to
+            // be drained once it has finished.
+            let finished = self.is_finished();
+            match self.pop_many(&mut buf[moved..]) {
+                Some(0) => {}
+                Some(amount) => {
+                    moved += amount;
+                    self.notify_producer_or_defer();
+                }
+                None => {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            }
+
+            if moved >= n || finished {
+                return Poll::Ready(moved);
+            }
+            if self.poll_ready(cx).is_pending() {
+                return Poll::Pending;
+            }
+        })
+        .await
+    }
+
+    /// Wait until the queue holds at least `n` items, without dequeueing any of them.
+    ///
+    /// The returned future resolves to [`Finished`] if the stream is finished before
+    /// that, in which case the items that are left can still be dequeued. The producer
+    /// only wakes the consumer once the high watermark of the queue is reached, so if
+    /// `n` is below it, this may resolve later than once there are `n` items.
+    ///
+    /// # Panics
+    /// Panics if `n` is larger than the capacity of the queue.
+    pub async fn wait_for_len(&mut self, n: usize) -> Result<(), Finished> {
+        assert!(n <= self.capacity(), "The queue can not hold {} items", n);
+
+        let reached = |consumer: &Self| {
+            // The producer finishes after its last enqueue, so the length has to
+            // be checked again once it has finished.
+            let finished = consumer.queue.core.is_finished();
+            if consumer.len() >= n {
+                Some(Ok(()))
+            } else if finished {
+                Some(Err(Finished))
+            } else {
+                None
+            }
+        };
+
+        poll_fn(|cx| {
+            if let Some(res) = reached(self) {
+                return Poll::Ready(res);
+            }
+            if self.try_register_waker(cx.waker()).is_none() {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            // Check again, in case an item was enqueued before we registered
+            match reached(self) {
+                Some(res) => Poll::Ready(res),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Borrow the item at the head of the queue mutably, without dequeueing it.
+    ///
+    /// The returned future resolves once an item is available, or to [`Finished`] once
+    /// the stream is finished. The item can be modified in place, and stays in the queue
+    /// when the [`PeekMut`] is dropped, unless it is dequeued with [`PeekMut::pop`].
+    ///
+    /// If the producer drops or replaces items, it waits for space while
+    /// the item is borrowed.
+    pub async fn peek_mut<'me>(&'me mut self) -> Result<PeekMut<'me, 'queue, T, N, B>, Finished> {
+        let head = self.lock_filled_head().await;
+        if self.is_empty() {
+            // Nothing was enqueued, so the stream was finished.
+            return Err(Finished);
+        }
+
+        Ok(PeekMut {
+            consumer: self,
+            _head: head,
+        })
+    }
+
+    /// Borrow the item at the head of the queue, without dequeueing it.
+    ///
+    /// The returned future resolves once an item is available, or to [`Finished`] once
+    /// the stream is finished. The item stays in the queue until it is dequeued.
+    ///
+    /// If the producer drops or replaces items, it waits for space until this
+    /// consumer dequeues again.
+    pub async fn peek(&mut self) -> Result<&T, Finished> {
+        let head = self.lock_filled_head().await;
+        if self.is_empty() {
+            // Nothing was enqueued, so the stream was finished.
+            return Err(Finished);
+        }
+
+        self.keep_peeked(head);
+        // SAFETY: we are the only consumer, the queue is not empty, and we hold
+        // the head lock.
+        Ok(unsafe { &self.queue.inner.head_region()[0] })
+    }
+
+    /// Borrow the item at the head of the queue without waiting, like [`Consumer::peek`].
+    ///
+    /// Returns `None` if the queue is empty, or if the producer is dropping or replacing
+    /// an item right now.
+    pub fn try_peek(&mut self) -> Option<&T> {
+        let head = self.lock_head()?;
+        if self.is_empty() {
+            return None;
+        }
+
+        self.keep_peeked(head);
+        // SAFETY: as above.
+        Some(unsafe { &self.queue.inner.head_region()[0] })
+    }
+
+    /// Dequeue the item at the head of the queue, but only if `f` accepts it.
+    ///
+    /// The returned future resolves once an item is available, or to [`Finished`] once
+    /// the stream is finished. If `f` returns false, the item stays in the queue and
+    /// `Ok(None)` is returned.
+    pub async fn dequeue_if<F>(&mut self, f: F) -> Result<Option<T>, Finished>
+    where
+        F: FnOnce(&T) -> bool,
+    {
+        let head = self.peek_mut().await?;
+        if f(&head) {
+            Ok(Some(PeekMut::pop(head)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Dequeue the item at the head of the queue without waiting, but only if `f`
+    /// accepts it, like [`Consumer::dequeue_if`].
+    ///
+    /// Returns `None` if the queue is empty, if `f` returns false, or if the producer
+    /// is dropping or replacing an item right now.
+    pub fn try_dequeue_if<F>(&mut self, f: F) -> Option<T>
+    where
+        F: FnOnce(&T) -> bool,
+    {
+        let queue = self.queue;
+        let _head = self.lock_head()?;
+
+        // SAFETY: we are the only consumer, and hold the head lock.
+        let accepted = unsafe { queue.inner.head_region() }.first().is_some_and(f);
+        if !accepted {
+            return None;
+        }
+
+        // SAFETY: as above.
+        let value = unsafe { queue.inner.dequeue() }?;
+        queue.core.metrics.dequeued();
+        drop(_head);
+
+        self.notify_producer_or_defer();
+        Some(value)
+    }
+
+    /// Dequeue the oldest item that `f` accepts, leaving the items in front of it in the
+    /// queue, in order.
+    ///
+    /// The returned future resolves once there is an item that `f` accepts, or to
+    /// [`Finished`] once the stream is finished and `f` rejected every item that is left.
+    /// The queue is scanned again whenever an item was enqueued, so `f` may be called for
+    /// an item more than once. A rejected item keeps taking up room in the queue, so the
+    /// producer may have to wait until it is dequeued.
+    pub async fn dequeue_matching<F>(&mut self, mut f: F) -> Result<T, Finished>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let queue = self.queue;
+        poll_fn(|cx| {
+            let Some(head) = self.lock_head() else {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            };
+            // The producer finishes after its last enqueue, so the queue has to
+            // be checked again once it has finished.
+            let finished = queue.core.is_finished();
+            // The items up to here are scanned, so a wake is only needed for the ones after.
+            let scanned = queue.inner.tail_position();
+
+            // SAFETY: we are the only consumer, and hold the head lock.
+            let found = (0..queue.inner.len())
+                .find(|&index| unsafe { queue.inner.get(index) }.is_some_and(&mut f));
+            if let Some(index) = found {
+                // SAFETY: as above, and the item at `index` is in the queue.
+                let value = unsafe { queue.inner.remove(index) };
+                queue.core.metrics.dequeued();
+                drop(head);
+                self.notify_producer_or_defer();
+                return Poll::Ready(Ok(value));
+            }
+            drop(head);
+
+            if finished {
+                return Poll::Ready(Err(Finished));
+            }
+            let changed = || queue.inner.tail_position() != scanned || queue.core.is_finished();
+            if self.try_register_waker(cx.waker()).is_none() || changed() {
+                // Check again after registering, in case the producer
+                // woke the old waker in between.
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Keep holding the head lock after a peek, until the next dequeue.
+    fn keep_peeked(&mut self, head: Option<MutexGuard<'queue, ()>>) {
+        if let Some(head) = head {
+            // The lock is taken over by the next `lock_head`.
+            core::mem::forget(head);
+            self.peeked = true;
+        }
+    }
+
+    /// Wait until there is an item in the queue or the stream is finished, and lock
+    /// the head of the queue.
+    async fn lock_filled_head(&mut self) -> Option<MutexGuard<'queue, ()>> {
+        poll_fn(|cx| {
+            if !self.changed() {
+                if self.try_register_waker(cx.waker()).is_none() || self.changed() {
+                    cx.waker().wake_by_ref();
+                }
+                return Poll::Pending;
+            }
+
+            match self.lock_head() {
+                Some(head) => Poll::Ready(head),
+                None => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Fold the dequeued items into an accumulator, starting with `init`.
+    ///
+    /// Every item is dequeued as it becomes available and passed to `f` together with
+    /// the accumulator. The fold runs until `f` returns [`ControlFlow::Break`], or until
+    /// the stream is finished, and the returned future then resolves to the final
+    /// accumulator.
+    pub async fn fold<A, F>(&mut self, init: A, mut f: F) -> A
+    where
+        F: FnMut(A, T) -> ControlFlow<A, A>,
+    {
+        let mut budget = self.queue.core.budget();
+        let mut acc = init;
+        loop {
+            let Ok(item) = budget.spend(self.dequeue()).await else {
+                return acc;
+            };
+            match f(acc, item) {
+                ControlFlow::Continue(next) => acc = next,
+                ControlFlow::Break(last) => return last,
+            }
+        }
+    }
+
+    /// Run `f` for every dequeued item, until the stream is finished.
+    ///
+    /// The next item is only dequeued once the future returned by `f` for the previous
+    /// item has completed. If the returned future is dropped while waiting for an item,
+    /// no item is lost. If it is dropped while a future returned by `f` is running, the
+    /// item that it was processing is dropped with it.
+    pub async fn for_each<F, Fut>(&mut self, mut f: F)
+    where
+        F: FnMut(T) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let mut budget = self.queue.core.budget();
+        while let Ok(item) = budget.spend(self.dequeue()).await {
+            f(item).await;
+        }
+    }
+
+    /// Create an adapter that passes every dequeued item to `f`, together with
+    /// the mutable state `init`.
+    ///
+    /// Every [`Scan::next`] yields the value returned by `f`, until `f` returns
+    /// `None` like [`Iterator::scan`], or until the stream is finished.
+    pub fn scan<'me, S, O, F>(&'me mut self, init: S, f: F) -> Scan<'me, 'queue, T, N, S, F, B>
+    where
+        F: FnMut(&mut S, T) -> Option<O>,
+    {
+        Scan {
+            consumer: self,
+            state: init,
+            f: Some(f),
+        }
+    }
+
+    /// Create an adapter that dequeues items in batches of up to `K` items.
+    ///
+    /// Every [`ReadyChunks::next`] waits for an item, and then also takes every item that
+    /// is immediately available after it, until the batch is full.
+    pub fn ready_chunks<'me, const K: usize>(
+        &'me mut self,
+    ) -> ReadyChunks<'me, 'queue, T, N, K, B> {
+        ReadyChunks::new(self)
+    }
+
     /// Attempt to dequeue an item from the backing queue.
     ///
     /// If [`ConsumerError::WouldBlock`] is returned, the [`Producer`](super::Producer)
@@ -88,13 +612,41 @@ where
     /// In such a case, the application can attempt to re-wake the [`Producer`](super::Producer)
     /// by calling [`Consumer::try_wake_producer`].
     pub fn try_dequeue(&mut self) -> Result<T, ConsumerError<T>> {
-        let res = if let Some(val) = self.inner.dequeue() {
-            Ok(val)
-        } else {
-            Err(ConsumerError::Empty)
-        };
+        let res = self.pop();
+
+        if !self.notify_producer() {
+            return Err(ConsumerError::WouldBlock(res.ok()));
+        }
+
+        res
+    }
+
+    /// Attempt to dequeue an item, retrying up to `attempts` times in total while
+    /// the queue is contended.
+    ///
+    /// Like [`Consumer::try_dequeue`], but [`ConsumerError::WouldBlock`] is only returned
+    /// if the producer was still holding the lock after the last attempt. An empty
+    /// queue is not retried.
+    pub fn try_dequeue_for(&mut self, attempts: usize) -> Result<T, ConsumerError<T>> {
+        let mut res = self.pop();
+        for _ in 1..attempts {
+            if !matches!(res, Err(ConsumerError::WouldBlock(None))) {
+                break;
+            }
+            core::hint::spin_loop();
+            res = self.pop();
+        }
+
+        let mut woken = self.notify_producer();
+        for _ in 1..attempts {
+            if woken {
+                break;
+            }
+            core::hint::spin_loop();
+            woken = self.notify_producer();
+        }
 
-        if !self.try_wake_producer() {
+        if !woken {
             return Err(ConsumerError::WouldBlock(res.ok()));
         }
 
@@ -104,10 +656,12 @@ where
     /// Try to wake the [`Producer`](super::Producer) associated with the backing queue.
     ///
     /// Returns true if the waker was waked succesfully.
+    #[must_use = "the producer may not have been woken"]
     pub fn try_wake_producer(&mut self) -> bool {
-        if let Some(mut wk) = self.producer_waker.try_lock() {
+        if let Some(mut wk) = self.queue.producer_waker.try_lock() {
             wk.wake();
             trace!("Waking producer");
+            self.queue.core.metrics.woke(Side::Enqueuers);
             true
         } else {
             trace!("Failed to wake producer");
@@ -115,69 +669,552 @@ where
         }
     }
 
+    /// Wake the [`Producer`](super::Producer) if the queue has drained to the
+    /// low watermark.
+    ///
+    /// Returns false if the producer should have been woken, but waking failed.
+    pub(super) fn notify_producer(&mut self) -> bool {
+        if self.len() > self.queue.core.config.low_watermark {
+            true
+        } else {
+            self.try_wake_producer()
+        }
+    }
+
+    /// Wake the [`Producer`](super::Producer) if the queue has drained to the
+    /// low watermark, or defer it to whoever is holding the producer waker.
+    pub(super) fn notify_producer_or_defer(&mut self) {
+        if self.len() <= self.queue.core.config.low_watermark {
+            self.queue.core.metrics.woke(Side::Enqueuers);
+            self.queue
+                .producer_waker
+                .wake_or_defer(WakerRegistration::wake);
+        }
+    }
+
+    /// Dequeue an item from the backing queue.
+    ///
+    /// Returns [`ConsumerError::WouldBlock`] if the producer is currently
+    /// dropping or replacing an item to make room for a new one.
+    pub(super) fn pop(&mut self) -> Result<T, ConsumerError<T>> {
+        let queue = self.queue;
+
+        let Some(_head) = self.lock_head() else {
+            return Err(ConsumerError::WouldBlock(None));
+        };
+
+        // The producer finishes after its last enqueue, so the queue has to
+        // be checked again once it has finished.
+        let finished = queue.core.is_finished();
+
+        // SAFETY: we are the only consumer, and hold the head lock.
+        if let Some(value) = unsafe { queue.inner.dequeue() } {
+            queue.core.metrics.dequeued();
+            Ok(value)
+        } else if finished && self.is_disconnected() {
+            Err(ConsumerError::Disconnected)
+        } else if finished {
+            Err(ConsumerError::Finished)
+        } else {
+            Err(ConsumerError::Empty)
+        }
+    }
+
+    /// Dequeue an item like [`Consumer::pop`], or register the waker of `cx`, storing
+    /// the generation of the registration in `registration`.
+    fn poll_pop(
+        &mut self,
+        cx: &mut Context<'_>,
+        registration: &mut Option<u32>,
+    ) -> Poll<Result<T, Finished>> {
+        match self.pop() {
+            Ok(value) => {
+                // Wake the producer because we managed to dequeue a value
+                self.notify_producer_or_defer();
+                Poll::Ready(Ok(value))
+            }
+            Err(ConsumerError::Finished | ConsumerError::Disconnected) => {
+                Poll::Ready(Err(Finished))
+            }
+            Err(_) => {
+                *registration = self.try_register_waker(cx.waker());
+                if registration.is_none() || self.changed() {
+                    cx.waker().wake_by_ref()
+                }
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Pass up to `max` items to `f` in place, and remove the ones it consumed.
+    ///
+    /// Returns `Err(true)` if the queue is empty, and `Err(false)` if the producer
+    /// is currently dropping or replacing an item to make room for a new one. Once the
+    /// stream is finished, an empty queue results in `Ok(0)`.
+    fn pop_n_with<F>(&mut self, max: usize, f: &mut F) -> Result<usize, bool>
+    where
+        F: FnMut(&mut [T]) -> usize,
+    {
+        let queue = self.queue;
+        let _head = self.lock_head().ok_or(false)?;
+        let finished = queue.core.is_finished();
+
+        let mut consumed = 0;
+        while consumed < max {
+            // SAFETY: we are the only consumer, and hold the head lock.
+            let region = unsafe { queue.inner.head_region() };
+            if region.is_empty() {
+                break;
+            }
+
+            let available = region.len().min(max - consumed);
+            let amount = f(&mut region[..available]).min(available);
+            // SAFETY: as above, and the region is no longer used.
+            unsafe { queue.inner.consume(amount) };
+            consumed += amount;
+
+            if amount < available {
+                break;
+            }
+        }
+
+        if consumed == 0 && self.is_empty() && !finished {
+            return Err(true);
+        }
+
+        queue.core.metrics.dequeued_many(consumed);
+        Ok(consumed)
+    }
+
+    /// Move the items that are available into the start of `buf`.
+    ///
+    /// Returns `None` if the producer is currently dropping or replacing an item to
+    /// make room for a new one.
+    fn pop_many(&mut self, buf: &mut [MaybeUninit<T>]) -> Option<usize> {
+        let queue = self.queue;
+        let _head = self.lock_head()?;
+
+        let mut moved = 0;
+        for slot in buf.iter_mut() {
+            // SAFETY: we are the only consumer, and hold the head lock.
+            let Some(value) = (unsafe { queue.inner.dequeue() }) else {
+                break;
+            };
+            slot.write(value);
+            moved += 1;
+        }
+
+        queue.core.metrics.dequeued_many(moved);
+        Some(moved)
+    }
+
+    /// Returns true if an item was enqueued, or the stream was finished.
+    ///
+    /// This is checked after registering the waker, in case the producer
+    /// woke the old waker in between.
+    pub(super) fn changed(&self) -> bool {
+        !self.is_empty() || self.queue.core.is_finished()
+    }
+
+    /// Lock the head of the queue, so that the producer does not take items out of it,
+    /// unless the lock is already held for a read window.
+    ///
+    /// Returns `None` if the producer is currently dropping or replacing an item.
+    pub(super) fn lock_head(&mut self) -> Option<Option<MutexGuard<'queue, ()>>> {
+        if self.peeked {
+            self.peeked = false;
+            // SAFETY: the guard was forgotten by the last peek.
+            return Some(Some(unsafe { self.queue.head_lock.adopt() }));
+        }
+        if self.window {
+            Some(None)
+        } else {
+            self.queue.head_lock.try_lock().map(Some)
+        }
+    }
+
     /// Try to register `waker` as the waker for this [`Consumer`]
     ///
-    /// Returns true if the waker was registered succesfully.
-    fn try_register_waker(&mut self, waker: &Waker) -> bool {
-        if let Some(mut wk) = self.consumer_waker.try_lock() {
-            wk.register(waker);
+    /// Returns the generation of the registration if the waker was registered succesfully.
+    pub(super) fn try_register_waker(&mut self, waker: &Waker) -> Option<u32> {
+        let generation = self
+            .queue
+            .consumer_waker
+            .try_lock()
+            .map(|mut wk| wk.register_named(waker, self.name));
+        if generation.is_some() {
             trace!("Registered consumer waker.");
-            true
         } else {
             trace!("Failed to register consumer waker.");
-            false
+        }
+        generation
+    }
+
+    /// Remove the waker registered in `generation`, if no other waker was
+    /// registered since.
+    pub(super) fn unregister_waker(&mut self, generation: u32) {
+        if let Some(mut wk) = self.queue.consumer_waker.try_lock() {
+            wk.unregister(generation);
         }
     }
 }
 
-pub struct ConsumerFuture<'consumer, 'queue, T, const N: usize>
+impl<T, const N: usize, B> GroupMember for Consumer<'_, T, N, B>
 where
     T: Unpin,
+    B: Storage<T, N>,
 {
-    consumer: &'consumer mut Consumer<'queue, T, N>,
-    dequeued_value: Option<T>,
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.changed() {
+            return Poll::Ready(());
+        }
+
+        if self.try_register_waker(cx.waker()).is_none() {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+
+        // Check again, in case an item was enqueued before we registered
+        if self.changed() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 }
 
-impl<T, const N: usize> Future for ConsumerFuture<'_, '_, T, N>
+impl<T, const N: usize, B> core::fmt::Debug for Consumer<'_, T, N, B>
 where
     T: Unpin,
+    B: Storage<T, N>,
 {
-    type Output = T;
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .field("waker", &WakerState::live(&self.queue.consumer_waker))
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T, const N: usize, B> defmt::Format for Consumer<'_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Consumer {{ len: {}, capacity: {}, waker: {}, finished: {} }}",
+            self.len(),
+            self.capacity(),
+            WakerState::live(&self.queue.consumer_waker),
+            self.is_finished(),
+        )
+    }
+}
+
+impl<T, const N: usize, B> Drop for Consumer<'_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn drop(&mut self) {
+        let queue = self.queue;
+        if self.peeked {
+            // SAFETY: the guard was forgotten by the last peek.
+            drop(unsafe { queue.head_lock.adopt() });
+        } else if self.window {
+            // SAFETY: the guard was forgotten when the window was opened.
+            unsafe { queue.head_lock.force_unlock() };
+        }
+
+        debug!("Consumer dropped, disconnecting");
+        queue.disconnected.store(true, Ordering::Release);
+        queue.core.metrics.woke(Side::Enqueuers);
+        queue.producer_waker.wake_or_defer(WakerRegistration::wake);
+    }
+}
+
+/// The adapter returned by [`Consumer::scan`].
+pub struct Scan<'consumer, 'queue, T, const N: usize, S, F, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    consumer: &'consumer mut Consumer<'queue, T, N, B>,
+    state: S,
+    /// The scan function, or `None` once it has ended the scan.
+    f: Option<F>,
+}
+
+impl<T, const N: usize, S, O, F, B> Scan<'_, '_, T, N, S, F, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+    F: FnMut(&mut S, T) -> Option<O>,
+{
+    /// Dequeue the next item, and pass it to the scan function.
+    ///
+    /// Resolves to `None` without dequeueing once the scan function has ended the scan,
+    /// or once the stream is finished.
+    pub async fn next(&mut self) -> Option<O> {
+        let f = self.f.as_mut()?;
+        let item = self.consumer.dequeue().await.ok()?;
+        let out = f(&mut self.state, item);
+        if out.is_none() {
+            self.f = None;
+        }
+        out
+    }
+
+    /// The current state of the scan.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    /// Stop scanning, and return the final state.
+    pub fn into_state(self) -> S {
+        self.state
+    }
+}
+
+/// The adapter returned by [`Consumer::ready_chunks`].
+pub struct ReadyChunks<'consumer, 'queue, T, const N: usize, const K: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    consumer: &'consumer mut Consumer<'queue, T, N, B>,
+}
+
+impl<'consumer, 'queue, T, const N: usize, const K: usize, B>
+    ReadyChunks<'consumer, 'queue, T, N, K, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// Checked at compile time, when the adapter is created.
+    const VALID_SIZE: () = assert!(K > 0, "A batch must hold at least one item");
+
+    fn new(consumer: &'consumer mut Consumer<'queue, T, N, B>) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_SIZE;
+
+        Self { consumer }
+    }
+
+    /// Wait for the next batch of items.
+    ///
+    /// Resolves to `None` once the stream is finished and all items have been dequeued.
+    pub async fn next(&mut self) -> Option<Vec<T, K>> {
+        let first = self.consumer.dequeue().await.ok()?;
+
+        let mut batch = Vec::new();
+        // The batch holds at least one item
+        let _ = batch.push(first);
+        while !batch.is_full() {
+            let Ok(item) = self.consumer.pop() else {
+                break;
+            };
+            let _ = batch.push(item);
+        }
+
+        if batch.len() > 1 {
+            self.consumer.notify_producer_or_defer();
+        }
+        Some(batch)
+    }
+}
+
+/// A mutable borrow of the item at the head of the queue, returned by [`Consumer::peek_mut`].
+pub struct PeekMut<'consumer, 'queue, T, const N: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    consumer: &'consumer mut Consumer<'queue, T, N, B>,
+    /// The head lock, unless it is held for a read window.
+    _head: Option<MutexGuard<'queue, ()>>,
+}
+
+impl<T, const N: usize, B> PeekMut<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// Dequeue the borrowed item.
+    ///
+    /// If the queue drained to the low watermark, the [`Producer`](super::Producer) is
+    /// woken, or the wake is deferred to whoever is holding its waker.
+    pub fn pop(this: Self) -> T {
+        let PeekMut { consumer, _head } = this;
+        let queue = consumer.queue;
+
+        // SAFETY: we are the only consumer, and hold the head lock.
+        let Some(value) = (unsafe { queue.inner.dequeue() }) else {
+            unreachable!("the borrowed item was dequeued");
+        };
+        queue.core.metrics.dequeued();
+        drop(_head);
+
+        consumer.notify_producer_or_defer();
+        value
+    }
+}
+
+impl<T, const N: usize, B> Deref for PeekMut<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: we are the only consumer, the queue is not empty, and we hold
+        // the head lock.
+        unsafe { &self.consumer.queue.inner.head_region()[0] }
+    }
+}
+
+impl<T, const N: usize, B> DerefMut for PeekMut<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: as above.
+        unsafe { &mut self.consumer.queue.inner.head_region()[0] }
+    }
+}
+
+/// The future returned by [`Consumer::dequeue_n_with`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct DequeueNWithFuture<'consumer, 'queue, T, const N: usize, F, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    consumer: &'consumer mut Consumer<'queue, T, N, B>,
+    max: usize,
+    f: F,
+    /// The generation of the registered waker.
+    registration: Option<u32>,
+    terminated: bool,
+}
+
+impl<T, const N: usize, F, B> Future for DequeueNWithFuture<'_, '_, T, N, F, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+    F: FnMut(&mut [T]) -> usize + Unpin,
+{
+    type Output = usize;
 
     fn poll(
         self: core::pin::Pin<&mut Self>,
         cx: &mut core::task::Context<'_>,
     ) -> Poll<Self::Output> {
-        let try_wake_producer = |me: &mut Self, value| {
-            if me.consumer.try_wake_producer() {
-                return Poll::Ready(value);
-            } else {
-                me.dequeued_value = Some(value);
+        let me = self.get_mut();
+
+        match me.consumer.pop_n_with(me.max, &mut me.f) {
+            Ok(consumed) => {
+                me.consumer.notify_producer_or_defer();
+                me.terminated = true;
+                Poll::Ready(consumed)
+            }
+            Err(false) => {
                 cx.waker().wake_by_ref();
-                return Poll::Pending;
+                Poll::Pending
             }
-        };
+            Err(true) => {
+                me.registration = me.consumer.try_register_waker(cx.waker());
+                if me.registration.is_none() || me.consumer.changed() {
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, const N: usize, F, B> FusedFuture for DequeueNWithFuture<'_, '_, T, N, F, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+    F: FnMut(&mut [T]) -> usize + Unpin,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<T, const N: usize, F, B> Drop for DequeueNWithFuture<'_, '_, T, N, F, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn drop(&mut self) {
+        if let Some(generation) = self.registration {
+            self.consumer.unregister_waker(generation);
+        }
+    }
+}
+
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ConsumerFuture<'consumer, 'queue, T, const N: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    consumer: &'consumer mut Consumer<'queue, T, N, B>,
+    /// The generation of the registered waker.
+    registration: Option<u32>,
+    terminated: bool,
+}
+
+impl<T, const N: usize, B> Future for ConsumerFuture<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    type Output = Result<T, Finished>;
 
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Self::Output> {
         debug!("Poll consumer");
         let me = self.get_mut();
-        let con = &mut me.consumer;
 
-        if let Some(value) = me.dequeued_value.take() {
-            // Try to wake the producer because we managed to
-            // dequeue a value
-            return try_wake_producer(me, value);
-        }
+        let res = me.consumer.poll_pop(cx, &mut me.registration);
+        me.terminated = res.is_ready();
+        res
+    }
+}
 
-        me.dequeued_value = con.inner.dequeue();
-        if let Some(value) = me.dequeued_value.take() {
-            // Try to wake the producer because we managed to
-            // dequeue a value
-            try_wake_producer(me, value)
-        } else {
-            if !me.consumer.try_register_waker(cx.waker()) {
-                cx.waker().wake_by_ref()
-            }
-            Poll::Pending
+#[cfg(feature = "futures")]
+impl<T, const N: usize, B> FusedFuture for ConsumerFuture<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<T, const N: usize, B> Drop for ConsumerFuture<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn drop(&mut self) {
+        if let Some(generation) = self.registration {
+            self.consumer.unregister_waker(generation);
         }
     }
 }
@@ -0,0 +1,93 @@
+use crate::{wake_lock::WakeLock, waker::WakerRegistration};
+
+use super::{Owned, Queue, Storage};
+
+/// The state of a waker of a queue, as seen by a [`FrozenView`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakerState {
+    /// No waker is registered.
+    Empty,
+    /// A waker is registered, so that side is waiting.
+    Registered,
+    /// The waker was locked when the queue was frozen, e.g. because that side was
+    /// registering or waking.
+    Locked,
+}
+
+impl WakerState {
+    /// The state of `waker` in a frozen queue, where nothing may be woken.
+    fn of(waker: &WakeLock<WakerRegistration>) -> Self {
+        Self::from_empty(waker.inspect(WakerRegistration::is_empty))
+    }
+
+    /// The state of `waker` in a live queue.
+    ///
+    /// This takes the lock like everything else does, so that a wake that was
+    /// deferred to us in the meantime is performed once we release it.
+    pub(super) fn live(waker: &WakeLock<WakerRegistration>) -> Self {
+        Self::from_empty(waker.try_lock().map(|wk| wk.is_empty()))
+    }
+
+    fn from_empty(empty: Option<bool>) -> Self {
+        match empty {
+            Some(true) => Self::Empty,
+            Some(false) => Self::Registered,
+            None => Self::Locked,
+        }
+    }
+}
+
+/// A view of the contents of a [`Queue`], created with [`Queue::freeze`].
+///
+/// Inspecting the view never wakes anything, nor changes the queue.
+pub struct FrozenView<'queue, T, const N: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    pub(super) queue: &'queue Queue<T, N, B>,
+}
+
+impl<'queue, T, const N: usize, B> FrozenView<'queue, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// Returns the amount of items in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.inner.len()
+    }
+
+    /// Returns true if the queue holds no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum amount of items the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.queue.inner.capacity()
+    }
+
+    /// Iterate over the items in the queue, from the oldest to the newest.
+    pub fn iter(&self) -> impl Iterator<Item = &'queue T> + '_ {
+        let queue = self.queue;
+        // SAFETY: nothing dequeues while the queue is frozen.
+        (0..self.len()).map_while(move |index| unsafe { queue.inner.get(index) })
+    }
+
+    /// Returns the state of the waker of the producer.
+    pub fn producer_waker(&self) -> WakerState {
+        WakerState::of(&self.queue.producer_waker)
+    }
+
+    /// Returns the state of the waker of the consumer.
+    pub fn consumer_waker(&self) -> WakerState {
+        WakerState::of(&self.queue.consumer_waker)
+    }
+
+    /// Returns true if the producer has finished the stream.
+    pub fn is_finished(&self) -> bool {
+        self.queue.core.is_finished()
+    }
+}
@@ -1,65 +1,388 @@
-//! An async wrapper around [`heapless::spsc::Queue`]
-
-use heapless::spsc::Queue as HQueue;
+//! An async single-producer single-consumer queue, modeled after [`heapless::spsc::Queue`]
 
 mod producer;
-pub use producer::{Producer, ProducerError};
+pub use producer::{
+    EnqueueBeforeFuture, EnqueueError, FinishFuture, Producer, ProducerError, ProducerFuture,
+};
 
 mod consumer;
-pub use consumer::{Consumer, ConsumerError};
+pub use consumer::{
+    Consumer, ConsumerError, DequeueNWithFuture, Finished, PeekMut, ReadyChunks, Scan,
+};
+
+mod async_ref;
+pub use async_ref::AsyncRef;
+
+mod bytes;
+pub use bytes::LineError;
+
+mod frozen;
+pub use frozen::{FrozenView, WakerState};
+
+mod ring;
+
+mod select;
+pub use select::{select_array, SelectArray, SelectOrder, Selector};
 
-use crate::{mutex::Mutex, waker::WakerRegistration};
+mod storage;
+pub use storage::{External, Owned, Slice, Storage, DYNAMIC};
 
-/// An async queue
-pub struct Queue<T, const N: usize>
+use core::{
+    mem::MaybeUninit,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use heapless::{spsc::Queue as HQueue, Deque};
+
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::{Operation, Waiter};
+use crate::{
+    builder::Config, channel::Core, metrics::Metrics, mutex::Mutex, wake_lock::WakeLock,
+    waker::WakerRegistration,
+};
+
+use self::ring::Ring;
+
+/// The two halves of a split [`Queue`].
+pub struct Split<'queue, T, const N: usize, B = Owned<T, N>>
 where
     T: Unpin,
+    B: Storage<T, N>,
 {
-    inner: HQueue<T, N>,
-    producer_waker: Mutex<WakerRegistration>,
-    consumer_waker: Mutex<WakerRegistration>,
+    /// The producing half of the queue.
+    pub producer: Producer<'queue, T, N, B>,
+    /// The consuming half of the queue.
+    pub consumer: Consumer<'queue, T, N, B>,
 }
 
+/// An async queue, holding up to `N` items.
+///
+/// Unlike a [`heapless::spsc::Queue`], it does not keep one of its slots free. The slots
+/// are stored in `B`, which is the queue itself unless the queue was created
+/// [with other storage](Queue::with_storage).
+///
+/// A queue that can not hold any items is rejected at compile time:
+///
+/// ```compile_fail
+/// # use heapless_async_queues::spsc::Queue;
+/// let queue: Queue<u32, 0> = Queue::new();
+/// ```
+pub struct Queue<T, const N: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    inner: Ring<T, N, B>,
+    producer_waker: WakeLock<WakerRegistration>,
+    consumer_waker: WakeLock<WakerRegistration>,
+    /// Held by the consumer while it accesses the oldest item, so that the producer
+    /// does not take it out of the queue at the same time.
+    head_lock: Mutex<()>,
+    /// Set once the consumer was dropped.
+    disconnected: AtomicBool,
+    /// Set once the producer was dropped without finishing the stream.
+    producer_dropped: AtomicBool,
+    /// Set once the queue was split through a shared reference.
+    split_taken: AtomicBool,
+    core: Core,
+}
+
+/// A [`Queue`] whose capacity is the length of a slice, which is only known at runtime.
+///
+/// ```
+/// use core::mem::MaybeUninit;
+/// use heapless_async_queues::spsc::{SliceQueue, Split};
+///
+/// # let configured_len = 16;
+/// let mut slots = [const { MaybeUninit::<u8>::uninit() }; 64];
+/// let mut queue = SliceQueue::from_slice(&mut slots[..configured_len]);
+/// let Split { producer, .. } = queue.split();
+/// assert_eq!(producer.capacity(), 16);
+/// ```
+pub type SliceQueue<'a, T> = Queue<T, DYNAMIC, Slice<'a, T>>;
+
+/// The [`Producer`] of a [`SliceQueue`].
+pub type SliceProducer<'queue, 'a, T> = Producer<'queue, T, DYNAMIC, Slice<'a, T>>;
+
+/// The [`Consumer`] of a [`SliceQueue`].
+pub type SliceConsumer<'queue, 'a, T> = Consumer<'queue, T, DYNAMIC, Slice<'a, T>>;
+
 impl<T, const N: usize> Queue<T, N>
 where
     T: Unpin,
 {
     /// Create a new Queue
     pub const fn new() -> Self {
+        Self::with_config(Config::DEFAULT)
+    }
+
+    pub(crate) const fn with_config(config: Config) -> Self {
+        Self::with_storage_config(Owned::new(), config)
+    }
+}
+
+impl<T, const N: usize, B> Queue<T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// Create a new Queue, storing its slots in `storage`.
+    pub const fn with_storage(storage: B) -> Self {
+        Self::with_storage_config(storage, Config::DEFAULT)
+    }
+
+    pub(crate) const fn with_storage_config(storage: B, config: Config) -> Self {
         Self {
-            inner: HQueue::new(),
-            producer_waker: Mutex::new(WakerRegistration::new()),
-            consumer_waker: Mutex::new(WakerRegistration::new()),
+            inner: Ring::new(storage),
+            producer_waker: WakeLock::new(WakerRegistration::new()),
+            consumer_waker: WakeLock::new(WakerRegistration::new()),
+            head_lock: Mutex::new(()),
+            disconnected: AtomicBool::new(false),
+            producer_dropped: AtomicBool::new(false),
+            split_taken: AtomicBool::new(false),
+            core: Core::new(config),
         }
     }
 
     /// Split the queue into a producer and consumer
-    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
-        let (cw, pw) = (&self.consumer_waker, &self.producer_waker);
+    ///
+    /// If the queue was finished by a previous producer, or either of its previous halves
+    /// was dropped, it can be used again.
+    pub fn split(&mut self) -> Split<'_, T, N, B> {
+        *self.core.finished.get_mut() = false;
+        *self.disconnected.get_mut() = false;
+        *self.producer_dropped.get_mut() = false;
+        // A consumer may have been dropped while holding a read window.
+        self.head_lock = Mutex::new(());
+        let queue = &*self;
+        Split {
+            producer: Producer::new(queue),
+            consumer: Consumer::new(queue),
+        }
+    }
+
+    /// Split a queue that lives forever, e.g. in a `static`, into a producer and consumer.
+    ///
+    /// Unlike [`Queue::split`], this does not need exclusive access to the queue, so it
+    /// can only be done once. Returns `None` if the queue was already split this way.
+    ///
+    /// ```
+    /// use heapless_async_queues::spsc::{Queue, Split};
+    ///
+    /// static QUEUE: Queue<u32, 4> = Queue::new();
+    ///
+    /// let Split { producer, consumer } = QUEUE.split_ref().unwrap();
+    /// assert!(QUEUE.split_ref().is_none());
+    /// ```
+    pub fn split_ref(&'static self) -> Option<Split<'static, T, N, B>> {
+        if self.split_taken.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        Some(Split {
+            producer: Producer::new(self),
+            consumer: Consumer::new(self),
+        })
+    }
+
+    /// Join the halves of a queue that was split with [`Queue::split_ref`], so that it
+    /// can be split again.
+    ///
+    /// The items in the queue are kept for the next consumer. A queue that was split with
+    /// [`Queue::split`] does not need this, as it can be split again once both halves
+    /// are dropped.
+    ///
+    /// # Panics
+    /// Panics if the halves were not split from this queue.
+    pub fn unsplit(&self, split: Split<'_, T, N, B>) {
+        let Split { producer, consumer } = split;
+        assert!(
+            ptr::eq(producer.queue, self) && ptr::eq(consumer.queue, self),
+            "The halves were not split from this queue"
+        );
+        drop((producer, consumer));
 
-        let (producer, consumer) = self.inner.split();
-        (
-            Producer::new(producer, pw, cw),
-            Consumer::new(consumer, pw, cw),
+        // Dropping the halves finished the stream, and disconnected both of them.
+        self.core.finished.store(false, Ordering::Release);
+        self.disconnected.store(false, Ordering::Release);
+        self.producer_dropped.store(false, Ordering::Release);
+        self.split_taken.store(false, Ordering::Release);
+    }
+
+    /// Dequeue an item while nothing else can access the queue, e.g. to drop it.
+    pub(crate) fn pop_exclusive(&mut self) -> Option<T> {
+        // SAFETY: we have exclusive access to the queue.
+        unsafe { self.inner.dequeue() }
+    }
+
+    /// Freeze the queue, to inspect what is in flight, e.g. from a panic or fault handler.
+    ///
+    /// # Safety
+    /// The producer and consumer may not be used while the view exists, e.g. because
+    /// they are in contexts that will never run again. A dequeue that was interrupted
+    /// by the caller may leave an item in the view that was already moved out.
+    pub unsafe fn freeze(&self) -> FrozenView<'_, T, N, B> {
+        FrozenView { queue: self }
+    }
+
+    /// Returns the [`Metrics`] of this queue, if it was
+    /// built with metrics enabled.
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.core.metrics()
+    }
+
+    /// Call `f` for the producer and the consumer, if they are waiting.
+    ///
+    /// A side that is registering its waker at the moment is not reported.
+    #[cfg(feature = "diagnostics")]
+    pub fn waiters(&self, mut f: impl FnMut(Waiter)) {
+        let sides = [
+            (&self.producer_waker, Operation::Enqueue),
+            (&self.consumer_waker, Operation::Dequeue),
+        ];
+        for (waker, operation) in sides {
+            if let Some(name) = waker.try_lock().and_then(|wk| wk.waiting()) {
+                f(Waiter { name, operation });
+            }
+        }
+    }
+}
+
+impl<'a, T> SliceQueue<'a, T>
+where
+    T: Unpin,
+{
+    /// Create a new Queue, storing its items in `slots`.
+    ///
+    /// # Panics
+    /// If `slots` is empty.
+    pub const fn from_slice(slots: &'a mut [MaybeUninit<T>]) -> Self {
+        Self::with_storage(Slice::new(slots))
+    }
+}
+
+impl<T, const N: usize> Queue<T, N>
+where
+    T: Unpin,
+{
+    /// Take the items that are left in the queue, e.g. to inspect them once the
+    /// producer and consumer are done.
+    ///
+    /// A [`heapless::spsc::Queue`] can only hold `N - 1` items, so the items are
+    /// returned in a [`Deque`], which can hold all of them.
+    pub fn into_inner(mut self) -> Deque<T, N> {
+        let mut items = Deque::new();
+        while let Some(value) = self.pop_exclusive() {
+            // The deque has the same capacity as the queue
+            let _ = items.push_back(value);
+        }
+        items
+    }
+}
+
+impl<T, const N: usize> From<HQueue<T, N>> for Queue<T, N>
+where
+    T: Unpin,
+{
+    /// Create a new Queue, holding the items of `queue`
+    fn from(mut queue: HQueue<T, N>) -> Self {
+        let me = Self::new();
+        while let Some(value) = queue.dequeue() {
+            // SAFETY: we have exclusive access to the queue, and it has the
+            // same capacity as the heapless queue.
+            let _ = unsafe { me.inner.enqueue(value) };
+        }
+        me
+    }
+}
+
+impl<T, const N: usize> From<Deque<T, N>> for Queue<T, N>
+where
+    T: Unpin,
+{
+    /// Create a new Queue, holding the items of `items`
+    fn from(items: Deque<T, N>) -> Self {
+        let me = Self::new();
+        for value in items {
+            // SAFETY: we have exclusive access to the queue, and it has the
+            // same capacity as the deque.
+            let _ = unsafe { me.inner.enqueue(value) };
+        }
+        me
+    }
+}
+
+impl<T, const N: usize, B> core::fmt::Debug for Queue<T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Queue")
+            .field("len", &self.inner.len())
+            .field("capacity", &self.inner.capacity())
+            .field("producer_waker", &WakerState::live(&self.producer_waker))
+            .field("consumer_waker", &WakerState::live(&self.consumer_waker))
+            .field("finished", &self.core.is_finished())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T, const N: usize, B> defmt::Format for Queue<T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Queue {{ len: {}, capacity: {}, producer_waker: {}, consumer_waker: {}, finished: {} }}",
+            self.inner.len(),
+            self.inner.capacity(),
+            WakerState::live(&self.producer_waker),
+            WakerState::live(&self.consumer_waker),
+            self.core.is_finished(),
         )
     }
 }
 
+impl<T, const N: usize> Default for Queue<T, N>
+where
+    T: Unpin,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod test {
     extern crate std;
+    use core::{future::Future, mem::MaybeUninit, ops::ControlFlow, pin::pin};
     use std::boxed::Box;
     use std::println;
+    use std::string::ToString;
     use std::time::Duration;
     use std::vec::Vec;
 
-    use super::Queue;
+    use super::{
+        AsyncRef, ConsumerError, EnqueueError, External, Finished, PeekMut, ProducerError, Queue,
+        SelectOrder, Selector, SliceQueue, Split, WakerState,
+    };
+    use crate::{
+        builder::{OverflowPolicy, QueueBuilder},
+        metrics::Metrics,
+    };
 
     #[tokio::test]
     async fn spsc() {
         let queue: &'static mut Queue<u32, 8> = Box::leak(Box::new(Queue::new()));
 
-        let (mut tx, mut rx) = queue.split();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
         const MAX: u32 = 100;
         let mut data = Vec::new();
         for i in 0..=MAX {
@@ -71,7 +394,7 @@ mod test {
             println!("Dequeueing...");
             let mut rx_data = Vec::new();
             loop {
-                let value = rx.dequeue().await;
+                let value = rx.dequeue().await.unwrap();
                 println!("Succesfully dequeued {}", value);
                 rx_data.push(value);
                 if value == MAX {
@@ -85,7 +408,7 @@ mod test {
             let mut interval = tokio::time::interval(Duration::from_millis(1));
             println!("Enqueing...");
             for i in data {
-                tx.enqueue(i).await;
+                tx.enqueue(i).await.unwrap();
                 interval.tick().await;
                 println!("Succesfully enqueued {}", i);
             }
@@ -95,4 +418,1085 @@ mod test {
         t1.unwrap();
         t2.unwrap();
     }
+
+    #[test]
+    fn overflow_policies() {
+        let expected = [
+            (OverflowPolicy::DropNewest, [0, 1, 2]),
+            (OverflowPolicy::DropOldest, [2, 3, 4]),
+            (OverflowPolicy::ReplaceNewest, [0, 1, 4]),
+        ];
+
+        for (policy, expected) in expected {
+            let mut queue: Queue<u32, 3> = QueueBuilder::new()
+                .overflow(policy)
+                .metrics(true)
+                .build_spsc();
+            let Split {
+                producer: mut tx,
+                consumer: mut rx,
+            } = queue.split();
+
+            for i in 0..5 {
+                assert!(tx.try_enqueue(i).is_ok());
+            }
+
+            for value in expected {
+                assert_eq!(rx.try_dequeue().ok(), Some(value));
+            }
+            assert!(rx.try_dequeue().is_err());
+
+            let metrics = Metrics {
+                enqueued: 5 - 2 * (policy == OverflowPolicy::DropNewest) as usize,
+                dequeued: 3,
+                dropped: 2,
+            };
+            drop((tx, rx));
+            assert_eq!(queue.metrics(), Some(metrics));
+        }
+    }
+
+    #[tokio::test]
+    async fn fail_policy() {
+        let mut queue: Queue<u32, 2> = QueueBuilder::new()
+            .overflow(OverflowPolicy::Fail)
+            .metrics(true)
+            .build_spsc();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        tx.enqueue(0).await.unwrap();
+        assert!(tx.try_enqueue(1).is_ok());
+        assert!(matches!(tx.try_enqueue(2), Err(ProducerError::Full(2))));
+        // Resolves without waiting for space, handing the value back
+        assert!(!tx.ready());
+        assert_eq!(tx.enqueue(3).await, Err(EnqueueError::Full(3)));
+
+        assert_eq!(rx.try_dequeue().ok(), Some(0));
+        assert_eq!(rx.try_dequeue().ok(), Some(1));
+        assert!(rx.try_dequeue().is_err());
+
+        let metrics = Metrics {
+            enqueued: 2,
+            dequeued: 2,
+            dropped: 0,
+        };
+        drop((tx, rx));
+        assert_eq!(queue.metrics(), Some(metrics));
+    }
+
+    #[tokio::test]
+    async fn poll_budget() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        static OTHER_RAN: AtomicBool = AtomicBool::new(false);
+
+        let mut queue: Queue<u32, 8> = QueueBuilder::new().poll_budget(2).build_spsc();
+        let Split {
+            producer: mut tx,
+            consumer: _rx,
+        } = queue.split();
+
+        tokio::spawn(async { OTHER_RAN.store(true, Ordering::Relaxed) });
+
+        // The queue never makes the producer wait, but it still yields
+        tx.enqueue_iter(0..4).await;
+        assert!(OTHER_RAN.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn enqueue_before() {
+        use crate::time::Clock;
+
+        static CLOCK: Clock = Clock::new();
+
+        let mut queue: Queue<u32, 1> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+        tx.enqueue(0).await.unwrap();
+
+        let (res, ()) = tokio::join!(tx.enqueue_before(1, CLOCK.after(2)), async {
+            for _ in 0..2 {
+                tokio::task::yield_now().await;
+                CLOCK.tick();
+            }
+        });
+        assert_eq!(res, Err(1));
+
+        let (res, value) = tokio::join!(tx.enqueue_before(2, CLOCK.after(2)), rx.dequeue());
+        assert_eq!(res, Ok(()));
+        assert_eq!(value, Ok(0));
+    }
+
+    #[test]
+    fn bounded_retries() {
+        let mut queue: Queue<u32, 4> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        // The consumer is registering its waker, for longer than the retries take
+        let registering = tx.queue.consumer_waker.try_lock();
+        // The value was enqueued anyway
+        assert_eq!(
+            tx.try_enqueue_for(0, 3),
+            Err(ProducerError::WouldBlock(None))
+        );
+        drop(registering);
+        assert!(tx.try_enqueue_for(1, 3).is_ok());
+
+        assert_eq!(rx.try_dequeue_for(3).ok(), Some(0));
+        assert_eq!(rx.try_dequeue_for(3).ok(), Some(1));
+        assert!(matches!(rx.try_dequeue_for(3), Err(ConsumerError::Empty)));
+    }
+
+    #[tokio::test]
+    async fn watermarks() {
+        let queue: &'static mut Queue<u32, 8> =
+            Box::leak(Box::new(QueueBuilder::new().watermarks(1, 4).build_spsc()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let consumer = tokio::task::spawn(async move {
+            let value = rx.dequeue().await.unwrap();
+            // The consumer is only woken once the high watermark is reached
+            assert_eq!(rx.len(), 3);
+            value
+        });
+
+        // Give the consumer time to register its waker
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        for i in 0..4 {
+            tx.enqueue(i).await.unwrap();
+        }
+
+        assert_eq!(consumer.await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn from_heapless() {
+        let mut source: heapless::spsc::Queue<u32, 4> = heapless::spsc::Queue::new();
+        source.enqueue(0).unwrap();
+        source.enqueue(1).unwrap();
+
+        let mut queue = Queue::from(source.clone());
+        let mut rx = queue.split().consumer;
+        assert_eq!(rx.capacity(), 4);
+        assert_eq!(rx.dequeue().await, Ok(0));
+
+        {
+            let mut async_ref = AsyncRef::new(&mut source);
+            let Split {
+                producer: mut tx,
+                consumer: mut rx,
+            } = async_ref.split();
+            // The items have to fit back into the heapless queue
+            assert_eq!(tx.capacity(), 3);
+            assert_eq!(rx.dequeue().await, Ok(0));
+            tx.enqueue(2).await.unwrap();
+        }
+
+        assert_eq!(source.dequeue(), Some(1));
+        assert_eq!(source.dequeue(), Some(2));
+        assert_eq!(source.dequeue(), None);
+    }
+
+    #[tokio::test]
+    async fn into_inner() {
+        let mut queue: Queue<u32, 2> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+        tx.enqueue_iter([0, 1]).await;
+        assert_eq!(rx.dequeue().await, Ok(0));
+        tx.enqueue(2).await.unwrap();
+        drop((tx, rx));
+
+        // The queue can be full, unlike a heapless queue
+        let items = queue.into_inner();
+        assert_eq!(items.iter().copied().collect::<Vec<_>>(), [1, 2]);
+
+        let mut queue = Queue::from(items);
+        let mut rx = queue.split().consumer;
+        assert_eq!(rx.len(), 2);
+        assert_eq!(rx.dequeue().await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn fold_and_scan() {
+        let queue: &'static mut Queue<u8, 4> = Box::leak(Box::default());
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        tokio::task::spawn(async move {
+            for byte in [1, 2, 3, 0, 4, 5, 6, 0] {
+                tx.enqueue(byte).await.unwrap();
+            }
+        });
+
+        // Sum up bytes until a terminating zero
+        let sum = rx
+            .fold(0u32, |sum, byte| match byte {
+                0 => ControlFlow::Break(sum),
+                byte => ControlFlow::Continue(sum + byte as u32),
+            })
+            .await;
+        assert_eq!(sum, 6);
+
+        let mut running = rx.scan(0u32, |sum, byte| {
+            *sum += byte as u32;
+            (byte != 0).then_some(*sum)
+        });
+        assert_eq!(running.next().await, Some(4));
+        assert_eq!(running.next().await, Some(9));
+        assert_eq!(running.next().await, Some(15));
+        assert_eq!(running.next().await, None);
+        assert_eq!(running.next().await, None);
+        assert_eq!(running.into_state(), 15);
+    }
+
+    #[tokio::test]
+    async fn dequeue_n_with() {
+        let queue: &'static mut Queue<u32, 8> = Box::leak(Box::default());
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        // Move the head, so that the items wrap around the end of the buffer
+        for i in 0..5 {
+            tx.enqueue(i).await.unwrap();
+            rx.dequeue().await.unwrap();
+        }
+        for i in 0..6 {
+            tx.enqueue(i).await.unwrap();
+        }
+
+        let mut seen = Vec::new();
+        let consumed = rx
+            .dequeue_n_with(4, |items| {
+                seen.extend_from_slice(items);
+                items.len()
+            })
+            .await;
+        assert_eq!(consumed, 4);
+        assert_eq!(seen, [0, 1, 2, 3]);
+
+        // Only consume part of the region
+        let consumed = rx.dequeue_n_with(8, |items| items.len() - 1).await;
+        assert_eq!(consumed, 1);
+        assert_eq!(rx.dequeue().await, Ok(5));
+    }
+
+    #[tokio::test]
+    async fn finish() {
+        let queue: &'static mut Queue<u32, 4> = Box::leak(Box::default());
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let consumer = tokio::task::spawn(async move {
+            let mut sum = 0;
+            while let Ok(value) = rx.dequeue().await {
+                sum += value;
+            }
+            assert!(rx.is_finished());
+            assert!(matches!(rx.try_dequeue(), Err(ConsumerError::Finished)));
+            assert_eq!(rx.dequeue().await, Err(Finished));
+            sum
+        });
+
+        // More items than fit into the queue
+        assert_eq!(tx.enqueue_iter(1..=10).await, 10);
+        tx.finish().await;
+
+        // All items are dequeued before the stream ends
+        assert_eq!(consumer.await.unwrap(), 55);
+    }
+
+    #[tokio::test]
+    async fn enqueue_iter_count() {
+        let mut queue: Queue<u32, 2> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: rx,
+        } = queue.split();
+
+        // Only the items that reached the queue are counted once the consumer is dropped
+        {
+            let mut enqueue = pin!(tx.enqueue_iter(0..100));
+            assert!(embassy_futures::poll_once(&mut enqueue).is_pending());
+            drop(rx);
+            assert_eq!(enqueue.await, 2);
+        }
+        assert_eq!(tx.enqueue_iter(0..100).await, 0);
+        drop(tx);
+
+        // Items dropped by the overflow policy are not counted either
+        let mut queue: Queue<u32, 2> = QueueBuilder::new()
+            .overflow(OverflowPolicy::DropNewest)
+            .build_spsc();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+        assert_eq!(tx.enqueue_iter(0..5).await, 2);
+        assert_eq!(rx.dequeue().await, Ok(0));
+        assert_eq!(rx.dequeue().await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn peek_mut() {
+        let queue: &'static mut Queue<(u32, u8), 4> = Box::leak(Box::new(
+            QueueBuilder::new()
+                .overflow(OverflowPolicy::DropOldest)
+                .build_spsc(),
+        ));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        tx.enqueue((1, 2)).await.unwrap();
+        tx.enqueue((2, 0)).await.unwrap();
+        tx.finish().await;
+
+        // Retry the first message until it runs out of attempts
+        let mut attempts = 0;
+        loop {
+            let mut head = rx.peek_mut().await.unwrap();
+            attempts += 1;
+            if head.1 == 0 {
+                assert_eq!(PeekMut::pop(head), (1, 0));
+                break;
+            }
+            head.1 -= 1;
+        }
+        assert_eq!(attempts, 3);
+
+        assert_eq!(rx.len(), 1);
+        assert_eq!(PeekMut::pop(rx.peek_mut().await.unwrap()), (2, 0));
+        assert!(matches!(rx.peek_mut().await, Err(Finished)));
+    }
+
+    #[tokio::test]
+    async fn peek() {
+        let queue: &'static mut Queue<u32, 2> = Box::leak(Box::new(
+            QueueBuilder::new()
+                .overflow(OverflowPolicy::DropOldest)
+                .build_spsc(),
+        ));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        assert_eq!(rx.try_peek(), None);
+        tx.enqueue_iter([1, 2]).await;
+        assert_eq!(rx.peek().await, Ok(&1));
+        assert_eq!(rx.try_peek(), Some(&1));
+        assert_eq!(rx.dequeue().await, Ok(1));
+
+        // The producer can drop items again once the consumer no longer peeks
+        tx.enqueue_iter([3, 4]).await;
+        assert_eq!(rx.peek().await, Ok(&3));
+        assert_eq!(rx.dequeue().await, Ok(3));
+        assert_eq!(rx.dequeue().await, Ok(4));
+
+        tx.finish().await;
+        assert_eq!(rx.peek().await, Err(Finished));
+    }
+
+    #[tokio::test]
+    async fn dequeue_matching() {
+        let mut queue: Queue<u32, 4> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        // Move the head, so that the items wrap around the end of the buffer
+        tx.enqueue_iter([0, 0, 0]).await;
+        for _ in 0..3 {
+            rx.dequeue().await.unwrap();
+        }
+
+        // The items in front of the accepted one stay in the queue, in order
+        tx.enqueue_iter([1, 3, 4, 5]).await;
+        assert_eq!(rx.dequeue_matching(|v| v % 2 == 0).await, Ok(4));
+        assert_eq!(rx.len(), 3);
+
+        {
+            let mut matching = pin!(rx.dequeue_matching(|v| v % 2 == 0));
+            assert!(embassy_futures::poll_once(&mut matching).is_pending());
+            tx.enqueue(6).await.unwrap();
+            assert_eq!(matching.await, Ok(6));
+        }
+
+        tx.finish().await;
+        assert_eq!(rx.dequeue_matching(|v| v % 2 == 0).await, Err(Finished));
+        assert_eq!(rx.dequeue().await, Ok(1));
+        assert_eq!(rx.dequeue().await, Ok(3));
+        assert_eq!(rx.dequeue().await, Ok(5));
+    }
+
+    #[tokio::test]
+    async fn dequeue_if() {
+        let queue: &'static mut Queue<u32, 4> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        assert_eq!(rx.try_dequeue_if(|_| true), None);
+        tx.enqueue_iter([1, 2]).await;
+
+        // A rejected item stays at the head
+        assert_eq!(rx.try_dequeue_if(|&v| v == 2), None);
+        assert_eq!(rx.dequeue_if(|&v| v == 2).await, Ok(None));
+        assert_eq!(rx.try_dequeue_if(|&v| v == 1), Some(1));
+        assert_eq!(rx.len(), 1);
+
+        let producer = tokio::task::spawn(async move {
+            tx.enqueue(3).await.unwrap();
+            tx.finish().await;
+        });
+
+        assert_eq!(rx.dequeue_if(|&v| v == 2).await, Ok(Some(2)));
+        assert_eq!(rx.dequeue_if(|&v| v == 3).await, Ok(Some(3)));
+        producer.await.unwrap();
+        assert_eq!(rx.dequeue_if(|_| true).await, Err(Finished));
+    }
+
+    #[tokio::test]
+    async fn dequeue_many() {
+        let queue: &'static mut Queue<u32, 4> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let mut buf = [MaybeUninit::uninit(); 6];
+        assert_eq!(rx.dequeue_many(&mut buf), 0);
+        tx.enqueue_iter([0, 1, 2]).await;
+        assert_eq!(rx.dequeue_many(&mut buf[..2]), 2);
+        assert_eq!(unsafe { buf[1].assume_init() }, 1);
+
+        let producer = tokio::task::spawn(async move {
+            tx.enqueue_iter(3..8).await;
+            tx.finish().await;
+        });
+
+        // The items wrap around the end of the backing buffer on the way
+        assert_eq!(rx.dequeue_at_least(&mut buf, 6).await, 6);
+        let values = buf.map(|value| unsafe { value.assume_init() });
+        assert_eq!(values, [2, 3, 4, 5, 6, 7]);
+
+        producer.await.unwrap();
+        assert_eq!(rx.dequeue_at_least(&mut buf, 1).await, 0);
+    }
+
+    #[tokio::test]
+    async fn wait_for_len() {
+        let queue: &'static mut Queue<u32, 4> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let producer = tokio::task::spawn(async move {
+            for value in 0..3 {
+                tx.enqueue(value).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+            tx.finish().await;
+        });
+
+        assert_eq!(rx.wait_for_len(2).await, Ok(()));
+        assert!(rx.len() >= 2);
+        assert_eq!(rx.dequeue().await, Ok(0));
+        assert_eq!(rx.dequeue().await, Ok(1));
+
+        // Only one item is left once the stream is finished
+        assert_eq!(rx.wait_for_len(2).await, Err(Finished));
+        assert_eq!(rx.dequeue().await, Ok(2));
+        producer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn wait_for_capacity() {
+        let queue: &'static mut Queue<u32, 4> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        tx.enqueue_iter(0..4).await;
+        {
+            let mut wait = pin!(tx.wait_for_capacity(2));
+            assert!(embassy_futures::poll_once(&mut wait).is_pending());
+            assert_eq!(rx.dequeue().await, Ok(0));
+            assert!(embassy_futures::poll_once(&mut wait).is_pending());
+            assert_eq!(rx.dequeue().await, Ok(1));
+            assert!(embassy_futures::poll_once(&mut wait).is_ready());
+        }
+
+        // The burst fits without waiting
+        assert_eq!(tx.enqueue_slice(&[4, 5]), 2);
+    }
+
+    #[tokio::test]
+    async fn flush() {
+        let queue: &'static mut Queue<u32, 4> =
+            Box::leak(Box::new(QueueBuilder::new().watermarks(1, 1).build_spsc()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let consumer = tokio::task::spawn(async move {
+            let mut values = Vec::new();
+            while let Ok(value) = rx.dequeue().await {
+                values.push(value);
+                tokio::task::yield_now().await;
+            }
+            values
+        });
+
+        tx.enqueue_iter(0..4).await;
+        tx.flush().await;
+        assert!(tx.is_empty());
+        tx.finish().await;
+        assert_eq!(consumer.await.unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn disconnect() {
+        let mut queue: Queue<u32, 2> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        // Dropping the producer ends the stream once the queue is drained
+        tx.enqueue(0).await.unwrap();
+        drop(tx);
+        assert!(rx.is_disconnected());
+        assert_eq!(rx.dequeue().await, Ok(0));
+        assert_eq!(rx.try_dequeue(), Err(ConsumerError::Disconnected));
+        assert_eq!(rx.dequeue().await, Err(Finished));
+        drop(rx);
+
+        // Closing the queue finishes the stream, and is not a disconnect
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+        tx.enqueue(1).await.unwrap();
+        tx.close().await;
+        assert!(rx.is_closed() && !rx.is_finished());
+        assert_eq!(rx.dequeue().await, Ok(1));
+        assert!(rx.is_finished());
+        assert!(!rx.is_disconnected());
+        assert_eq!(rx.try_dequeue(), Err(ConsumerError::Finished));
+        drop(rx);
+
+        let Split {
+            producer: mut tx,
+            consumer: rx,
+        } = queue.split();
+        tx.enqueue_iter([1, 2]).await;
+        {
+            let mut enqueue = pin!(tx.enqueue(3));
+            assert!(embassy_futures::poll_once(&mut enqueue).is_pending());
+
+            // The waiting enqueue gives up once the consumer is dropped
+            drop(rx);
+            assert_eq!(enqueue.await, Err(EnqueueError::Disconnected(3)));
+        }
+        assert!(tx.is_disconnected());
+        assert!(matches!(
+            tx.try_enqueue(4),
+            Err(ProducerError::Disconnected(4))
+        ));
+        tx.flush().await;
+        drop(tx);
+
+        // Nothing is enqueued once the consumer is gone, even if it would fit
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+        assert_eq!(rx.dequeue().await, Ok(1));
+        drop(rx);
+        assert_eq!(tx.enqueue(5).await, Err(EnqueueError::Disconnected(5)));
+        assert_eq!(tx.enqueue_slice(&[5]), 0);
+        assert_eq!(tx.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn finish_without_await() {
+        let queue: &'static mut Queue<u32, 2> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let consumer = tokio::task::spawn(async move { rx.dequeue().await });
+        // Give the consumer time to register its waker
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // The waiting consumer is woken even if the finish future is never polled
+        drop(tx.finish());
+        let dequeued = tokio::time::timeout(Duration::from_secs(1), consumer).await;
+        assert_eq!(dequeued.unwrap().unwrap(), Err(Finished));
+    }
+
+    #[tokio::test]
+    async fn cancel_enqueue() {
+        let mut queue: Queue<u32, 1> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        assert_eq!(tx.enqueue(0).cancel(), None);
+        let mut enqueue = tx.enqueue(1);
+        assert!(embassy_futures::poll_once(&mut enqueue).is_pending());
+        assert_eq!(enqueue.cancel(), Some(1));
+        assert_eq!(tx.take_unsent(), None);
+
+        // A dropped enqueue keeps its value in the producer
+        let mut enqueue = tx.enqueue(2);
+        assert!(embassy_futures::poll_once(&mut enqueue).is_pending());
+        drop(enqueue);
+        assert_eq!(tx.take_unsent(), Some(2));
+        assert_eq!(tx.take_unsent(), None);
+
+        assert_eq!(rx.dequeue().await, Ok(0));
+        assert!(rx.try_dequeue().is_err());
+    }
+
+    #[tokio::test]
+    async fn enqueue_overwrite() {
+        let mut queue: Queue<u32, 2> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        assert_eq!(tx.enqueue_overwrite(0), Ok(None));
+        assert_eq!(tx.enqueue_overwrite(1), Ok(None));
+        assert_eq!(tx.enqueue_overwrite(2), Ok(Some(0)));
+
+        // The oldest item can not be overwritten while it is peeked
+        assert_eq!(rx.peek().await, Ok(&1));
+        assert_eq!(tx.enqueue_overwrite(3), Err(3));
+        assert_eq!(rx.dequeue().await, Ok(1));
+
+        // Other enqueues still wait for space
+        tx.enqueue(3).await.unwrap();
+        assert!(tx.try_enqueue(4).is_err());
+        assert_eq!(rx.dequeue().await, Ok(2));
+        assert_eq!(rx.dequeue().await, Ok(3));
+        drop((tx, rx));
+
+        // The overflow policy of the queue does not matter
+        let mut queue: Queue<u32, 1> = QueueBuilder::new()
+            .overflow(OverflowPolicy::Fail)
+            .build_spsc();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+        assert_eq!(tx.enqueue_overwrite(0), Ok(None));
+        assert_eq!(tx.enqueue_overwrite(1), Ok(Some(0)));
+        assert_eq!(rx.dequeue().await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn unsplit() {
+        static QUEUE: Queue<u32, 4> = Queue::new();
+
+        let mut split = QUEUE.split_ref().unwrap();
+        split.producer.enqueue_iter([0, 1]).await;
+        assert_eq!(split.consumer.dequeue().await, Ok(0));
+        QUEUE.unsplit(split);
+
+        // The next consumer gets the items that are left, and waits for more
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = QUEUE.split_ref().unwrap();
+        assert!(!tx.is_disconnected());
+        tx.enqueue(2).await.unwrap();
+        assert_eq!(rx.dequeue().await, Ok(1));
+        assert_eq!(rx.dequeue().await, Ok(2));
+        assert!(!rx.is_finished());
+    }
+
+    #[tokio::test]
+    async fn debug() {
+        let mut queue: Queue<u32, 2> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: rx,
+        } = queue.split();
+        tx.enqueue(0).await.unwrap();
+
+        assert_eq!(
+            std::format!("{:?}", rx),
+            "Consumer { len: 1, capacity: 2, waker: Empty, finished: false }"
+        );
+        drop((tx, rx));
+        assert_eq!(
+            std::format!("{:?}", queue),
+            "Queue { len: 1, capacity: 2, producer_waker: Empty, \
+             consumer_waker: Empty, finished: true }"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut queue: Queue<u32, 1> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        assert_eq!(rx.try_dequeue(), Err(ConsumerError::Empty));
+        assert!(tx.try_enqueue(0).is_ok());
+        let full = tx.try_enqueue(1).unwrap_err();
+        assert_eq!(full.value(), Some(&1));
+        assert_eq!(full.to_string(), "the queue is full");
+
+        // The full queue hands the value back, even if waking the consumer fails
+        let registering = tx.queue.consumer_waker.try_lock();
+        assert_eq!(tx.try_enqueue(2).unwrap_err().into_value(), Some(2));
+        drop(registering);
+
+        drop(tx);
+        assert_eq!(rx.try_dequeue(), Ok(0));
+        assert_eq!(
+            rx.try_dequeue().unwrap_err().to_string(),
+            "the producer was dropped"
+        );
+    }
+
+    #[tokio::test]
+    async fn enqueue_slice() {
+        let queue: &'static mut Queue<u32, 4> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        assert_eq!(tx.enqueue_slice(&[0, 1, 2]), 3);
+        assert_eq!(rx.dequeue().await, Ok(0));
+        // The free slots wrap around the end of the backing buffer
+        assert_eq!(tx.enqueue_slice(&[3, 4, 5]), 2);
+
+        let consumer = tokio::task::spawn(async move {
+            let mut values = Vec::new();
+            while let Ok(value) = rx.dequeue().await {
+                values.push(value);
+            }
+            values
+        });
+
+        let items: Vec<u32> = (5..32).collect();
+        tx.enqueue_all(&items).await;
+        tx.finish().await;
+        assert_eq!(consumer.await.unwrap(), (1..32).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn slice_queue() {
+        let slots = Box::leak(Box::new([const { MaybeUninit::uninit() }; 8]));
+        let queue = Box::leak(Box::new(SliceQueue::from_slice(&mut slots[..3])));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+        assert_eq!(tx.capacity(), 3);
+
+        let consumer = tokio::task::spawn(async move {
+            let mut values = Vec::new();
+            while let Ok(value) = rx.dequeue().await {
+                values.push(value);
+            }
+            values
+        });
+
+        tx.enqueue_iter(0..10u32).await;
+        tx.finish().await;
+        assert_eq!(consumer.await.unwrap(), (0..10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn freeze() {
+        let queue: &'static mut Queue<u32, 3> = Box::leak(Box::new(Queue::new()));
+        // A fault handler would reach the queue through its static
+        let frozen: *const Queue<u32, 3> = queue;
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        for i in 0..3 {
+            tx.enqueue(i).await.unwrap();
+        }
+        assert_eq!(rx.dequeue().await, Ok(0));
+        tx.enqueue(3).await.unwrap();
+        let mut waiting = pin!(tx.enqueue(4));
+        assert!(embassy_futures::poll_once(&mut waiting).is_pending());
+
+        // SAFETY: neither the producer nor the consumer are used while the view exists.
+        let view = unsafe { (*frozen).freeze() };
+        assert!(view.iter().copied().eq([1, 2, 3]));
+        assert_eq!(view.producer_waker(), WakerState::Registered);
+        assert_eq!(view.consumer_waker(), WakerState::Empty);
+    }
+
+    #[tokio::test]
+    async fn external_storage() {
+        let slots = Box::leak(Box::new([const { MaybeUninit::uninit() }; 4]));
+        let queue = Box::leak(Box::new(
+            QueueBuilder::new()
+                .metrics(true)
+                .build_spsc_with(External::new(slots)),
+        ));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let consumer = tokio::task::spawn(async move {
+            let mut values = Vec::new();
+            while let Ok(value) = rx.dequeue().await {
+                values.push(value);
+            }
+            assert_eq!(rx.metrics().unwrap().dequeued, 10);
+            values
+        });
+
+        assert_eq!(tx.enqueue_iter((0..10u32).map(|i| i.to_string())).await, 10);
+        tx.finish().await;
+
+        let expected: Vec<_> = (0..10u32).map(|i| i.to_string()).collect();
+        assert_eq!(consumer.await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn stale_wakers() {
+        let mut queue: Queue<u32, 4> = Queue::new();
+        let Split {
+            producer: tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        core::future::poll_fn(|cx| {
+            let mut dequeue = core::pin::pin!(rx.dequeue());
+            assert!(dequeue.as_mut().poll(cx).is_pending());
+            core::task::Poll::Ready(())
+        })
+        .await;
+
+        // The dropped future does not leave its waker behind
+        assert!(tx.queue.consumer_waker.try_lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ready_chunks() {
+        let queue: &'static mut Queue<u32, 8> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        tx.enqueue_iter(0..5).await;
+        tx.finish().await;
+
+        let mut chunks = rx.ready_chunks::<3>();
+        assert_eq!(chunks.next().await.unwrap(), [0, 1, 2]);
+        assert_eq!(chunks.next().await.unwrap(), [3, 4]);
+        assert_eq!(chunks.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn select_array() {
+        let mut a: Queue<u32, 4> = Queue::new();
+        let mut b: Queue<u32, 4> = Queue::new();
+        let Split {
+            producer: mut tx_a,
+            consumer: rx_a,
+        } = a.split();
+        let Split {
+            producer: mut tx_b,
+            consumer: rx_b,
+        } = b.split();
+        let mut consumers = [rx_a, rx_b];
+
+        tx_a.enqueue(0).await.unwrap();
+        tx_b.enqueue_iter(1..3).await;
+
+        // The fullest consumer goes first
+        assert_eq!(super::select_array(&mut consumers).await, Ok((1, 1)));
+        assert_eq!(super::select_array(&mut consumers).await, Ok((0, 0)));
+        assert_eq!(super::select_array(&mut consumers).await, Ok((1, 2)));
+
+        tx_a.finish().await;
+        tx_b.finish().await;
+        assert_eq!(super::select_array(&mut consumers).await, Err(Finished));
+    }
+
+    #[tokio::test]
+    async fn poll_fns() {
+        use core::{future::poll_fn, task::Poll};
+
+        let mut queue: Queue<u32, 1> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        // A hand-written future that enqueues two values
+        let mut values = [1, 2].into_iter();
+        let mut value = values.next();
+        let mut enqueue = pin!(poll_fn(|cx| {
+            while value.is_some() {
+                if tx.poll_enqueue(cx, &mut value).is_pending() {
+                    return Poll::Pending;
+                }
+                value = values.next();
+            }
+            Poll::Ready(())
+        }));
+        assert!(embassy_futures::poll_once(&mut enqueue).is_pending());
+
+        assert_eq!(poll_fn(|cx| rx.poll_dequeue(cx)).await, Ok(1));
+        enqueue.await;
+        assert_eq!(poll_fn(|cx| rx.poll_dequeue(cx)).await, Ok(2));
+    }
+
+    #[tokio::test]
+    async fn select_order() {
+        let mut a: Queue<u32, 4> = Queue::new();
+        let mut b: Queue<u32, 4> = Queue::new();
+        let Split {
+            producer: mut tx_a,
+            consumer: rx_a,
+        } = a.split();
+        let Split {
+            producer: mut tx_b,
+            consumer: rx_b,
+        } = b.split();
+
+        tx_a.enqueue_iter(0..2).await;
+        tx_b.enqueue_iter(10..13).await;
+
+        // The first consumer goes first, even though the second one is fuller
+        let mut biased = Selector::new([rx_a, rx_b], SelectOrder::Biased);
+        assert_eq!(biased.select().await, Ok((0, 0)));
+        assert_eq!(biased.select().await, Ok((0, 1)));
+        assert_eq!(biased.select().await, Ok((1, 10)));
+
+        // The consumers take turns
+        tx_a.enqueue_iter(2..4).await;
+        let mut fair = Selector::new(biased.into_inner(), SelectOrder::Fair);
+        assert_eq!(fair.select().await, Ok((0, 2)));
+        assert_eq!(fair.select().await, Ok((1, 11)));
+        assert_eq!(fair.select().await, Ok((0, 3)));
+        assert_eq!(fair.select().await, Ok((1, 12)));
+
+        tx_a.finish().await;
+        tx_b.finish().await;
+        assert_eq!(fair.select().await, Err(Finished));
+    }
+
+    #[tokio::test]
+    async fn for_each() {
+        let queue: &'static mut Queue<u32, 4> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let producer = tokio::spawn(async move {
+            tx.enqueue_iter(0..10).await;
+            tx.finish().await;
+        });
+
+        let mut sum = 0;
+        rx.for_each(|item| {
+            sum += item;
+            async { tokio::task::yield_now().await }
+        })
+        .await;
+
+        producer.await.unwrap();
+        assert_eq!(sum, 45);
+    }
+
+    #[cfg(feature = "futures")]
+    #[tokio::test]
+    async fn fused() {
+        use futures_core::future::FusedFuture;
+
+        let mut queue: Queue<u32, 4> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let mut enqueue = tx.enqueue(1);
+        assert!(!enqueue.is_terminated());
+        (&mut enqueue).await.unwrap();
+        assert!(enqueue.is_terminated());
+
+        let mut dequeue = rx.dequeue();
+        assert!(!dequeue.is_terminated());
+        assert_eq!((&mut dequeue).await, Ok(1));
+        assert!(dequeue.is_terminated());
+    }
+
+    #[tokio::test]
+    async fn select_loop() {
+        use embassy_futures::select::{select, Either};
+
+        let queue: &'static mut Queue<u32, 4> = Box::leak(Box::new(Queue::new()));
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let producer = tokio::task::spawn(async move {
+            tx.enqueue_iter(0..1000).await;
+            tx.finish().await;
+        });
+
+        // Abandon the dequeue whenever the other branch is ready first
+        let mut values = Vec::new();
+        loop {
+            match select(rx.dequeue(), tokio::task::yield_now()).await {
+                Either::First(Ok(value)) => values.push(value),
+                Either::First(Err(Finished)) => break,
+                Either::Second(()) => {}
+            }
+        }
+
+        producer.await.unwrap();
+        assert_eq!(values, (0..1000).collect::<Vec<_>>());
+    }
 }
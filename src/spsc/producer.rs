@@ -1,23 +1,46 @@
 use core::{
-    future::Future,
-    task::{Poll, Waker},
+    future::{poll_fn, Future},
+    sync::atomic::Ordering,
+    task::{Context, Poll, Waker},
 };
 
-use heapless::spsc::Producer as HProducer;
+#[cfg(feature = "futures")]
+use futures_core::future::FusedFuture;
 
-use crate::{log::*, mutex::Mutex, waker::WakerRegistration};
+#[cfg(feature = "diagnostics")]
+use crate::diagnostics::{Waiter, WaiterName};
+use crate::{
+    builder::OverflowPolicy,
+    channel::{Eviction, Flavor},
+    instrument::Side,
+    log::*,
+    metrics::{Metrics, MetricsSource},
+    time::Deadline,
+    waker::{Name, WakerRegistration, NO_NAME},
+};
+
+use super::{Owned, Queue, Storage, WakerState};
 
 /// The error value that can be returned by
 /// the fallible [`Producer::try_enqueue`] method.
+///
+/// Instead of retrying in a loop, an operation that should be
+/// retried can be passed to [`retry`](crate::retry::retry).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ProducerError<T> {
     /// Waking the consumer would block.
     ///
+    /// Holds the value if it was not enqueued either, because the queue is full.
+    /// Otherwise, the value was enqueued, but the consumer may not notice it until
+    /// it is woken with [`Producer::try_wake_consumer`].
+    ///
     /// Retrying is possible, but is highly discouraged
     /// as it may block forever.
     ///
     /// It only works if releasing the lock held by the
     /// consumer preempts the code that performs the retries.
-    WouldBlock,
+    WouldBlock(Option<T>),
     /// Attempting to enqueue a value failed because the queue
     /// is full.
     ///
@@ -27,64 +50,429 @@ pub enum ProducerError<T> {
     /// It only works if dequeueing an item from the backing
     /// queue preempts the code that performs the retries.
     Full(T),
+    /// The [`Consumer`](super::Consumer) was dropped, so the value
+    /// would never be dequeued.
+    Disconnected(T),
+}
+
+impl<T> ProducerError<T> {
+    /// Returns the value that was not enqueued, if any.
+    pub fn value(&self) -> Option<&T> {
+        match self {
+            Self::WouldBlock(value) => value.as_ref(),
+            Self::Full(value) | Self::Disconnected(value) => Some(value),
+        }
+    }
+
+    /// Take the value that was not enqueued, if any.
+    pub fn into_value(self) -> Option<T> {
+        match self {
+            Self::WouldBlock(value) => value,
+            Self::Full(value) | Self::Disconnected(value) => Some(value),
+        }
+    }
+}
+
+impl<T> core::fmt::Display for ProducerError<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::WouldBlock(_) => "waking the consumer would block",
+            Self::Full(_) => "the queue is full",
+            Self::Disconnected(_) => "the consumer was dropped",
+        })
+    }
+}
+
+impl<T> core::error::Error for ProducerError<T> where T: core::fmt::Debug {}
+
+/// The error that a [`ProducerFuture`] resolves to if its value was not enqueued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EnqueueError<T> {
+    /// The queue was full, and fails enqueues with [`OverflowPolicy::Fail`].
+    Full(T),
+    /// The [`Consumer`](super::Consumer) was dropped, so the value
+    /// would never be dequeued.
+    Disconnected(T),
+}
+
+impl<T> EnqueueError<T> {
+    /// Take the value that was not enqueued.
+    pub fn into_value(self) -> T {
+        match self {
+            Self::Full(value) | Self::Disconnected(value) => value,
+        }
+    }
+
+    /// Map the value that was not enqueued with `f`, keeping the reason.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> EnqueueError<U> {
+        match self {
+            Self::Full(value) => EnqueueError::Full(f(value)),
+            Self::Disconnected(value) => EnqueueError::Disconnected(f(value)),
+        }
+    }
+}
+
+impl<T> core::fmt::Display for EnqueueError<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            Self::Full(_) => "the queue is full",
+            Self::Disconnected(_) => "the consumer was dropped",
+        })
+    }
 }
 
+impl<T> core::error::Error for EnqueueError<T> where T: core::fmt::Debug {}
+
 /// An async producer
-pub struct Producer<'queue, T, const N: usize>
+///
+/// Dropping it finishes the stream, like [`Producer::finish`], but the consumer can tell
+/// the two apart with [`Consumer::is_disconnected`](super::Consumer::is_disconnected).
+pub struct Producer<'queue, T, const N: usize, B = Owned<T, N>>
 where
     T: Unpin,
+    B: Storage<T, N>,
 {
-    inner: HProducer<'queue, T, N>,
-    producer_waker: &'queue Mutex<WakerRegistration>,
-    consumer_waker: &'queue Mutex<WakerRegistration>,
+    pub(super) queue: &'queue Queue<T, N, B>,
+    /// The value of the last enqueue future that was dropped before enqueueing it.
+    unsent: Option<T>,
+    /// Whether the slots that hold no item were initialized for a write window.
+    pub(super) initialized: bool,
+    name: Name,
 }
 
-impl<'queue, T, const N: usize> Producer<'queue, T, N>
+impl<'queue, T, const N: usize, B> Producer<'queue, T, N, B>
 where
     T: Unpin,
+    B: Storage<T, N>,
 {
-    pub(crate) fn new(
-        producer: HProducer<'queue, T, N>,
-        producer_waker: &'queue Mutex<WakerRegistration>,
-        consumer_waker: &'queue Mutex<WakerRegistration>,
-    ) -> Self {
+    pub(crate) fn new(queue: &'queue Queue<T, N, B>) -> Self {
         Self {
-            inner: producer,
-            producer_waker,
-            consumer_waker,
+            queue,
+            unsent: None,
+            initialized: false,
+            name: NO_NAME,
         }
     }
 
+    /// Set the name that is reported for this producer while it waits.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_name(&mut self, name: impl Into<WaiterName>) {
+        self.name = Some(name.into());
+    }
+
+    /// Call `f` for every task that is waiting on the backing queue.
+    #[cfg(feature = "diagnostics")]
+    pub fn waiters(&self, f: impl FnMut(Waiter)) {
+        self.queue.waiters(f)
+    }
+
     /// Check if an item can be enqueued.
     ///
-    /// If this returns true, at least the first subsequent [`Self::enqueue`] will succeed
-    /// immediately.
+    /// If this returns true, at least the first subsequent [`Self::enqueue`] will resolve
+    /// immediately. It only fails if the [`Consumer`](super::Consumer) was dropped.
     pub fn ready(&self) -> bool {
-        self.inner.ready()
+        let overflow = self.queue.core.config.overflow;
+        !self.queue.inner.is_full()
+            || !matches!(overflow, OverflowPolicy::Block | OverflowPolicy::Fail)
+            || self.is_disconnected()
+    }
+
+    /// Returns true if the [`Consumer`](super::Consumer) was dropped, so that enqueued
+    /// items are never dequeued.
+    ///
+    /// Once it is, enqueues no longer enqueue anything: they hand the value back right
+    /// away, e.g. as [`EnqueueError::Disconnected`]. The items that are still in the
+    /// queue are dequeued by the next consumer if the queue is split again.
+    pub fn is_disconnected(&self) -> bool {
+        self.queue.disconnected.load(Ordering::Acquire)
     }
 
     /// Returns the maximum number of elements the queue can hold.
     pub fn capacity(&self) -> usize {
-        self.inner.capacity()
+        self.queue.inner.capacity()
     }
 
     /// Returns the amount of elements currently in the queue.
     pub fn len(&self) -> usize {
-        self.inner.len()
+        self.queue.inner.len()
+    }
+
+    /// Returns true if the queue currently holds no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the [`Metrics`] of the backing queue, if it was
+    /// built with metrics enabled.
+    pub fn metrics(&self) -> Option<Metrics> {
+        self.queue.core.metrics()
+    }
+
+    /// Returns a [`MetricsSource`] for the metrics of the backing queue, which can
+    /// be kept after this handle was moved elsewhere.
+    pub fn metrics_source(&self) -> MetricsSource<'queue> {
+        MetricsSource::new(&self.queue.core.metrics)
     }
 
     /// Enqueue `value` into the backing queue.
     ///
     /// The returned Future only resolves once the value was
-    /// succesfully enqueued.
-    pub fn enqueue<'me>(&'me mut self, value: T) -> ProducerFuture<'me, 'queue, T, N> {
-        let value = self.inner.enqueue(value).err();
+    /// succesfully enqueued. [`ProducerFuture::cancel`] hands the value back before that,
+    /// and dropping the future keeps it for [`Producer::take_unsent`].
+    ///
+    /// If the queue is full and fails enqueues with [`OverflowPolicy::Fail`], it resolves
+    /// to [`EnqueueError::Full`] right away, handing the value back. Once the
+    /// [`Consumer`](super::Consumer) is dropped, it resolves to
+    /// [`EnqueueError::Disconnected`] without enqueueing the value.
+    #[must_use = "the value may not be enqueued unless the returned future is awaited"]
+    pub fn enqueue<'me>(&'me mut self, value: T) -> ProducerFuture<'me, 'queue, T, N, B> {
+        // A disconnected queue hands the value back once the future is polled
+        let value = if self.is_disconnected() {
+            Some(value)
+        } else {
+            self.push(value).err()
+        };
+        if value.is_none() {
+            self.notify_consumer_or_defer();
+        }
         ProducerFuture {
             producer: self,
             value_to_enqueue: value,
+            registration: None,
+            terminated: false,
         }
     }
 
+    /// Enqueue `value` from a hand-written future, without an [`Producer::enqueue`] future.
+    ///
+    /// Like the [`ProducerFuture`], the value is taken out of `value` once it was enqueued,
+    /// or once the queue rejected it. While this is pending, the value stays in `value`,
+    /// and the waker of `cx` is woken once there may be room for it.
+    pub fn poll_enqueue(
+        &mut self,
+        cx: &mut Context<'_>,
+        value: &mut Option<T>,
+    ) -> Poll<Result<(), EnqueueError<T>>> {
+        self.poll_push(cx, value, &mut None)
+    }
+
+    /// Enqueue `value` into the backing queue, unless there is no space for it
+    /// before `deadline`.
+    ///
+    /// The returned future resolves once the value was enqueued, or hands the value
+    /// back once the deadline has passed. The value is also handed back right away if
+    /// the queue fails enqueues with [`OverflowPolicy::Fail`], or once the
+    /// [`Consumer`](super::Consumer) was dropped. Dropping the future before any of that
+    /// keeps the value for [`Producer::take_unsent`].
+    #[must_use = "the value may not be enqueued unless the returned future is awaited"]
+    pub fn enqueue_before<'me, 'clock, const W: usize>(
+        &'me mut self,
+        value: T,
+        deadline: Deadline<'clock, W>,
+    ) -> EnqueueBeforeFuture<'me, 'queue, 'clock, T, N, W, B> {
+        EnqueueBeforeFuture {
+            producer: self,
+            value_to_enqueue: Some(value),
+            deadline,
+            registration: None,
+        }
+    }
+
+    /// Take the value of the last enqueue future that was dropped before it enqueued it.
+    ///
+    /// The futures returned by [`Producer::enqueue`] and [`Producer::enqueue_before`] keep
+    /// their value here when they are dropped, e.g. because a timeout cancelled them, so
+    /// that the enqueue can be retried. Only the value of the last one is kept: a value
+    /// that is not taken before the next future is dropped is dropped with it.
+    pub fn take_unsent(&mut self) -> Option<T> {
+        self.unsent.take()
+    }
+
+    /// Enqueue `value` without waiting, waking the consumer or deferring that to
+    /// whoever is holding its waker.
+    pub(crate) fn enqueue_or_defer(&mut self, value: T) -> Result<(), T> {
+        self.push(value)?;
+        self.notify_consumer_or_defer();
+        Ok(())
+    }
+
+    /// Enqueue every item of `iter`, in order.
+    ///
+    /// The items are only taken from the iterator once there is space for them, and the
+    /// returned future resolves to the amount of items that were enqueued once the
+    /// iterator is exhausted. Items that the overflow policy of the queue drops are not
+    /// counted. If the [`Consumer`](super::Consumer) is dropped, it stops taking items
+    /// from the iterator, and resolves to the amount of items that were enqueued before.
+    /// Dropping the future while it waits for room keeps the item that it was waiting with
+    /// for [`Producer::take_unsent`].
+    ///
+    /// The consumer is woken once for every batch of items that could be enqueued without
+    /// waiting, instead of once for every item.
+    pub async fn enqueue_iter<I>(&mut self, iter: I) -> usize
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut budget = self.queue.core.budget();
+        let mut sent = 0;
+        // Whether items were enqueued since the consumer was last woken
+        let mut quiet = false;
+        for value in iter {
+            if self.is_disconnected() {
+                debug!("Consumer dropped, giving up on enqueueing");
+                break;
+            }
+            let value = match self.push_or_drop(value) {
+                Ok(enqueued) => {
+                    quiet |= enqueued;
+                    sent += enqueued as usize;
+                    None
+                }
+                Err(value) => Some(value),
+            };
+
+            // Wake the consumer before waiting for it, or yielding to it
+            if quiet && (value.is_some() || budget.runs_out()) {
+                self.notify_consumer_or_defer();
+                quiet = false;
+            }
+
+            match value {
+                None => budget.spend(core::future::ready(())).await,
+                Some(value) => {
+                    let enqueue = ProducerFuture {
+                        producer: &mut *self,
+                        value_to_enqueue: Some(value),
+                        registration: None,
+                        terminated: false,
+                    };
+                    // Only a queue that waits for room gets here, so the value is
+                    // only rejected once the consumer is gone.
+                    if budget.spend(enqueue).await.is_err() {
+                        debug!("Consumer dropped, giving up on enqueueing");
+                        break;
+                    }
+                    sent += 1;
+                }
+            }
+        }
+
+        if quiet {
+            self.notify_consumer_or_defer();
+        }
+        sent
+    }
+
+    /// Copy as many items of `items` into the free slots of the queue as fit, without
+    /// waiting.
+    ///
+    /// Returns how many items at the start of `items` were enqueued. The overflow policy
+    /// of the queue is not applied: only free slots are filled, and nothing is dropped.
+    /// Nothing is enqueued once the [`Consumer`](super::Consumer) was dropped.
+    pub fn enqueue_slice(&mut self, items: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        if self.is_disconnected() {
+            return 0;
+        }
+        let sent = self.push_slice(items);
+        if sent > 0 {
+            self.notify_consumer_or_defer();
+        }
+        sent
+    }
+
+    /// Enqueue every item of `items`, in order.
+    ///
+    /// Whenever there is free space, as many items as fit are copied into the queue at
+    /// once, and the returned future resolves once all of them were enqueued. Like
+    /// [`Producer::enqueue_slice`], this waits for free space regardless of the overflow
+    /// policy of the queue. Dropping the future before it resolves leaves the items that
+    /// were already copied in the queue. If the [`Consumer`](super::Consumer) is dropped,
+    /// it resolves without waiting for room for the rest.
+    pub async fn enqueue_all(&mut self, items: &[T])
+    where
+        T: Copy,
+    {
+        let mut sent = 0;
+        while sent < items.len() {
+            self.wait_for_capacity(1).await;
+            sent += self.enqueue_slice(&items[sent..]);
+            if self.is_disconnected() {
+                debug!("Consumer dropped, giving up on enqueueing");
+                return;
+            }
+        }
+    }
+
+    /// Wait until the queue has at least `n` free slots.
+    ///
+    /// Once the returned future resolves, `n` items can be enqueued without waiting, as
+    /// only this producer fills the free slots. The consumer only wakes the producer once
+    /// the low watermark of the queue is reached, so this may resolve later than once
+    /// there are `n` free slots. Resolves right away once the
+    /// [`Consumer`](super::Consumer) was dropped, as enqueues no longer wait then.
+    ///
+    /// # Panics
+    /// Panics if `n` is larger than the capacity of the queue.
+    pub async fn wait_for_capacity(&mut self, n: usize) {
+        assert!(n <= self.capacity(), "The queue can not hold {} items", n);
+
+        let done = |producer: &Self| {
+            producer.capacity() - producer.len() >= n || producer.is_disconnected()
+        };
+        poll_fn(|cx| {
+            if done(self) {
+                return Poll::Ready(());
+            }
+            if self.try_register_waker(cx.waker()).is_none() || done(self) {
+                // Check again after registering, in case the consumer
+                // woke the old waker in between.
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Wait until the consumer has dequeued every item in the queue.
+    ///
+    /// The returned future resolves once the queue is empty, or once the
+    /// [`Consumer`](super::Consumer) was dropped. An empty queue is always below the low
+    /// watermark, so the consumer wakes the producer once it got there.
+    pub async fn flush(&mut self) {
+        // Every slot is free once the queue is empty
+        self.wait_for_capacity(self.capacity()).await
+    }
+
+    /// Finish the stream.
+    ///
+    /// Once the [`Consumer`](super::Consumer) has dequeued all items that are still in
+    /// the queue, its dequeues resolve to [`Finished`](super::Finished). The stream is
+    /// finished and the consumer woken immediately, or the wake is deferred to whoever is
+    /// holding its waker, so the returned future does not have to be awaited.
+    pub fn finish(self) -> FinishFuture<'queue, T, N, B> {
+        debug!("Finishing stream");
+        let queue = self.queue;
+        queue.core.finish();
+        queue.core.metrics.woke(Side::Dequeuers);
+        queue.consumer_waker.wake_or_defer(WakerRegistration::wake);
+        FinishFuture {
+            _producer: self,
+            terminated: false,
+        }
+    }
+
+    /// Close the queue, so that no more items are sent through it.
+    ///
+    /// This is [`Producer::finish`] under the name other channels use: closing the queue
+    /// finishes the stream, and once the [`Consumer`](super::Consumer) has dequeued the
+    /// items that are left, its dequeues resolve to [`Finished`](super::Finished).
+    pub fn close(self) -> FinishFuture<'queue, T, N, B> {
+        self.finish()
+    }
+
     /// Try to enqueue `value` into the backing queue.
     ///
     /// If [`ProducerError::WouldBlock`] is returned, the [`Consumer`](super::Consumer)
@@ -92,22 +480,91 @@ where
     /// In such a case, the application can attempt to re-wake the [`Consumer`](super::Consumer)
     /// by calling [`Producer::try_wake_consumer`].
     pub fn try_enqueue(&mut self, value: T) -> Result<(), ProducerError<T>> {
-        let res = self.inner.enqueue(value).map_err(ProducerError::Full);
+        if self.is_disconnected() {
+            return Err(ProducerError::Disconnected(value));
+        }
+        let res = self.push(value);
 
-        if !self.try_wake_consumer() {
-            return Err(ProducerError::WouldBlock);
+        if !self.notify_consumer() {
+            return Err(ProducerError::WouldBlock(res.err()));
         }
 
-        res
+        res.map_err(ProducerError::Full)
+    }
+
+    /// Attempt to enqueue `value`, retrying up to `attempts` times in total while
+    /// the queue is contended.
+    ///
+    /// Like [`Producer::try_enqueue`], but [`ProducerError::WouldBlock`] is only returned
+    /// if the consumer was still holding the lock after the last attempt. A full
+    /// queue is not retried.
+    pub fn try_enqueue_for(&mut self, value: T, attempts: usize) -> Result<(), ProducerError<T>> {
+        if self.is_disconnected() {
+            return Err(ProducerError::Disconnected(value));
+        }
+        let res = self.push(value);
+
+        let mut woken = self.notify_consumer();
+        for _ in 1..attempts {
+            if woken {
+                break;
+            }
+            core::hint::spin_loop();
+            woken = self.notify_consumer();
+        }
+
+        if !woken {
+            return Err(ProducerError::WouldBlock(res.err()));
+        }
+
+        res.map_err(ProducerError::Full)
+    }
+
+    /// Enqueue `value` without waiting, overwriting the oldest item if the queue is full.
+    ///
+    /// Returns the item that was overwritten, if any. The consumer is woken, or the wake
+    /// is deferred to whoever is holding its waker, so this may be called from an interrupt.
+    ///
+    /// This works whatever the overflow policy of the queue. If the queue is full while
+    /// the consumer holds the oldest item, the value is handed back instead, without
+    /// overwriting anything: it does while it dequeues, and from a
+    /// [`Consumer::peek`](super::Consumer::peek), [`Consumer::peek_mut`](super::Consumer::peek_mut)
+    /// or read window until it dequeues again. Once it has, there is room for the value.
+    pub fn enqueue_overwrite(&mut self, value: T) -> Result<Option<T>, T> {
+        let queue = self.queue;
+
+        // SAFETY: we are the only producer.
+        let oldest = match unsafe { queue.inner.enqueue(value) } {
+            Ok(()) => None,
+            Err(value) => {
+                let Some(_head) = queue.head_lock.try_lock() else {
+                    return Err(value);
+                };
+
+                trace!("Queue full, overwriting oldest value");
+                // SAFETY: the consumer only dequeues while holding the head lock.
+                let oldest = unsafe { queue.inner.dequeue() };
+                // SAFETY: we are the only producer.
+                unsafe { queue.inner.enqueue(value) }?;
+                queue.core.metrics.dropped_many(oldest.is_some() as usize);
+                oldest
+            }
+        };
+
+        queue.core.metrics.enqueued();
+        self.notify_consumer_or_defer();
+        Ok(oldest)
     }
 
     /// Try to wake the [`Consumer`](super::Consumer) associated with the backing queue.
     ///
     /// Returns true if the waker was waked succesfully.
+    #[must_use = "the consumer may not have been woken"]
     pub fn try_wake_consumer(&mut self) -> bool {
-        if let Some(mut wk) = self.consumer_waker.try_lock() {
+        if let Some(mut wk) = self.queue.consumer_waker.try_lock() {
             wk.wake();
             trace!("Waking consumer");
+            self.queue.core.metrics.woke(Side::Dequeuers);
             true
         } else {
             debug!("Failed to wake consumer");
@@ -115,72 +572,447 @@ where
         }
     }
 
+    /// Wake the [`Consumer`](super::Consumer) if the queue has reached the
+    /// high watermark.
+    ///
+    /// Returns false if the consumer should have been woken, but waking failed.
+    pub(super) fn notify_consumer(&mut self) -> bool {
+        if self.len() < self.queue.core.config.high_watermark {
+            true
+        } else {
+            self.try_wake_consumer()
+        }
+    }
+
+    /// Wake the [`Consumer`](super::Consumer) if the queue has reached the
+    /// high watermark, or defer it to whoever is holding the consumer waker.
+    pub(super) fn notify_consumer_or_defer(&mut self) {
+        if self.len() >= self.queue.core.config.high_watermark {
+            self.queue.core.metrics.woke(Side::Dequeuers);
+            self.queue
+                .consumer_waker
+                .wake_or_defer(WakerRegistration::wake);
+        }
+    }
+
+    /// Enqueue `value` into the backing queue, applying the overflow
+    /// policy of the queue if it is full.
+    ///
+    /// Only returns the value if the producer has to wait before it can be enqueued, or if
+    /// the queue rejects it.
+    fn push(&mut self, value: T) -> Result<(), T> {
+        let queue = self.queue;
+        queue.core.push(self, value)
+    }
+
+    /// Enqueue the value in `value` like [`Producer::push`], or register the waker of `cx`,
+    /// storing the generation of the registration in `registration`.
+    ///
+    /// The value is only left in `value` if the producer has to wait.
+    fn poll_push(
+        &mut self,
+        cx: &mut Context<'_>,
+        value: &mut Option<T>,
+        registration: &mut Option<u32>,
+    ) -> Poll<Result<(), EnqueueError<T>>> {
+        let Some(v) = value.take() else {
+            return Poll::Ready(Ok(()));
+        };
+
+        if self.is_disconnected() {
+            trace!("Consumer dropped, handing back value");
+            return Poll::Ready(Err(EnqueueError::Disconnected(v)));
+        }
+        let v = match self.push(v) {
+            Ok(()) => {
+                // Wake the consumer because we've enqueued our value
+                self.notify_consumer_or_defer();
+                return Poll::Ready(Ok(()));
+            }
+            Err(v) => v,
+        };
+
+        if self.queue.core.config.overflow == OverflowPolicy::Fail {
+            trace!("Queue full, failing enqueue");
+            return Poll::Ready(Err(EnqueueError::Full(v)));
+        }
+        *value = Some(v);
+
+        *registration = self.try_register_waker(cx.waker());
+        // Check again, in case the consumer made room before we registered
+        if registration.is_none() || self.ready() {
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+
+    /// Copy the items of `items` that fit into the free slots of the queue.
+    fn push_slice(&mut self, items: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        let queue = self.queue;
+
+        let mut sent = 0;
+        // The free slots may wrap around the end of the backing buffer
+        for _ in 0..2 {
+            // SAFETY: we are the only producer.
+            let region = unsafe { queue.inner.tail_region() };
+            let amount = region.len().min(items.len() - sent);
+            for (slot, item) in region.iter_mut().zip(&items[sent..sent + amount]) {
+                slot.write(*item);
+            }
+            // SAFETY: as above, and the first `amount` slots were initialized.
+            unsafe { queue.inner.commit(amount) };
+            sent += amount;
+        }
+
+        queue.core.metrics.enqueued_many(sent);
+        sent
+    }
+
+    /// Like [`Producer::push`], but drops the value if the queue rejects it.
+    ///
+    /// Returns true if the value was enqueued, and false if it was dropped.
+    fn push_or_drop(&mut self, value: T) -> Result<bool, T> {
+        let queue = self.queue;
+        queue.core.push_or_drop(self, value)
+    }
+
     /// Try to register `waker` as the waker for this [`Producer`]
     ///
-    /// Returns true if the waker was registered succesfully.
-    fn try_register_waker(&mut self, waker: &Waker) -> bool {
-        if let Some(mut wk) = self.producer_waker.try_lock() {
-            wk.register(waker);
+    /// Returns the generation of the registration if the waker was registered succesfully.
+    pub(super) fn try_register_waker(&mut self, waker: &Waker) -> Option<u32> {
+        let generation = self
+            .queue
+            .producer_waker
+            .try_lock()
+            .map(|mut wk| wk.register_named(waker, self.name));
+        if generation.is_some() {
             trace!("Registered producer waker");
-            true
         } else {
             trace!("Failed to register producer waker");
-            false
+        }
+        generation
+    }
+
+    /// Remove the waker registered in `generation`, if no other waker was
+    /// registered since.
+    pub(super) fn unregister_waker(&mut self, generation: u32) {
+        if let Some(mut wk) = self.queue.producer_waker.try_lock() {
+            wk.unregister(generation);
         }
     }
 }
 
-pub struct ProducerFuture<'producer, 'queue, T, const N: usize>
+impl<T, const N: usize, B> core::fmt::Debug for Producer<'_, T, N, B>
 where
     T: Unpin,
+    B: Storage<T, N>,
 {
-    producer: &'producer mut Producer<'queue, T, N>,
-    value_to_enqueue: Option<T>,
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Producer")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .field("waker", &WakerState::live(&self.queue.producer_waker))
+            .field("disconnected", &self.is_disconnected())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T, const N: usize, B> defmt::Format for Producer<'_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Producer {{ len: {}, capacity: {}, waker: {}, disconnected: {} }}",
+            self.len(),
+            self.capacity(),
+            WakerState::live(&self.queue.producer_waker),
+            self.is_disconnected(),
+        )
+    }
 }
 
-impl<T, const N: usize> Future for ProducerFuture<'_, '_, T, N>
+impl<T, const N: usize, B> Drop for Producer<'_, T, N, B>
 where
     T: Unpin,
+    B: Storage<T, N>,
+{
+    fn drop(&mut self) {
+        // Dropping the producer finishes the stream, so that the consumer
+        // does not wait for items that never come.
+        let queue = self.queue;
+        if !queue.core.is_finished() {
+            debug!("Producer dropped, finishing stream");
+            // Set before finishing, so the consumer sees it once the stream is finished
+            queue.producer_dropped.store(true, Ordering::Release);
+            queue.core.finish();
+            queue.core.metrics.woke(Side::Dequeuers);
+            queue.consumer_waker.wake_or_defer(WakerRegistration::wake);
+        }
+    }
+}
+
+/// The future returned by [`Producer::finish`].
+///
+/// The stream is already finished when it is returned, so it does not have to be awaited.
+pub struct FinishFuture<'queue, T, const N: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// The producer is only dropped with the future.
+    _producer: Producer<'queue, T, N, B>,
+    terminated: bool,
+}
+
+impl<T, const N: usize, B> Future for FinishFuture<'_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
 {
     type Output = ();
 
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        _cx: &mut core::task::Context<'_>,
+    ) -> Poll<Self::Output> {
+        // The consumer was already woken by `Producer::finish`
+        self.get_mut().terminated = true;
+        Poll::Ready(())
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, const N: usize, B> FusedFuture for FinishFuture<'_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<T, const N: usize, B> Flavor<T> for Producer<'_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn insert(&mut self, value: T) -> Result<(), T> {
+        // SAFETY: we are the only producer.
+        unsafe { self.queue.inner.enqueue(value) }
+    }
+
+    fn evict(&mut self, value: T, eviction: Eviction) -> Result<usize, T> {
+        let queue = self.queue;
+
+        // If the consumer is dequeueing right now, there will be
+        // space for `value` soon.
+        let Some(_head) = queue.head_lock.try_lock() else {
+            return Err(value);
+        };
+
+        match eviction {
+            Eviction::Oldest => {
+                trace!("Queue full, dropping oldest value");
+                // SAFETY: the consumer only dequeues while holding the head lock.
+                let dropped = unsafe { queue.inner.dequeue() }.is_some();
+
+                // SAFETY: we are the only producer.
+                unsafe { queue.inner.enqueue(value) }.map(|()| dropped as usize)
+            }
+            Eviction::Newest => {
+                // SAFETY: we are the only producer.
+                let value = match unsafe { queue.inner.enqueue(value) } {
+                    Ok(()) => return Ok(0),
+                    // A queue without capacity has nothing to replace.
+                    Err(value) if queue.inner.len() == 0 => return Err(value),
+                    Err(value) => value,
+                };
+
+                trace!("Queue full, replacing newest value");
+                // SAFETY: we are the only producer, and the consumer only
+                // dequeues while holding the head lock.
+                drop(unsafe { queue.inner.replace_newest(value) });
+                Ok(1)
+            }
+        }
+    }
+}
+
+/// The future returned by [`Producer::enqueue`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ProducerFuture<'producer, 'queue, T, const N: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    producer: &'producer mut Producer<'queue, T, N, B>,
+    value_to_enqueue: Option<T>,
+    /// The generation of the registered waker.
+    registration: Option<u32>,
+    terminated: bool,
+}
+
+impl<T, const N: usize, B> ProducerFuture<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// Stop enqueueing, and hand back the value if it was not enqueued yet.
+    ///
+    /// This allows retrying the enqueue later, e.g. after a timeout, without
+    /// dropping the value.
+    pub fn cancel(mut self) -> Option<T> {
+        self.value_to_enqueue.take()
+    }
+}
+
+impl<T, const N: usize, B> Future for ProducerFuture<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    type Output = Result<(), EnqueueError<T>>;
+
     fn poll(
         self: core::pin::Pin<&mut Self>,
         cx: &mut core::task::Context<'_>,
     ) -> Poll<Self::Output> {
         trace!("Poll producer");
-        let try_wake_consumer = |me: &mut Self| {
-            if me.producer.try_wake_consumer() {
-                return Poll::Ready(());
-            } else {
-                cx.waker().wake_by_ref();
-                Poll::Pending
-            }
-        };
+        let me = self.get_mut();
+
+        let res = me
+            .producer
+            .poll_push(cx, &mut me.value_to_enqueue, &mut me.registration);
+        me.terminated = res.is_ready();
+        res
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, const N: usize, B> FusedFuture for ProducerFuture<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<T, const N: usize, B> Drop for ProducerFuture<'_, '_, T, N, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn drop(&mut self) {
+        if let Some(generation) = self.registration {
+            self.producer.unregister_waker(generation);
+        }
+        if let Some(value) = self.value_to_enqueue.take() {
+            self.producer.unsent = Some(value);
+        }
+    }
+}
+
+/// The future returned by [`Producer::enqueue_before`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct EnqueueBeforeFuture<
+    'producer,
+    'queue,
+    'clock,
+    T,
+    const N: usize,
+    const W: usize,
+    B = Owned<T, N>,
+> where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    producer: &'producer mut Producer<'queue, T, N, B>,
+    value_to_enqueue: Option<T>,
+    deadline: Deadline<'clock, W>,
+    /// The generation of the registered waker.
+    registration: Option<u32>,
+}
+
+impl<T, const N: usize, const W: usize, B> Future for EnqueueBeforeFuture<'_, '_, '_, T, N, W, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    type Output = Result<(), T>;
 
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Self::Output> {
+        trace!("Poll producer with deadline");
         let me = self.get_mut();
-        let prod = &mut me.producer;
-        let val_to_enqueue = &mut me.value_to_enqueue;
 
-        let value = if let Some(value) = val_to_enqueue.take() {
-            value
-        } else {
-            // Try to wake the consumer because we've enqueued our value
-            return try_wake_consumer(me);
-        };
+        let value = me
+            .value_to_enqueue
+            .take()
+            .expect("`EnqueueBeforeFuture` polled after completion");
 
-        let failed_enqueue_value = if let Some(value) = prod.inner.enqueue(value).err() {
-            value
-        } else {
-            // Try to wake the consumer because we've enqueued our value
-            return try_wake_consumer(me);
+        if me.producer.is_disconnected() {
+            debug!("Consumer dropped, handing back value");
+            return Poll::Ready(Err(value));
+        }
+        let value = match me.producer.push(value) {
+            Ok(()) => {
+                me.producer.notify_consumer_or_defer();
+                return Poll::Ready(Ok(()));
+            }
+            Err(value) => value,
         };
 
-        me.value_to_enqueue = Some(failed_enqueue_value);
+        let rejected = me.producer.queue.core.config.overflow == OverflowPolicy::Fail;
+        if rejected || me.deadline.poll_passed(cx.waker()) {
+            debug!("Deadline passed, handing back value");
+            return Poll::Ready(Err(value));
+        }
+        me.value_to_enqueue = Some(value);
 
-        if !me.producer.try_register_waker(cx.waker()) {
+        me.registration = me.producer.try_register_waker(cx.waker());
+        // Check again, in case the consumer made room before we registered
+        if me.registration.is_none() || me.producer.ready() {
             cx.waker().wake_by_ref();
         }
         Poll::Pending
     }
 }
+
+#[cfg(feature = "futures")]
+impl<T, const N: usize, const W: usize, B> FusedFuture
+    for EnqueueBeforeFuture<'_, '_, '_, T, N, W, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn is_terminated(&self) -> bool {
+        self.value_to_enqueue.is_none()
+    }
+}
+
+impl<T, const N: usize, const W: usize, B> Drop for EnqueueBeforeFuture<'_, '_, '_, T, N, W, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn drop(&mut self) {
+        if let Some(generation) = self.registration {
+            self.producer.unregister_waker(generation);
+        }
+        if let Some(value) = self.value_to_enqueue.take() {
+            self.producer.unsent = Some(value);
+        }
+    }
+}
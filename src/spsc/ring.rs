@@ -0,0 +1,289 @@
+//! The ring buffer backing [`Queue`](super::Queue).
+//!
+//! This is the algorithm of [`heapless::spsc::Queue`], but all of the operations
+//! are performed through a shared reference. This lets the [`Producer`](super::Producer)
+//! retire the oldest item itself when the queue is configured to do so.
+//!
+//! Unlike in [`heapless::spsc::Queue`], the head and tail count up to twice the amount
+//! of slots before wrapping around. A full ring can then be told apart from an empty one
+//! without keeping a slot free, so all slots are usable.
+
+use core::{
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ptr, slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::storage::Storage;
+
+pub(crate) struct Ring<T, const N: usize, B>
+where
+    B: Storage<T, N>,
+{
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    /// The amount of slots in use at most, which is all of them unless limited.
+    capacity: usize,
+    buffer: B,
+    /// The storage decides whether the ring can be shared.
+    _items: PhantomData<fn() -> T>,
+}
+
+impl<T, const N: usize, B> Ring<T, N, B>
+where
+    B: Storage<T, N>,
+{
+    /// Checked at compile time, when a ring is created.
+    const VALID_SIZE: () = assert!(
+        N > 0 && N <= usize::MAX / 2,
+        "The queue must hold at least one item"
+    );
+
+    pub const fn new(buffer: B) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID_SIZE;
+
+        Self {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            capacity: N,
+            buffer,
+            _items: PhantomData,
+        }
+    }
+
+    /// Only use `capacity` slots of the ring.
+    pub fn limit(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// The amount of slots in the storage, which is `N` unless it is sized at runtime.
+    fn slots(&self) -> usize {
+        self.buffer.slots()
+    }
+
+    /// A pointer to the slot that position `pos` refers to.
+    unsafe fn slot(&self, pos: usize) -> *mut MaybeUninit<T> {
+        self.buffer.as_ptr().add(pos % self.slots())
+    }
+
+    fn advance(&self, pos: usize, amount: usize) -> usize {
+        (pos + amount) % (2 * self.slots())
+    }
+
+    fn distance(&self, head: usize, tail: usize) -> usize {
+        (tail + 2 * self.slots() - head) % (2 * self.slots())
+    }
+
+    /// The maximum amount of elements the ring can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity.min(self.slots())
+    }
+
+    /// The amount of elements currently in the ring.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        self.distance(head, tail)
+    }
+
+    /// The position of the tail, which changes whenever an item is enqueued.
+    pub fn tail_position(&self) -> usize {
+        self.tail.load(Ordering::Acquire)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.capacity()
+    }
+
+    /// Enqueue `val` at the tail of the ring.
+    ///
+    /// # Safety
+    /// Only a single context may enqueue at any given time.
+    pub unsafe fn enqueue(&self, val: T) -> Result<(), T> {
+        let current_tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        if self.distance(head, current_tail) >= self.capacity() {
+            return Err(val);
+        }
+
+        (*self.slot(current_tail)).write(val);
+        self.tail
+            .store(self.advance(current_tail, 1), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Dequeue the item at the head of the ring.
+    ///
+    /// # Safety
+    /// Only a single context may dequeue at any given time.
+    pub unsafe fn dequeue(&self) -> Option<T> {
+        let current_head = self.head.load(Ordering::Relaxed);
+
+        if current_head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let value = (*self.slot(current_head)).assume_init_read();
+        self.head
+            .store(self.advance(current_head, 1), Ordering::Release);
+
+        Some(value)
+    }
+
+    /// Replace the item at the tail of the ring with `val`, returning the old item.
+    ///
+    /// # Safety
+    /// Only a single context may enqueue at any given time, no other context may
+    /// dequeue, and the ring may not be empty.
+    pub unsafe fn replace_newest(&self, val: T) -> T {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let newest = self.advance(tail, 2 * self.slots() - 1);
+
+        (*self.slot(newest)).as_mut_ptr().replace(val)
+    }
+
+    /// Remove the item at `index` from the head of the ring, moving the items in front
+    /// of it up by one slot.
+    ///
+    /// # Safety
+    /// Only a single context may dequeue at any given time, no item may be borrowed, and
+    /// `index` must be less than the length of the ring.
+    pub unsafe fn remove(&self, index: usize) -> T {
+        let head = self.head.load(Ordering::Relaxed);
+        let value = (*self.slot(head + index)).assume_init_read();
+        // The producer does not touch the slots of items, so they can be moved.
+        for offset in (0..index).rev() {
+            ptr::copy_nonoverlapping(self.slot(head + offset), self.slot(head + offset + 1), 1);
+        }
+        self.head.store(self.advance(head, 1), Ordering::Release);
+        value
+    }
+
+    /// The item at `index` from the head of the ring, if there is one.
+    ///
+    /// # Safety
+    /// No other context may dequeue while the item is borrowed.
+    pub unsafe fn get(&self, index: usize) -> Option<&T> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if index >= self.distance(head, tail) {
+            return None;
+        }
+        Some((*self.slot(self.advance(head, index))).assume_init_ref())
+    }
+
+    /// The contiguous region of items starting at the head of the ring.
+    ///
+    /// If the items wrap around the end of the buffer, this only contains
+    /// the items up to the end of the buffer.
+    ///
+    /// # Safety
+    /// Only a single context may dequeue at any given time, and the region
+    /// must no longer be used once [`Ring::consume`] is called.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn head_region(&self) -> &mut [T] {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        let len = self
+            .distance(head, tail)
+            .min(self.slots() - head % self.slots());
+
+        slice::from_raw_parts_mut(self.slot(head).cast::<T>(), len)
+    }
+
+    /// All items of the ring, as the region starting at the head, and the region that
+    /// wraps around to the start of the buffer.
+    ///
+    /// # Safety
+    /// Only a single context may dequeue at any given time, and the regions
+    /// must no longer be used once [`Ring::consume`] is called.
+    #[cfg(feature = "framing")]
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn head_regions(&self) -> (&mut [T], &mut [T]) {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        let len = self.distance(head, tail);
+        let first = len.min(self.slots() - head % self.slots());
+
+        (
+            slice::from_raw_parts_mut(self.slot(head).cast::<T>(), first),
+            slice::from_raw_parts_mut(self.buffer.as_ptr().cast::<T>(), len - first),
+        )
+    }
+
+    /// The contiguous region of free slots starting at the tail of the ring.
+    ///
+    /// If the free slots wrap around the end of the buffer, this only contains
+    /// the slots up to the end of the buffer.
+    ///
+    /// # Safety
+    /// Only a single context may enqueue at any given time, and the region
+    /// must no longer be used once [`Ring::commit`] is called.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn tail_region(&self) -> &mut [MaybeUninit<T>] {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        let free = self.capacity().saturating_sub(self.distance(head, tail));
+        let len = free.min(self.slots() - tail % self.slots());
+
+        slice::from_raw_parts_mut(self.slot(tail), len)
+    }
+
+    /// Write `value` into every slot that does not hold an item.
+    ///
+    /// # Safety
+    /// Only a single context may enqueue at any given time, and no
+    /// [`Ring::tail_region`] may be in use.
+    pub unsafe fn fill_free(&self, value: T)
+    where
+        T: Copy,
+    {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        // The consumer only frees slots in the meantime, which hold an item.
+        for offset in 0..self.slots() - self.distance(head, tail) {
+            (*self.slot(tail + offset)).write(value);
+        }
+    }
+
+    /// Add the first `amount` slots of the [`Ring::tail_region`] to the ring.
+    ///
+    /// # Safety
+    /// Only a single context may enqueue at any given time, and the first
+    /// `amount` slots of the tail region must have been initialized.
+    pub unsafe fn commit(&self, amount: usize) {
+        let tail = self.tail.load(Ordering::Relaxed);
+        self.tail
+            .store(self.advance(tail, amount), Ordering::Release);
+    }
+
+    /// Drop the first `amount` items of the [`Ring::head_region`], and
+    /// remove them from the ring.
+    ///
+    /// # Safety
+    /// Only a single context may dequeue at any given time, and `amount` may
+    /// not be larger than the length of the head region.
+    pub unsafe fn consume(&self, amount: usize) {
+        let region = self.head_region();
+        ptr::drop_in_place(&mut region[..amount]);
+
+        let head = self.head.load(Ordering::Relaxed);
+        self.head
+            .store(self.advance(head, amount), Ordering::Release);
+    }
+}
+
+impl<T, const N: usize, B> Drop for Ring<T, N, B>
+where
+    B: Storage<T, N>,
+{
+    fn drop(&mut self) {
+        // SAFETY: we have exclusive access to the ring.
+        while unsafe { self.dequeue() }.is_some() {}
+    }
+}
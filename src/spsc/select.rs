@@ -0,0 +1,197 @@
+use core::{cmp::Reverse, future::Future, task::Poll};
+
+use crate::log::*;
+
+use super::{Consumer, Finished, Owned, Storage};
+
+/// The order in which a [`Selector`] picks between consumers that have items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SelectOrder {
+    /// The first consumer of those that hold the most items, like [`select_array`].
+    Fullest,
+    /// Always the first consumer that has items, for the lowest latency on a
+    /// consumer with a high priority. Later consumers can starve.
+    Biased,
+    /// The consumers take turns, starting with the one after the consumer that
+    /// was dequeued from last.
+    Fair,
+}
+
+/// A set of consumers to select from repeatedly, in a fixed [`SelectOrder`].
+///
+/// The selector keeps track of whose turn it is for [`SelectOrder::Fair`].
+pub struct Selector<'queue, T, const N: usize, const K: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    consumers: [Consumer<'queue, T, N, B>; K],
+    order: SelectOrder,
+    /// The consumer to check first, if the order is fair.
+    turn: usize,
+}
+
+impl<'queue, T, const N: usize, const K: usize, B> Selector<'queue, T, N, K, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// Create a new [`Selector`], selecting from `consumers` in `order`.
+    pub fn new(consumers: [Consumer<'queue, T, N, B>; K], order: SelectOrder) -> Self {
+        Self {
+            consumers,
+            order,
+            turn: 0,
+        }
+    }
+
+    /// Dequeue an item from whichever of the consumers has one first.
+    ///
+    /// Like [`select_array`], but consumers that have items are picked in the order of
+    /// the selector.
+    #[must_use = "no item is dequeued unless the returned future is awaited"]
+    pub fn select(&mut self) -> SelectArray<'_, 'queue, T, N, K, B> {
+        SelectArray {
+            consumers: &mut self.consumers,
+            registrations: [None; K],
+            order: self.order,
+            turn: Some(&mut self.turn),
+        }
+    }
+
+    /// Returns the consumers of the selector.
+    pub fn consumers(&mut self) -> &mut [Consumer<'queue, T, N, B>; K] {
+        &mut self.consumers
+    }
+
+    /// Returns the consumers of the selector.
+    pub fn into_inner(self) -> [Consumer<'queue, T, N, B>; K] {
+        self.consumers
+    }
+}
+
+/// Dequeue an item from whichever of `consumers` has one first.
+///
+/// Resolves to the index of the consumer and its item. If several consumers have items,
+/// the first one of those that hold the most is dequeued from, so a busy consumer can not
+/// starve the others. Consumers whose stream is finished are skipped, and once all of them
+/// are, the returned future resolves to [`Finished`].
+///
+/// Like [`Consumer::dequeue`], the returned future can be dropped at any time without
+/// losing an item.
+#[must_use = "no item is dequeued unless the returned future is awaited"]
+pub fn select_array<'me, 'queue, T, const N: usize, const K: usize, B>(
+    consumers: &'me mut [Consumer<'queue, T, N, B>; K],
+) -> SelectArray<'me, 'queue, T, N, K, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    SelectArray {
+        consumers,
+        registrations: [None; K],
+        order: SelectOrder::Fullest,
+        turn: None,
+    }
+}
+
+/// The future returned by [`select_array`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct SelectArray<'me, 'queue, T, const N: usize, const K: usize, B = Owned<T, N>>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    consumers: &'me mut [Consumer<'queue, T, N, B>; K],
+    /// The generations of the registered wakers.
+    registrations: [Option<u32>; K],
+    order: SelectOrder,
+    /// The consumer to check first, if the order is fair.
+    turn: Option<&'me mut usize>,
+}
+
+impl<T, const N: usize, const K: usize, B> SelectArray<'_, '_, T, N, K, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    /// Returns the index of the consumer to dequeue from, if any have items.
+    fn pick(&self) -> Option<usize> {
+        let has_items = |&index: &usize| !self.consumers[index].is_empty();
+        match self.order {
+            SelectOrder::Fullest => (0..K)
+                .filter(has_items)
+                .min_by_key(|&index| Reverse(self.consumers[index].len())),
+            SelectOrder::Biased => (0..K).find(has_items),
+            SelectOrder::Fair => {
+                let turn = self.turn.as_deref().copied().unwrap_or(0);
+                (turn..K).chain(0..turn).find(has_items)
+            }
+        }
+    }
+}
+
+impl<T, const N: usize, const K: usize, B> Future for SelectArray<'_, '_, T, N, K, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    type Output = Result<(usize, T), Finished>;
+
+    fn poll(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut core::task::Context<'_>,
+    ) -> Poll<Self::Output> {
+        trace!("Poll select");
+        let me = self.get_mut();
+
+        if let Some(index) = me.pick() {
+            let consumer = &mut me.consumers[index];
+            return match consumer.pop() {
+                Ok(value) => {
+                    consumer.notify_producer_or_defer();
+                    if let Some(turn) = me.turn.as_deref_mut() {
+                        *turn = (index + 1) % K;
+                    }
+                    Poll::Ready(Ok((index, value)))
+                }
+                Err(_) => {
+                    // The producer is making room for a new item
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            };
+        }
+
+        if me.consumers.iter().all(Consumer::is_finished) {
+            return Poll::Ready(Err(Finished));
+        }
+
+        for (consumer, registration) in me.consumers.iter_mut().zip(&mut me.registrations) {
+            if consumer.is_finished() {
+                continue;
+            }
+            *registration = consumer.try_register_waker(cx.waker());
+            // Check again, in case an item was enqueued before we registered
+            if registration.is_none() || consumer.changed() {
+                cx.waker().wake_by_ref();
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<T, const N: usize, const K: usize, B> Drop for SelectArray<'_, '_, T, N, K, B>
+where
+    T: Unpin,
+    B: Storage<T, N>,
+{
+    fn drop(&mut self) {
+        for (consumer, registration) in self.consumers.iter_mut().zip(self.registrations) {
+            if let Some(generation) = registration {
+                consumer.unregister_waker(generation);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! The memory backing a [`Queue`](super::Queue).
+//!
+//! By default, the slots of a queue are stored inside of the queue itself. With
+//! [`External`] storage, they can be placed somewhere else, e.g. in a RAM region that
+//! the queue object itself does not live in:
+//!
+//! ```
+//! use core::{mem::MaybeUninit, ptr::addr_of_mut};
+//! use heapless_async_queues::spsc::{External, Queue};
+//!
+//! // e.g. `#[link_section = ".dtcm"]`
+//! static mut SLOTS: [MaybeUninit<u32>; 8] = [const { MaybeUninit::uninit() }; 8];
+//!
+//! // SAFETY: this is the only reference to `SLOTS`.
+//! let slots = unsafe { &mut *addr_of_mut!(SLOTS) };
+//! let mut queue = Queue::with_storage(External::new(slots));
+//! let split = queue.split();
+//! ```
+//!
+//! [`Slice`] storage holds as many slots as the slice it is created from, for queues
+//! whose capacity is only known at runtime, like a [`SliceQueue`](super::SliceQueue).
+//!
+//! The [`static_queue!`](crate::static_queue) macro declares both in one go, and lets the
+//! queue state, i.e. its wakers and indices, and the slots be placed independently. Only
+//! the slots then take up space in e.g. DMA-capable RAM:
+//!
+//! ```
+//! use heapless_async_queues::static_queue;
+//!
+//! let queue = static_queue! {
+//!     #[cfg_attr(target_os = "none", link_section = ".dtcm")]
+//!     queue: u32, 8;
+//!     #[cfg_attr(target_os = "none", link_section = ".axisram")]
+//!     slots
+//! }
+//! .unwrap();
+//! let split = queue.split();
+//! ```
+
+use core::{cell::UnsafeCell, marker::PhantomData, mem::MaybeUninit};
+
+/// The `N` slots backing a [`Queue`](super::Queue).
+///
+/// # Safety
+/// [`Storage::as_ptr`] must always return the same pointer to [`Storage::slots`]
+/// consecutive slots, which must be valid for reads and writes while the storage exists,
+/// and must not be accessed by anything but the queue. The amount of slots must never
+/// change, and must be at least one and at most [`DYNAMIC`].
+pub unsafe trait Storage<T, const N: usize> {
+    /// Returns a pointer to the first slot.
+    fn as_ptr(&self) -> *mut MaybeUninit<T>;
+
+    /// Returns the amount of slots, which is `N` unless the storage is sized at runtime.
+    fn slots(&self) -> usize {
+        N
+    }
+}
+
+/// The `N` of storage that is sized at runtime, like [`Slice`].
+pub const DYNAMIC: usize = usize::MAX / 2;
+
+/// Storage inside of the [`Queue`](super::Queue) itself.
+pub struct Owned<T, const N: usize>([UnsafeCell<MaybeUninit<T>>; N]);
+
+impl<T, const N: usize> Owned<T, N> {
+    pub(crate) const fn new() -> Self {
+        Self([const { UnsafeCell::new(MaybeUninit::uninit()) }; N])
+    }
+}
+
+// SAFETY: the queue only accesses the slots from a single producer and a single
+// consumer, which the items are sent between.
+unsafe impl<T, const N: usize> Sync for Owned<T, N> where T: Send {}
+
+// SAFETY: the slots are only accessed through `UnsafeCell`s.
+unsafe impl<T, const N: usize> Storage<T, N> for Owned<T, N> {
+    fn as_ptr(&self) -> *mut MaybeUninit<T> {
+        UnsafeCell::raw_get(self.0.as_ptr())
+    }
+}
+
+/// Storage in a `'static` buffer provided by the application.
+pub struct External<T, const N: usize> {
+    slots: *mut MaybeUninit<T>,
+}
+
+// SAFETY: the buffer is exclusively borrowed, so it can be used from any context
+// that the items can be sent to.
+unsafe impl<T, const N: usize> Send for External<T, N> where T: Send {}
+
+// SAFETY: as for `Owned`.
+unsafe impl<T, const N: usize> Sync for External<T, N> where T: Send {}
+
+impl<T, const N: usize> External<T, N> {
+    /// Use `slots` as the storage of a queue.
+    pub const fn new(slots: &'static mut [MaybeUninit<T>; N]) -> Self {
+        Self {
+            slots: slots.as_mut_ptr(),
+        }
+    }
+}
+
+// SAFETY: the pointer is derived from an exclusive `'static` borrow of `N` slots.
+unsafe impl<T, const N: usize> Storage<T, N> for External<T, N> {
+    fn as_ptr(&self) -> *mut MaybeUninit<T> {
+        self.slots
+    }
+}
+
+/// Storage in a slice provided by the application, whose length is only known at runtime.
+pub struct Slice<'a, T> {
+    slots: *mut MaybeUninit<T>,
+    len: usize,
+    _slots: PhantomData<&'a mut [MaybeUninit<T>]>,
+}
+
+// SAFETY: as for `External`.
+unsafe impl<T> Send for Slice<'_, T> where T: Send {}
+
+// SAFETY: as for `Owned`.
+unsafe impl<T> Sync for Slice<'_, T> where T: Send {}
+
+impl<'a, T> Slice<'a, T> {
+    /// Use `slots` as the storage of a queue.
+    ///
+    /// # Panics
+    /// If `slots` is empty, or longer than [`DYNAMIC`].
+    pub const fn new(slots: &'a mut [MaybeUninit<T>]) -> Self {
+        assert!(!slots.is_empty(), "The queue must hold at least one item");
+        assert!(
+            slots.len() <= DYNAMIC,
+            "The queue can hold at most `DYNAMIC` items"
+        );
+        Self {
+            len: slots.len(),
+            slots: slots.as_mut_ptr(),
+            _slots: PhantomData,
+        }
+    }
+}
+
+// SAFETY: the pointer is derived from an exclusive borrow of `len` slots, which outlives
+// the storage.
+unsafe impl<T> Storage<T, DYNAMIC> for Slice<'_, T> {
+    fn as_ptr(&self) -> *mut MaybeUninit<T> {
+        self.slots
+    }
+
+    fn slots(&self) -> usize {
+        self.len
+    }
+}
+
+/// Declare a static [`Queue`](crate::spsc::Queue) with [`External`] storage, and return
+/// a `&'static mut` reference to it.
+///
+/// The attributes before `queue` are applied to the static that holds the queue state,
+/// and the ones before `slots` to the static that holds the slots, so both can be placed
+/// in their own linker section. Neither static is initialized before the macro runs.
+///
+/// Returns `None` if this invocation of the macro already returned its queue.
+///
+/// ```
+/// use heapless_async_queues::{spsc::{External, Queue}, static_queue};
+///
+/// fn take() -> Option<&'static mut Queue<u8, 4, External<u8, 4>>> {
+///     static_queue!(queue: u8, 4)
+/// }
+///
+/// assert!(take().is_some());
+/// assert!(take().is_none());
+/// ```
+#[macro_export]
+macro_rules! static_queue {
+    (
+        $(#[$queue_attr:meta])* queue: $t:ty, $n:expr
+        $(; $(#[$slots_attr:meta])* slots)? $(;)?
+    ) => {{
+        use ::core::{mem::MaybeUninit, ptr::addr_of_mut, sync::atomic::{AtomicBool, Ordering}};
+        use $crate::spsc::{External, Queue};
+
+        $($(#[$slots_attr])*)?
+        static mut SLOTS: [MaybeUninit<$t>; $n] = [const { MaybeUninit::uninit() }; $n];
+        $(#[$queue_attr])*
+        static mut QUEUE: MaybeUninit<Queue<$t, $n, External<$t, $n>>> = MaybeUninit::uninit();
+        static TAKEN: AtomicBool = AtomicBool::new(false);
+
+        if TAKEN.swap(true, Ordering::AcqRel) {
+            None
+        } else {
+            // SAFETY: `TAKEN` makes sure that the statics are only borrowed once.
+            let (slots, queue) = unsafe { (&mut *addr_of_mut!(SLOTS), &mut *addr_of_mut!(QUEUE)) };
+            Some(queue.write(Queue::with_storage(External::new(slots))))
+        }
+    }};
+}
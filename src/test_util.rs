@@ -0,0 +1,208 @@
+//! Deterministic polling of futures, for testing code that uses the queues.
+//!
+//! An [`Executor`] only polls its tasks when told to, and every task is woken through
+//! its own [`MockWaker`], which counts its wakes and its live clones. This makes it
+//! possible to script an exact interleaving of polls, wakes and drops:
+//!
+//! ```
+//! use heapless_async_queues::{spsc::{Queue, Split}, test_util::Executor};
+//!
+//! let mut queue: Queue<u32, 1> = Queue::new();
+//! let Split { producer: mut tx, consumer: mut rx } = queue.split();
+//!
+//! let mut executor = Executor::new();
+//! let consumer = executor.spawn(async move {
+//!     assert_eq!(rx.dequeue().await, Ok(1));
+//! });
+//!
+//! // The consumer registers its waker, and waits
+//! assert!(!executor.poll(consumer));
+//! assert_eq!(executor.waker(consumer).clones(), 1);
+//!
+//! assert!(tx.try_enqueue(1).is_ok());
+//! assert!(executor.is_woken(consumer));
+//! assert_eq!(executor.run_until_stalled(), 1);
+//! assert!(executor.is_done(consumer));
+//! ```
+
+extern crate std;
+
+use core::{future::Future, pin::Pin, task::Context};
+use std::{
+    boxed::Box,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Wake,
+    vec::Vec,
+};
+
+#[derive(Default)]
+struct WakeCount(AtomicUsize);
+
+impl Wake for WakeCount {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A [`Waker`](core::task::Waker) that records how it is used.
+#[derive(Default)]
+pub struct MockWaker {
+    count: Arc<WakeCount>,
+}
+
+impl MockWaker {
+    /// Create a new mock waker, which has not been woken.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a [`Waker`](core::task::Waker) that wakes this mock waker.
+    pub fn waker(&self) -> core::task::Waker {
+        self.count.clone().into()
+    }
+
+    /// Returns how often this mock waker has been woken.
+    pub fn wakes(&self) -> usize {
+        self.count.0.load(Ordering::SeqCst)
+    }
+
+    /// Returns how many of the wakers created by [`MockWaker::waker`] still exist,
+    /// e.g. because they are registered with a queue.
+    pub fn clones(&self) -> usize {
+        Arc::strong_count(&self.count) - 1
+    }
+}
+
+/// A task of an [`Executor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskId(usize);
+
+struct Task<'a> {
+    future: Option<Pin<Box<dyn Future<Output = ()> + 'a>>>,
+    waker: MockWaker,
+    /// The amount of wakes that were seen by the last poll.
+    polled_wakes: usize,
+}
+
+/// A single-threaded executor that only polls its tasks when told to.
+///
+/// Tasks are polled in the order that they were spawned in, so every run of a test
+/// sees the same interleaving.
+#[derive(Default)]
+pub struct Executor<'a> {
+    tasks: Vec<Task<'a>>,
+}
+
+impl<'a> Executor<'a> {
+    /// Create an executor without any tasks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `future` as a task. It is not polled until it is told to.
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'a) -> TaskId {
+        self.tasks.push(Task {
+            future: Some(Box::pin(future)),
+            waker: MockWaker::new(),
+            polled_wakes: 0,
+        });
+        TaskId(self.tasks.len() - 1)
+    }
+
+    /// Poll the task `id` once, whether it was woken or not.
+    ///
+    /// Returns true if the task has completed.
+    pub fn poll(&mut self, id: TaskId) -> bool {
+        let task = &mut self.tasks[id.0];
+        let Some(future) = task.future.as_mut() else {
+            return true;
+        };
+
+        task.polled_wakes = task.waker.wakes();
+        let waker = task.waker.waker();
+        if future
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready()
+        {
+            task.future = None;
+        }
+        task.future.is_none()
+    }
+
+    /// Poll every task that was woken since it was last polled, until none are.
+    ///
+    /// Returns the amount of polls. A task that keeps waking itself keeps this
+    /// from returning.
+    pub fn run_until_stalled(&mut self) -> usize {
+        let mut polls = 0;
+        while let Some(id) = (0..self.tasks.len())
+            .map(TaskId)
+            .find(|&id| self.is_woken(id))
+        {
+            self.poll(id);
+            polls += 1;
+        }
+        polls
+    }
+
+    /// Drop the task `id`, as if it was cancelled.
+    pub fn cancel(&mut self, id: TaskId) {
+        self.tasks[id.0].future = None;
+    }
+
+    /// Check if the task `id` was woken since it was last polled.
+    pub fn is_woken(&self, id: TaskId) -> bool {
+        let task = &self.tasks[id.0];
+        task.future.is_some() && task.waker.wakes() > task.polled_wakes
+    }
+
+    /// Check if the task `id` has completed, or was cancelled.
+    pub fn is_done(&self, id: TaskId) -> bool {
+        self.tasks[id.0].future.is_none()
+    }
+
+    /// Returns the [`MockWaker`] of the task `id`.
+    pub fn waker(&self, id: TaskId) -> &MockWaker {
+        &self.tasks[id.0].waker
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::spsc::{Queue, Split};
+
+    use super::Executor;
+
+    #[test]
+    fn cancel_after_registering() {
+        let mut queue: Queue<u32, 1> = Queue::new();
+        let Split {
+            producer: mut tx,
+            consumer: mut rx,
+        } = queue.split();
+
+        let mut executor = Executor::new();
+        let consumer = executor.spawn(async {
+            let _ = rx.dequeue().await;
+        });
+
+        assert!(!executor.poll(consumer));
+        assert_eq!(executor.waker(consumer).clones(), 1);
+
+        // Cancelling the waiting consumer releases its waker
+        executor.cancel(consumer);
+        assert_eq!(executor.waker(consumer).clones(), 0);
+
+        assert!(tx.try_enqueue(1).is_ok());
+        assert_eq!(executor.waker(consumer).wakes(), 0);
+        assert_eq!(executor.run_until_stalled(), 0);
+    }
+}
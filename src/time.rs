@@ -0,0 +1,136 @@
+//! A tick-based clock, for operations with deadlines.
+//!
+//! Like the [`Watchdog`](crate::watchdog::Watchdog), a [`Clock`] does not keep track of
+//! time by itself: the application advances it by calling [`Clock::tick`], for example
+//! from a timer interrupt. Operations that take a [`Deadline`] give up once the clock
+//! has reached it.
+//!
+//! ```
+//! use heapless_async_queues::{spsc::{Queue, Split}, time::Clock};
+//!
+//! static CLOCK: Clock = Clock::new();
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let mut queue: Queue<u32, 1> = Queue::new();
+//! let Split { producer: mut tx, consumer: _rx } = queue.split();
+//! tx.enqueue(0).await.unwrap();
+//!
+//! # let timer = tokio::spawn(async { loop { CLOCK.tick(); tokio::task::yield_now().await } });
+//! // Nothing dequeues, so the value is handed back after 10 ticks
+//! assert_eq!(tx.enqueue_before(1, CLOCK.after(10)).await, Err(1));
+//! # timer.abort();
+//! # });
+//! ```
+
+use core::{
+    sync::atomic::{AtomicU32, Ordering},
+    task::Waker,
+};
+
+use crate::{builder::WakeStrategy, waker_set::WakerSet};
+
+/// A point in time, in ticks of a [`Clock`].
+///
+/// The tick count wraps around, so an instant is only meaningful for deadlines
+/// that are less than `u32::MAX / 2` ticks away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instant(u32);
+
+impl Instant {
+    /// Create an instant at `ticks` ticks.
+    pub const fn from_ticks(ticks: u32) -> Self {
+        Self(ticks)
+    }
+
+    /// Returns the tick count of this instant.
+    pub const fn ticks(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if `self` is `other` or later.
+    fn has_reached(self, other: Instant) -> bool {
+        self.0.wrapping_sub(other.0) as i32 >= 0
+    }
+}
+
+/// A clock that is advanced by the application.
+///
+/// Up to `W` futures can wait for a deadline of the clock at the same time.
+pub struct Clock<const W: usize = 4> {
+    now: AtomicU32,
+    wakers: WakerSet<W>,
+}
+
+impl<const W: usize> Clock<W> {
+    /// Create a new clock, at tick zero.
+    pub const fn new() -> Self {
+        Self {
+            now: AtomicU32::new(0),
+            wakers: WakerSet::new(),
+        }
+    }
+
+    /// Advance the clock by one tick, waking everything that waits for a deadline.
+    ///
+    /// This may be called from any context, including interrupts.
+    pub fn tick(&self) {
+        self.now.fetch_add(1, Ordering::AcqRel);
+        self.wakers.wake_or_defer(WakeStrategy::All);
+    }
+
+    /// Returns the current instant.
+    pub fn now(&self) -> Instant {
+        Instant(self.now.load(Ordering::Acquire))
+    }
+
+    /// Returns the deadline that is `ticks` ticks from now.
+    pub fn after(&self, ticks: u32) -> Deadline<'_, W> {
+        self.at(Instant(self.now().0.wrapping_add(ticks)))
+    }
+
+    /// Returns the deadline at `instant`.
+    pub fn at(&self, instant: Instant) -> Deadline<'_, W> {
+        Deadline {
+            clock: self,
+            instant,
+        }
+    }
+}
+
+impl<const W: usize> Default for Clock<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A deadline on a [`Clock`].
+#[derive(Clone, Copy)]
+pub struct Deadline<'clock, const W: usize = 4> {
+    clock: &'clock Clock<W>,
+    instant: Instant,
+}
+
+impl<const W: usize> Deadline<'_, W> {
+    /// Returns the instant of the deadline.
+    pub fn instant(&self) -> Instant {
+        self.instant
+    }
+
+    /// Returns true if the clock has reached the deadline.
+    pub fn has_passed(&self) -> bool {
+        self.clock.now().has_reached(self.instant)
+    }
+
+    /// Check if the deadline has passed, and register `waker` to be woken on the
+    /// next tick if it has not.
+    pub(crate) fn poll_passed(&self, waker: &Waker) -> bool {
+        if self.has_passed() {
+            return true;
+        }
+        if !self.clock.wakers.register(waker) {
+            waker.wake_by_ref();
+        }
+        // Check again, in case the clock ticked before we registered
+        self.has_passed()
+    }
+}
@@ -0,0 +1,203 @@
+//! Handles that stamp every item with the instant it was enqueued at.
+//!
+//! A [`TimestampedProducer`] stores every item together with the current [`Instant`] of a
+//! [`Clock`], and the [`TimestampedConsumer`] hands it out again with the item. The
+//! consumer also keeps [`Latency`] statistics of how long items spent in the queue, so
+//! the latency through a queue can be measured without changing the message type.
+//!
+//! ```
+//! use heapless_async_queues::{
+//!     spsc::{Queue, Split},
+//!     time::{Clock, Instant},
+//!     timestamped::{TimestampedConsumer, TimestampedProducer},
+//! };
+//!
+//! static CLOCK: Clock = Clock::new();
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let mut queue: Queue<(Instant, u32), 4> = Queue::new();
+//! let Split { producer, consumer } = queue.split();
+//! let mut tx = TimestampedProducer::new(producer, &CLOCK);
+//! let mut rx = TimestampedConsumer::new(consumer, &CLOCK);
+//!
+//! tx.enqueue(1).await.unwrap();
+//! CLOCK.tick();
+//! let (enqueued_at, value) = rx.dequeue().await.unwrap();
+//! assert_eq!((enqueued_at.ticks(), value), (0, 1));
+//! assert_eq!(rx.latency().max, 1);
+//! # });
+//! ```
+
+use crate::{
+    spsc::{Consumer, EnqueueError, Finished, Owned, Producer, Storage},
+    time::{Clock, Instant},
+};
+
+/// Statistics of how long items spent in a queue, in ticks of a [`Clock`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latency {
+    /// The amount of items that were measured.
+    pub count: u32,
+    /// The shortest time an item spent in the queue.
+    pub min: u32,
+    /// The longest time an item spent in the queue.
+    pub max: u32,
+    /// The time all measured items spent in the queue together.
+    pub total: u64,
+}
+
+impl Latency {
+    /// Returns the average time an item spent in the queue, if any item was measured.
+    pub fn mean(&self) -> Option<u32> {
+        (self.count > 0).then(|| (self.total / u64::from(self.count)) as u32)
+    }
+
+    fn record(&mut self, ticks: u32) {
+        self.min = if self.count == 0 {
+            ticks
+        } else {
+            self.min.min(ticks)
+        };
+        self.max = self.max.max(ticks);
+        self.count = self.count.saturating_add(1);
+        self.total = self.total.saturating_add(u64::from(ticks));
+    }
+}
+
+/// A [`Producer`] that stamps every item with the instant it was enqueued at.
+pub struct TimestampedProducer<
+    'queue,
+    'clock,
+    T,
+    const N: usize,
+    const W: usize = 4,
+    B = Owned<(Instant, T), N>,
+> where
+    T: Unpin,
+    B: Storage<(Instant, T), N>,
+{
+    producer: Producer<'queue, (Instant, T), N, B>,
+    clock: &'clock Clock<W>,
+}
+
+impl<'queue, 'clock, T, const N: usize, const W: usize, B>
+    TimestampedProducer<'queue, 'clock, T, N, W, B>
+where
+    T: Unpin,
+    B: Storage<(Instant, T), N>,
+{
+    /// Stamp the items enqueued through `producer` with the instants of `clock`.
+    pub fn new(producer: Producer<'queue, (Instant, T), N, B>, clock: &'clock Clock<W>) -> Self {
+        Self { producer, clock }
+    }
+
+    /// Enqueue `value`, stamped with the current instant.
+    ///
+    /// The instant is taken when this is called, so time spent waiting for
+    /// room in the queue counts towards the latency. Resolves to an error like
+    /// [`Producer::enqueue`] if the value was not enqueued.
+    pub async fn enqueue(&mut self, value: T) -> Result<(), EnqueueError<T>> {
+        let now = self.clock.now();
+        let res = self.producer.enqueue((now, value)).await;
+        res.map_err(|err| err.map(|(_, value)| value))
+    }
+
+    /// Stop stamping items, and hand back the producer.
+    pub fn into_inner(self) -> Producer<'queue, (Instant, T), N, B> {
+        self.producer
+    }
+}
+
+/// A [`Consumer`] of items stamped by a [`TimestampedProducer`].
+pub struct TimestampedConsumer<
+    'queue,
+    'clock,
+    T,
+    const N: usize,
+    const W: usize = 4,
+    B = Owned<(Instant, T), N>,
+> where
+    T: Unpin,
+    B: Storage<(Instant, T), N>,
+{
+    consumer: Consumer<'queue, (Instant, T), N, B>,
+    clock: &'clock Clock<W>,
+    latency: Latency,
+}
+
+impl<'queue, 'clock, T, const N: usize, const W: usize, B>
+    TimestampedConsumer<'queue, 'clock, T, N, W, B>
+where
+    T: Unpin,
+    B: Storage<(Instant, T), N>,
+{
+    /// Measure the latency of the items dequeued through `consumer` with `clock`.
+    pub fn new(consumer: Consumer<'queue, (Instant, T), N, B>, clock: &'clock Clock<W>) -> Self {
+        Self {
+            consumer,
+            clock,
+            latency: Latency::default(),
+        }
+    }
+
+    /// Dequeue the next item, together with the instant it was enqueued at.
+    pub async fn dequeue(&mut self) -> Result<(Instant, T), Finished> {
+        let (enqueued_at, value) = self.consumer.dequeue().await?;
+        let now = self.clock.now();
+        self.latency
+            .record(now.ticks().wrapping_sub(enqueued_at.ticks()));
+        Ok((enqueued_at, value))
+    }
+
+    /// Returns the latency statistics of the items dequeued so far.
+    pub fn latency(&self) -> Latency {
+        self.latency
+    }
+
+    /// Start the latency statistics over.
+    pub fn reset_latency(&mut self) {
+        self.latency = Latency::default();
+    }
+
+    /// Stop measuring items, and hand back the consumer.
+    pub fn into_inner(self) -> Consumer<'queue, (Instant, T), N, B> {
+        self.consumer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Latency, TimestampedConsumer, TimestampedProducer};
+    use crate::{
+        spsc::{Queue, Split},
+        time::{Clock, Instant},
+    };
+
+    #[tokio::test]
+    async fn latency() {
+        static CLOCK: Clock = Clock::new();
+
+        let mut queue: Queue<(Instant, u32), 4> = Queue::new();
+        let Split { producer, consumer } = queue.split();
+        let mut tx = TimestampedProducer::new(producer, &CLOCK);
+        let mut rx = TimestampedConsumer::new(consumer, &CLOCK);
+        assert_eq!(rx.latency().mean(), None);
+
+        tx.enqueue(0).await.unwrap();
+        CLOCK.tick();
+        tx.enqueue(1).await.unwrap();
+        CLOCK.tick();
+        CLOCK.tick();
+
+        assert_eq!(rx.dequeue().await.unwrap().1, 0);
+        assert_eq!(rx.dequeue().await.unwrap(), (Instant::from_ticks(1), 1));
+        let latency = Latency {
+            count: 2,
+            min: 2,
+            max: 3,
+            total: 5,
+        };
+        assert_eq!(rx.latency(), latency);
+        assert_eq!(latency.mean(), Some(2));
+    }
+}
@@ -0,0 +1,234 @@
+//! A lock-free triple buffer, always holding the latest value.
+//!
+//! The [`Writer`] always owns a free slot to write the next value into, so writing never
+//! waits for the [`Reader`]. The reader always sees the latest value that was published,
+//! and intermediate values that it never got to see are skipped.
+
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{mutex::Mutex, waker::WakerRegistration};
+
+/// Set in the shared index if the slot holds a value the reader has not seen yet.
+const DIRTY: u8 = 0b100;
+const INDEX: u8 = 0b011;
+
+/// A triple buffer.
+pub struct TripleBuffer<T> {
+    slots: [UnsafeCell<T>; 3],
+    /// The slot that is owned by neither the writer nor the reader.
+    shared: AtomicU8,
+    /// The slot that is owned by the reader. It is only accessed by the reader, and
+    /// kept here so that the slots are handed out correctly when splitting again.
+    reader_slot: AtomicU8,
+    reader_waker: Mutex<WakerRegistration>,
+}
+
+unsafe impl<T> Sync for TripleBuffer<T> where T: Send {}
+
+/// The two halves of a split [`TripleBuffer`].
+pub struct Split<'buffer, T> {
+    /// The writing half of the triple buffer.
+    pub writer: Writer<'buffer, T>,
+    /// The reading half of the triple buffer.
+    pub reader: Reader<'buffer, T>,
+}
+
+impl<T> TripleBuffer<T> {
+    /// Create a new [`TripleBuffer`] from its three slots.
+    ///
+    /// The reader initially sees `initial`.
+    pub const fn new(initial: T, second: T, third: T) -> Self {
+        Self {
+            slots: [
+                UnsafeCell::new(second),
+                UnsafeCell::new(third),
+                UnsafeCell::new(initial),
+            ],
+            shared: AtomicU8::new(1),
+            reader_slot: AtomicU8::new(2),
+            reader_waker: Mutex::new(WakerRegistration::new()),
+        }
+    }
+
+    /// Split the triple buffer into a writer and reader.
+    ///
+    /// The reader sees the latest value that was published by a previous writer.
+    pub fn split(&mut self) -> Split<'_, T> {
+        let shared = *self.shared.get_mut() & INDEX;
+        let reader = *self.reader_slot.get_mut();
+        // The writer owns the slot that is neither shared nor owned by the reader.
+        let slot = 3 - shared - reader;
+
+        let buffer = &*self;
+        Split {
+            writer: Writer { buffer, slot },
+            reader: Reader { buffer },
+        }
+    }
+}
+
+impl<T> Default for TripleBuffer<T>
+where
+    T: Default,
+{
+    fn default() -> Self {
+        Self::new(T::default(), T::default(), T::default())
+    }
+}
+
+/// The writing half of a [`TripleBuffer`].
+pub struct Writer<'buffer, T> {
+    buffer: &'buffer TripleBuffer<T>,
+    slot: u8,
+}
+
+impl<T> Writer<'_, T> {
+    /// The slot that the next value is written into.
+    ///
+    /// It holds an older value, and only becomes visible to the
+    /// reader after [`Writer::publish`] is called.
+    pub fn buffer(&mut self) -> &mut T {
+        // SAFETY: the reader never accesses the slot owned by the writer.
+        unsafe { &mut *self.buffer.slots[self.slot as usize].get() }
+    }
+
+    /// Publish the slot returned by [`Writer::buffer`] as the latest value.
+    ///
+    /// This never waits. If the reader is registering its waker at the same
+    /// time, it is not woken, but checks for the new value afterwards.
+    pub fn publish(&mut self) {
+        let previous = self.buffer.shared.swap(self.slot | DIRTY, Ordering::AcqRel);
+        self.slot = previous & INDEX;
+
+        if let Some(mut wk) = self.buffer.reader_waker.try_lock() {
+            wk.wake();
+        }
+    }
+
+    /// Write `value` and publish it as the latest value.
+    pub fn write(&mut self, value: T) {
+        *self.buffer() = value;
+        self.publish();
+    }
+}
+
+/// The reading half of a [`TripleBuffer`].
+pub struct Reader<'buffer, T> {
+    buffer: &'buffer TripleBuffer<T>,
+}
+
+impl<'buffer, T> Reader<'buffer, T> {
+    /// Returns true if a value was published that has not been read yet.
+    pub fn has_changed(&self) -> bool {
+        self.buffer.shared.load(Ordering::Acquire) & DIRTY != 0
+    }
+
+    /// Read the latest published value.
+    pub fn read_latest(&mut self) -> &T {
+        let buffer = self.buffer;
+        let mut slot = buffer.reader_slot.load(Ordering::Relaxed);
+        if self.has_changed() {
+            let previous = buffer.shared.swap(slot, Ordering::AcqRel);
+            slot = previous & INDEX;
+            buffer.reader_slot.store(slot, Ordering::Relaxed);
+        }
+
+        // SAFETY: the writer never accesses the slot owned by the reader.
+        unsafe { &*buffer.slots[slot as usize].get() }
+    }
+
+    /// Wait for a new value to be published, and read it.
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub fn changed<'me>(&'me mut self) -> ChangedFuture<'me, 'buffer, T> {
+        ChangedFuture { reader: Some(self) }
+    }
+}
+
+/// The future returned by [`Reader::changed`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ChangedFuture<'reader, 'buffer, T> {
+    reader: Option<&'reader mut Reader<'buffer, T>>,
+}
+
+impl<'reader, T> Future for ChangedFuture<'reader, '_, T> {
+    type Output = &'reader T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.get_mut();
+        let reader = me.reader.as_mut().expect("polled after completion");
+
+        // Register before checking, so that a value published in
+        // between can not be missed.
+        if let Some(mut wk) = reader.buffer.reader_waker.try_lock() {
+            wk.register(cx.waker());
+        } else {
+            cx.waker().wake_by_ref();
+        }
+
+        if reader.has_changed() {
+            let reader = me.reader.take().unwrap();
+            Poll::Ready(reader.read_latest())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::boxed::Box;
+
+    use super::{Split, TripleBuffer};
+
+    #[tokio::test]
+    async fn latest_value() {
+        let buffer: &'static mut TripleBuffer<u32> = Box::leak(Box::default());
+        let Split {
+            mut writer,
+            mut reader,
+        } = buffer.split();
+
+        assert_eq!(*reader.read_latest(), 0);
+        assert!(!reader.has_changed());
+
+        // The writer never waits, and the reader skips to the latest value
+        for i in 1..=10 {
+            writer.write(i);
+        }
+        assert_eq!(*reader.read_latest(), 10);
+
+        let reader = tokio::task::spawn(async move { *reader.changed().await });
+        writer.write(11);
+        assert_eq!(reader.await.unwrap(), 11);
+    }
+
+    #[test]
+    fn split_again() {
+        let mut buffer: TripleBuffer<u32> = TripleBuffer::default();
+        {
+            let Split { mut writer, .. } = buffer.split();
+            writer.write(1);
+        }
+
+        // The slots are handed out again where the previous halves left them
+        let Split {
+            mut writer,
+            mut reader,
+        } = buffer.split();
+        let written: *const u32 = writer.buffer();
+        assert_eq!(*reader.read_latest(), 1);
+        assert!(!core::ptr::eq(written, reader.read_latest()));
+
+        writer.write(2);
+        let written: *const u32 = writer.buffer();
+        assert_eq!(*reader.read_latest(), 2);
+        assert!(!core::ptr::eq(written, reader.read_latest()));
+    }
+}
@@ -0,0 +1,129 @@
+use core::{
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    mutex::{Mutex, MutexGuard},
+    waker::WakerRegistration,
+};
+
+/// Wakers that can all be woken at once.
+pub trait WakeAll {
+    fn wake_all(&mut self);
+}
+
+impl WakeAll for WakerRegistration {
+    fn wake_all(&mut self) {
+        self.wake()
+    }
+}
+
+impl<const W: usize> WakeAll for [WakerRegistration; W] {
+    fn wake_all(&mut self) {
+        self.iter_mut().for_each(WakerRegistration::wake)
+    }
+}
+
+/// Wakers behind a try-lock.
+///
+/// A wake that can not take the lock is deferred to the holder of the lock, which
+/// performs it once it releases the lock. This lets a future hand out its result
+/// right away, instead of holding on to it until waking the other side succeeds.
+pub struct WakeLock<T> {
+    wakers: Mutex<T>,
+    deferred: AtomicBool,
+}
+
+impl<T> WakeLock<T>
+where
+    T: WakeAll,
+{
+    pub const fn new(wakers: T) -> Self {
+        Self {
+            wakers: Mutex::new(wakers),
+            deferred: AtomicBool::new(false),
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<WakeLockGuard<'_, T>> {
+        self.wakers.try_lock().map(|guard| WakeLockGuard {
+            lock: self,
+            guard: ManuallyDrop::new(guard),
+        })
+    }
+
+    /// Call `wake` with the wakers, or defer waking all of them to the
+    /// current holder of the lock.
+    pub fn wake_or_defer(&self, wake: impl FnOnce(&mut T)) {
+        if let Some(mut wakers) = self.try_lock() {
+            wake(&mut wakers);
+        } else {
+            // Deferring before trying again makes sure that either we, or the
+            // holder of the lock after releasing it, get to perform the wake.
+            self.deferred.store(true, Ordering::SeqCst);
+            self.flush();
+        }
+    }
+
+    /// Call `f` with the wakers, without performing any deferred wake.
+    ///
+    /// Returns `None` if the lock is held.
+    pub fn inspect<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.wakers.try_lock().map(|wakers| f(&wakers))
+    }
+
+    /// Perform a deferred wake, unless the lock was taken by someone else,
+    /// which will perform it instead.
+    fn flush(&self) {
+        while self.deferred.load(Ordering::SeqCst) {
+            let Some(mut wakers) = self.wakers.try_lock() else {
+                return;
+            };
+            if self.deferred.swap(false, Ordering::SeqCst) {
+                wakers.wake_all();
+            }
+        }
+    }
+}
+
+#[must_use = "if unused the WakeLock will immediately unlock"]
+pub struct WakeLockGuard<'lock, T>
+where
+    T: WakeAll,
+{
+    lock: &'lock WakeLock<T>,
+    guard: ManuallyDrop<MutexGuard<'lock, T>>,
+}
+
+impl<T> Drop for WakeLockGuard<'_, T>
+where
+    T: WakeAll,
+{
+    fn drop(&mut self) {
+        // SAFETY: the guard is not used after this.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        self.lock.flush();
+    }
+}
+
+impl<T> Deref for WakeLockGuard<'_, T>
+where
+    T: WakeAll,
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for WakeLockGuard<'_, T>
+where
+    T: WakeAll,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
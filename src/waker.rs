@@ -16,21 +16,55 @@
 /// [smoltcp]: https://github.com/smoltcp-rs/smoltcp/blob/master/LICENSE-0BSD.txt
 use core::task::Waker;
 
+/// The name that is stored with a registration. Names are only kept with the
+/// `diagnostics` feature.
+#[cfg(feature = "diagnostics")]
+pub type Name = Option<crate::diagnostics::WaiterName>;
+/// The name that is stored with a registration. Names are only kept with the
+/// `diagnostics` feature.
+#[cfg(not(feature = "diagnostics"))]
+#[derive(Debug, Clone, Copy)]
+pub struct Name;
+
+/// The name of a registration without a name.
+#[cfg(feature = "diagnostics")]
+pub const NO_NAME: Name = None;
+/// The name of a registration without a name.
+#[cfg(not(feature = "diagnostics"))]
+pub const NO_NAME: Name = Name;
+
 /// Utility struct to register and wake a waker.
 #[derive(Debug)]
 pub struct WakerRegistration {
     waker: Option<Waker>,
+    /// Counts the registrations, so that a future can only unregister
+    /// its own registration.
+    generation: u32,
+    name: Name,
 }
 
 impl WakerRegistration {
     pub const EMPTY: Self = Self::new();
 
     pub const fn new() -> Self {
-        Self { waker: None }
+        Self {
+            waker: None,
+            generation: 0,
+            name: NO_NAME,
+        }
     }
 
     /// Register a waker. Overwrites the previous waker, if any.
-    pub fn register(&mut self, w: &Waker) {
+    ///
+    /// Returns the generation of the registration, for [`WakerRegistration::unregister`].
+    pub fn register(&mut self, w: &Waker) -> u32 {
+        self.register_named(w, NO_NAME)
+    }
+
+    /// Register a waker, like [`WakerRegistration::register`], and store `name` with it.
+    pub fn register_named(&mut self, w: &Waker, name: Name) -> u32 {
+        self.name = name;
+        self.generation = self.generation.wrapping_add(1);
         match self.waker {
             // Optimization: If both the old and new Wakers wake the same task, we can simply
             // keep the old waker, skipping the clone. (In most executor implementations,
@@ -42,15 +76,46 @@ impl WakerRegistration {
             // then clone the new waker and store it
             _ => self.waker = Some(w.clone()),
         }
+        self.generation
+    }
+
+    /// Remove the registered waker, if it is still the one that was registered
+    /// in `generation`.
+    ///
+    /// This keeps a future that is dropped while waiting from causing a spurious
+    /// wake of the next registration.
+    pub fn unregister(&mut self, generation: u32) {
+        if self.generation == generation {
+            self.waker = None;
+        }
+    }
+
+    /// Returns true if no other waker was registered since `generation`, even if
+    /// the waker was woken already.
+    pub fn is_current(&self, generation: u32) -> bool {
+        self.generation == generation
     }
 
     /// Wake the registered waker, if any.
     pub fn wake(&mut self) {
-        self.waker.take().map(|w| w.wake());
+        if let Some(w) = self.waker.take() {
+            w.wake()
+        }
+    }
+
+    /// Check if the registered waker, if any, wakes the same task as `w`
+    pub fn will_wake(&self, w: &Waker) -> bool {
+        self.waker.as_ref().is_some_and(|w2| w2.will_wake(w))
     }
 
     /// Check if this WakerRegistration is empty
     pub fn is_empty(&self) -> bool {
         self.waker.is_none()
     }
+
+    /// Returns the name of the registered waker, if any.
+    #[cfg(feature = "diagnostics")]
+    pub fn waiting(&self) -> Option<Name> {
+        self.waker.as_ref().map(|_| self.name)
+    }
 }
@@ -0,0 +1,149 @@
+use core::task::Waker;
+
+use crate::{
+    builder::WakeStrategy,
+    log::*,
+    wake_lock::WakeLock,
+    waker::{Name, WakerRegistration, NO_NAME},
+};
+
+/// The slot and generation of a waker that was registered in a [`WakerSet`].
+#[derive(Debug, Clone, Copy)]
+pub struct Registration {
+    index: usize,
+    generation: u32,
+}
+
+/// A fixed amount of waker slots, for primitives that can have
+/// several waiters on the same side.
+pub struct WakerSet<const W: usize> {
+    wakers: WakeLock<[WakerRegistration; W]>,
+}
+
+impl<const W: usize> WakerSet<W> {
+    pub const fn new() -> Self {
+        Self {
+            wakers: WakeLock::new([WakerRegistration::EMPTY; W]),
+        }
+    }
+
+    /// Register `waker` in the slot that already holds it, or in the first free slot.
+    ///
+    /// Failing to find a free slot means that more than `W` futures are waiting, which
+    /// makes the primitive degenerate into busy-waking. This is logged as an error, and
+    /// causes a panic if the `panic-on-waker-overflow` feature is enabled.
+    pub fn register(&self, waker: &Waker) -> bool {
+        self.register_named(waker, NO_NAME)
+    }
+
+    /// Register `waker`, like [`WakerSet::register`], and store `name` with it.
+    pub fn register_named(&self, waker: &Waker, name: Name) -> bool {
+        self.register_slot(waker, name).is_some()
+    }
+
+    /// Register `waker` and `name` like [`WakerSet::register_named`], and return where
+    /// it was registered, for [`WakerSet::unregister`] and [`WakerSet::cancel`].
+    pub fn register_slot(&self, waker: &Waker, name: Name) -> Option<Registration> {
+        let mut wks = self.wakers.try_lock()?;
+
+        let slot = if let Some(idx) = wks.iter().position(|wk| wk.will_wake(waker)) {
+            Some(idx)
+        } else {
+            wks.iter().position(|wk| wk.is_empty())
+        };
+
+        if let Some(index) = slot {
+            let generation = wks[index].register_named(waker, name);
+            Some(Registration { index, generation })
+        } else {
+            error!("No free waker slot, all {} slots are in use", W);
+            if cfg!(feature = "panic-on-waker-overflow") {
+                panic!("No free waker slot, all {} slots are in use", W);
+            }
+            None
+        }
+    }
+
+    /// Remove the waker of `registration`, if no other waker was registered in its
+    /// slot since, e.g. once the future that registered it is done.
+    ///
+    /// If the wakers are locked at the moment, waking all of them is deferred to the
+    /// holder of the lock instead, so that a wake meant for a waiter that is still
+    /// registered is not lost.
+    pub fn unregister(&self, registration: Registration) {
+        self.wakers.wake_or_defer(|wks| {
+            wks[registration.index].unregister(registration.generation);
+        })
+    }
+
+    /// Remove the waker of `registration` like [`WakerSet::unregister`], for a future
+    /// that is dropped before it is done.
+    ///
+    /// If the waker was woken already, the future will not act on that wake anymore,
+    /// so it is passed on to the other registered wakers according to `strategy`.
+    pub fn cancel(&self, registration: Registration, strategy: WakeStrategy) {
+        self.wakers.wake_or_defer(|wks| {
+            let wk = &mut wks[registration.index];
+            if !wk.is_current(registration.generation) {
+                return;
+            }
+
+            if wk.is_empty() {
+                Self::wake_registered(wks, strategy);
+            } else {
+                wk.unregister(registration.generation);
+            }
+        })
+    }
+
+    /// Wake the registered wakers according to `strategy`.
+    ///
+    /// With [`WakeStrategy::All`], this is implemented as unfairly as
+    /// can be by just waking everyone in order.
+    pub fn wake(&self, strategy: WakeStrategy) -> bool {
+        self.wakers
+            .try_lock()
+            .map(|mut wks| Self::wake_registered(&mut wks, strategy))
+            .is_some()
+    }
+
+    /// Wake the registered wakers according to `strategy`, or defer waking all of
+    /// them to whoever is holding the wakers at the moment.
+    pub fn wake_or_defer(&self, strategy: WakeStrategy) {
+        self.wakers
+            .wake_or_defer(|wks| Self::wake_registered(wks, strategy))
+    }
+
+    fn wake_registered(wks: &mut [WakerRegistration; W], strategy: WakeStrategy) {
+        match strategy {
+            WakeStrategy::All => wks.iter_mut().for_each(|wk| wk.wake()),
+            WakeStrategy::One => {
+                if let Some(wk) = wks.iter_mut().find(|wk| !wk.is_empty()) {
+                    wk.wake()
+                }
+            }
+        }
+    }
+
+    /// Returns the amount of registered wakers, or `None` if the wakers are being
+    /// registered or woken at the moment.
+    ///
+    /// A wake that is deferred while the wakers are counted is performed afterwards.
+    pub fn registered(&self) -> Option<usize> {
+        self.wakers
+            .try_lock()
+            .map(|wks| wks.iter().filter(|wk| !wk.is_empty()).count())
+    }
+
+    /// Call `f` with the name of every registered waker.
+    ///
+    /// Nothing is reported if the wakers are being registered or woken at the moment.
+    #[cfg(feature = "diagnostics")]
+    pub fn waiting(&self, mut f: impl FnMut(Name)) {
+        if let Some(wks) = self.wakers.try_lock() {
+            wks.iter()
+                .filter_map(WakerRegistration::waiting)
+                .for_each(&mut f);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! A watchdog, detecting the absence of a periodic feed.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll},
+};
+
+use crate::{log::*, mutex::Mutex, waker::WakerRegistration};
+
+/// A watchdog.
+///
+/// The watchdog expires once it has not been fed with [`Watchdog::feed`] for the
+/// configured amount of ticks, which resolves [`Watchdog::expired`]. It stays expired
+/// until it is fed again.
+///
+/// Like the [`Debouncer`](crate::debounce::Debouncer), the watchdog does not keep track
+/// of time by itself: the application advances it by calling [`Watchdog::tick`].
+pub struct Watchdog {
+    /// The amount of ticks that remain until the watchdog expires.
+    remaining: AtomicU32,
+    timeout: u32,
+    waker: Mutex<WakerRegistration>,
+}
+
+impl Watchdog {
+    /// Create a new [`Watchdog`] that expires if it is not fed for `timeout` ticks.
+    ///
+    /// The watchdog starts out as if it was just fed.
+    pub const fn new(timeout: u32) -> Self {
+        Self {
+            remaining: AtomicU32::new(timeout),
+            timeout,
+            waker: Mutex::new(WakerRegistration::new()),
+        }
+    }
+
+    /// Feed the watchdog, restarting its timeout.
+    ///
+    /// This may be called from any context, including interrupts.
+    pub fn feed(&self) {
+        self.remaining.store(self.timeout, Ordering::SeqCst);
+    }
+
+    /// Advance the watchdog by one tick.
+    ///
+    /// Wakes the waiter if the watchdog expires on this tick.
+    pub fn tick(&self) {
+        let expired = self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| r.checked_sub(1))
+            == Ok(1);
+
+        if expired {
+            if let Some(mut wk) = self.waker.try_lock() {
+                debug!("Watchdog expired, waking waiter");
+                wk.wake();
+            }
+            // If the waker is locked, the waiter is registering, and checks for
+            // expiry afterwards.
+        }
+    }
+
+    /// Returns true if the watchdog has expired.
+    pub fn is_expired(&self) -> bool {
+        self.remaining.load(Ordering::SeqCst) == 0
+    }
+
+    /// Wait for the watchdog to expire.
+    #[must_use = "futures do nothing unless you `.await` or poll them"]
+    pub fn expired(&self) -> ExpiredFuture<'_> {
+        ExpiredFuture { watchdog: self }
+    }
+}
+
+/// The future returned by [`Watchdog::expired`].
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct ExpiredFuture<'a> {
+    watchdog: &'a Watchdog,
+}
+
+impl Future for ExpiredFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let watchdog = self.watchdog;
+
+        // Register before checking, so that a tick expiring the watchdog
+        // in between can not be missed.
+        if let Some(mut wk) = watchdog.waker.try_lock() {
+            wk.register(cx.waker());
+        } else {
+            cx.waker().wake_by_ref();
+        }
+
+        if watchdog.is_expired() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+    use std::time::Duration;
+
+    use super::Watchdog;
+
+    #[tokio::test]
+    async fn expires_without_feed() {
+        static W: Watchdog = Watchdog::new(2);
+
+        let supervisor = tokio::task::spawn(W.expired());
+
+        for _ in 0..4 {
+            W.tick();
+            W.feed();
+        }
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!supervisor.is_finished());
+
+        W.tick();
+        W.tick();
+        supervisor.await.unwrap();
+        assert!(W.is_expired());
+
+        W.feed();
+        assert!(!W.is_expired());
+    }
+}